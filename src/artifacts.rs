@@ -0,0 +1,146 @@
+// Copyright 2012-2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Bookkeeping for the files a test leaves behind for inspection.
+//!
+//! Every test gets its own artifacts directory, holding the output dumps of
+//! the test and an `index.txt` with one line per file, telling what produced
+//! it, for which revision, and how large it is.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+pub struct Artifacts {
+    dir: PathBuf,
+    max_total_size: Option<u64>,
+}
+
+struct Entry {
+    path: PathBuf,
+    producer: String,
+    revision: String,
+    size: u64,
+}
+
+impl Artifacts {
+    pub fn new(dir: PathBuf, max_total_size: Option<u64>) -> Artifacts {
+        Artifacts { dir, max_total_size }
+    }
+
+    pub fn index_path(&self) -> PathBuf {
+        self.dir.join("index.txt")
+    }
+
+    /// Path of the artifact called `name`.
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(name)
+    }
+
+    /// Remove everything left behind by an earlier run of the test.
+    pub fn clear(&self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+
+    /// Write the artifact `name` and record it in the index.
+    pub fn write(&self, name: &str, contents: &[u8], producer: &str, revision: Option<&str>)
+                 -> PathBuf {
+        fs::create_dir_all(&self.dir).unwrap();
+        let path = self.path(name);
+        File::create(&path).and_then(|mut f| f.write_all(contents)).unwrap_or_else(|e| {
+            panic!("failed to write `{}`: {}", path.display(), e)
+        });
+        self.record(&path, producer, revision);
+        path
+    }
+
+    /// Record a file which was written elsewhere (e.g. an actual output
+    /// which has to stay where `update-references.sh` looks for it).
+    pub fn record(&self, path: &Path, producer: &str, revision: Option<&str>) {
+        fs::create_dir_all(&self.dir).unwrap();
+        let size = fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+        let mut entries = self.read_index();
+        entries.retain(|e| e.path != path);
+        entries.push(Entry {
+            path: path.to_path_buf(),
+            producer: producer.to_owned(),
+            revision: revision.unwrap_or("-").to_owned(),
+            size,
+        });
+        self.enforce_max_total_size(&mut entries);
+        self.write_index(&entries);
+    }
+
+    /// Truncate the oldest artifacts until all of them fit into
+    /// `max_total_size`. Files recorded from elsewhere are listed but neither
+    /// counted nor touched, they are outputs other tools look for.
+    fn enforce_max_total_size(&self, entries: &mut [Entry]) {
+        let max = match self.max_total_size {
+            Some(max) => max,
+            None => return,
+        };
+        let mut total: u64 = entries.iter()
+            .filter(|e| e.path.starts_with(&self.dir))
+            .map(|e| e.size)
+            .sum();
+        for entry in entries.iter_mut().filter(|e| e.path.starts_with(&self.dir)) {
+            if total <= max {
+                break;
+            }
+            if entry.size == 0 {
+                continue;
+            }
+            let excess = total - max;
+            let new_size = entry.size.saturating_sub(excess);
+            let truncated = OpenOptions::new().write(true).open(&entry.path)
+                .and_then(|f| f.set_len(new_size));
+            if truncated.is_ok() {
+                total -= entry.size - new_size;
+                entry.size = new_size;
+                if !entry.producer.ends_with(" (truncated)") {
+                    entry.producer.push_str(" (truncated)");
+                }
+            }
+        }
+    }
+
+    fn read_index(&self) -> Vec<Entry> {
+        let mut index = String::new();
+        if File::open(self.index_path()).and_then(|mut f| f.read_to_string(&mut index)).is_err() {
+            return Vec::new();
+        }
+        index.lines().filter_map(|line| {
+            let mut parts = line.splitn(4, '\t');
+            let path = parts.next()?;
+            let size = parts.next()?.parse().ok()?;
+            let revision = parts.next()?;
+            let producer = parts.next()?;
+            Some(Entry {
+                path: self.dir.join(path),
+                producer: producer.to_owned(),
+                revision: revision.to_owned(),
+                size,
+            })
+        }).collect()
+    }
+
+    fn write_index(&self, entries: &[Entry]) {
+        let mut index = String::new();
+        for entry in entries {
+            // Paths inside the artifacts directory are written relative to it.
+            let path = entry.path.strip_prefix(&self.dir).unwrap_or(&entry.path);
+            index.push_str(&format!("{}\t{}\t{}\t{}\n",
+                                    path.display(), entry.size, entry.revision, entry.producer));
+        }
+        let index_path = self.index_path();
+        File::create(&index_path).and_then(|mut f| f.write_all(index.as_bytes()))
+            .unwrap_or_else(|e| panic!("failed to write `{}`: {}", index_path.display(), e));
+    }
+}
@@ -9,16 +9,22 @@
 // except according to those terms.
 pub use self::Mode::*;
 
+use std::collections::HashMap;
 use std::env;
 use std::fmt;
-use std::fs::{read_dir, remove_file};
+use std::fs::{self, read_dir, remove_file};
+use std::io;
 use std::str::FromStr;
-use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+use std::time::SystemTime;
 #[cfg(not(feature = "norustc"))]
 use rustc;
 
 use test::ColorConfig;
 use runtest::dylib_env_var;
+use util;
 
 #[derive(Clone, Copy, PartialEq, Debug)]
 pub enum Mode {
@@ -31,6 +37,7 @@ pub enum Mode {
     DebugInfoGdb,
     DebugInfoLldb,
     Codegen,
+    Assembly,
     Rustdoc,
     CodegenUnits,
     Incremental,
@@ -66,6 +73,7 @@ impl FromStr for Mode {
             "debuginfo-lldb" => Ok(DebugInfoLldb),
             "debuginfo-gdb" => Ok(DebugInfoGdb),
             "codegen" => Ok(Codegen),
+            "assembly" => Ok(Assembly),
             "rustdoc" => Ok(Rustdoc),
             "codegen-units" => Ok(CodegenUnits),
             "incremental" => Ok(Incremental),
@@ -89,6 +97,7 @@ impl fmt::Display for Mode {
                               DebugInfoGdb => "debuginfo-gdb",
                               DebugInfoLldb => "debuginfo-lldb",
                               Codegen => "codegen",
+                              Assembly => "assembly",
                               Rustdoc => "rustdoc",
                               CodegenUnits => "codegen-units",
                               Incremental => "incremental",
@@ -100,6 +109,14 @@ impl fmt::Display for Mode {
     }
 }
 
+/// Decides whether a file with a test extension is a test, see
+/// `Config::test_file_filter`.
+pub type TestFileFilter = Arc<dyn Fn(&Path) -> bool + Send + Sync>;
+
+/// The expected outputs blessed by tests and what happened to them, see
+/// `Config::blessed`.
+pub type Blessed = Arc<Mutex<Vec<(PathBuf, ::BlessAction)>>>;
+
 #[derive(Clone)]
 pub struct Config {
     /// The library paths required for running the compiler
@@ -120,6 +137,10 @@ pub struct Config {
     /// The python executable to use for htmldocck
     pub docck_python: String,
 
+    /// The script used to check rustdoc output, htmldocck.py from the rust
+    /// source tree if not set
+    pub docck_script: Option<PathBuf>,
+
     /// The llvm FileCheck binary path
     pub llvm_filecheck: Option<PathBuf>,
 
@@ -133,10 +154,16 @@ pub struct Config {
     /// The directory containing the tests to run
     pub src_base: PathBuf,
 
+    /// Directory of auxiliary crates shared by all tests, searched for those
+    /// which aren't in the `auxiliary` directory next to the test
+    pub aux_base: Option<PathBuf>,
+
     /// The directory where programs should be built
     pub build_base: PathBuf,
 
-    /// The name of the stage being built (stage1, etc)
+    /// The name of the stage being built, conventionally `stageN-<target>`
+    /// (e.g. `stage1-x86_64-unknown-linux-gnu`). Defaults to
+    /// `stage-dev-<target>` for out-of-tree users.
     pub stage_id: String,
 
     /// The test mode, compile-fail, run-fail, run-pass
@@ -145,6 +172,108 @@ pub struct Config {
     /// Run ignored tests
     pub run_ignored: bool,
 
+    /// Fail instead of skipping test directories which cannot be read
+    pub strict_collection: bool,
+
+    /// Only check that run-pass tests compile, don't build binaries or run
+    /// them
+    pub force_check_only: bool,
+
+    /// Record the compiler version next to saved UI test output, and point
+    /// out when a failing comparison was against output from a different
+    /// compiler version
+    pub track_snapshot_versions: bool,
+
+    /// Fail tests which have alternative expected outputs (`foo.alt1.stderr`)
+    pub forbid_alternatives: bool,
+
+    /// Overwrite (or create) the expected outputs of ui tests with their
+    /// actual outputs instead of failing, and remove expected outputs which
+    /// would be empty. Tests still fail if the compiler crashed. `run_tests`
+    /// also sets it if the `BLESS` or `COMPILETEST_BLESS` environment
+    /// variable is set (to anything but `0`)
+    pub bless: bool,
+
+    /// The expected outputs blessed so far, see `bless`. Each run of
+    /// `run_tests` starts a list of its own and reports it at the end.
+    pub blessed: Blessed,
+
+    /// Whether to run the programs of run-pass and run-fail tests. If
+    /// `Some(false)`, run-pass tests only check that the program builds and
+    /// run-fail tests are ignored. Runs them if `None`.
+    pub run: Option<bool>,
+
+    /// Extensions of test files besides `rs`, e.g. `rsx`
+    pub test_extensions: Vec<String>,
+
+    /// Further restricts which of the files with a test extension are
+    /// collected as tests
+    pub test_file_filter: Option<TestFileFilter>,
+
+    /// Maximum total size in bytes of the files a test leaves behind in its
+    /// artifacts directory. The oldest ones are truncated first, the actual
+    /// outputs saved next to the expected ones never are. Unlimited if `None`.
+    pub max_artifacts_size: Option<u64>,
+
+    /// Run test programs in a process group of their own, and kill whatever
+    /// is left of the group once they exit
+    pub isolate_process_group: bool,
+
+    /// The edition to compile tests with, unless they have an `edition`
+    /// directive
+    pub edition: Option<String>,
+
+    /// Print the commands tests would run (with their environment, working
+    /// directory and whether they get input) instead of running them. Their
+    /// output isn't checked, and nothing is recorded about the tests
+    pub dry_run: bool,
+
+    /// Have rustc write dep-info when compiling tests, and skip (ignore)
+    /// tests which passed before if none of the files listed there, their
+    /// expected outputs or auxiliary crates changed since
+    pub skip_up_to_date: bool,
+
+    /// Compare outputs with their expected outputs byte for byte. Otherwise
+    /// trailing whitespace on each line, CRLF line endings and the number of
+    /// final newlines don't matter in the plain stdout and stderr, and actual
+    /// outputs are saved without them
+    pub strict_whitespace: bool,
+
+    /// Reject header comments which look like directives (`// foo-bar:` or
+    /// a bare `// foo-bar`) but aren't any, e.g. a misspelled one
+    pub strict_headers: bool,
+
+    /// Accept directives in plain comments (`// run-pass`) as well as in
+    /// `//@` ones (`//@ run-pass`). Unset it once a suite was migrated
+    pub legacy_directives: bool,
+
+    /// Only collect tests whose file, expected outputs or auxiliary crates
+    /// were modified after this time
+    pub changed_since: Option<SystemTime>,
+
+    /// Fail `known-bug` tests whose output changed. Otherwise that only
+    /// prints a note, since it most likely means the bug was fixed
+    pub fail_on_known_bug_pass: bool,
+
+    /// Release channel of the compiler (`stable`, `beta`, `nightly` or `dev`)
+    /// for `ignore-nightly` style directives. Detected from the version of
+    /// the compiler if not set.
+    pub channel: Option<String>,
+
+    /// Codegen backend (`-Zcodegen-backend`) to compile tests with. Aux
+    /// crates use the default one unless they ask for it.
+    pub codegen_backend: Option<PathBuf>,
+
+    /// Only smoke check compile-fail and ui tests: validate their headers
+    /// and annotations and compile them for metadata, sharing one
+    /// incremental cache, without comparing outputs. No stamps are written,
+    /// so the next full run still runs everything. Needs a nightly compiler.
+    pub smoke_mode: bool,
+
+    /// Write a JSON report of the ignored tests and why they are ignored
+    /// to this file when collecting tests
+    pub ignored_report: Option<PathBuf>,
+
     /// Only run tests that match this filter
     pub filter: Option<String>,
 
@@ -225,9 +354,37 @@ pub struct Config {
     pub llvm_components: String,
     pub llvm_cxxflags: String,
     pub nodejs: Option<String>,
+    /// Extra flags for nodejs, passed before the shim and the program
+    pub nodejs_args: Vec<String>,
+    /// JavaScript shim nodejs loads wasm32 programs with (e.g. rustc's
+    /// `src/etc/wasm32-shim.js`). Without it, the `.wasm` file is passed to
+    /// nodejs directly.
+    pub wasm_shim: Option<PathBuf>,
+    /// Command to run wasm32 test programs with instead of nodejs, e.g.
+    /// `wasmtime run`. The path of the `.wasm` file is appended.
+    pub wasm_runner: Option<String>,
+
+    /// Sanitizers (`address`, `leak`, `memory`, `thread`) which work for the
+    /// target, for `needs-sanitizer-*` directives. `None` probes the compiler.
+    pub sanitizers_supported: Option<Vec<String>>,
+    /// Path to `llvm-symbolizer`, used to symbolize sanitizer reports
+    pub llvm_symbolizer: Option<PathBuf>,
+    /// Whether the profiler runtime (for `-Cprofile-generate`) is available
+    /// for the target, for `needs-profiler-support` directives. `None` looks
+    /// for it in the sysroot of the compiler.
+    pub profiler_support: Option<bool>,
+
+    /// Whether test programs are built with debug assertions, for the
+    /// `ignore-debug` and `ignore-release` directives. `None` probes the
+    /// compiler
+    pub debug_assertions: Option<bool>,
+
+    /// The compare mode the tests run in (e.g. `nll`), which tests opt out
+    /// of with `ignore-compare-mode-<name>`
+    pub compare_mode: Option<String>,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct TestPaths {
     pub file: PathBuf,         // e.g., compile-test/foo/bar/baz.rs
     pub base: PathBuf,         // e.g., compile-test, auxiliary
@@ -235,47 +392,565 @@ pub struct TestPaths {
 }
 
 impl Config {
+    /// The default configuration, to be adjusted with the setters below, e.g.
+    /// `Config::new().mode(Mode::Ui).src_base("tests/ui")`.
+    pub fn new() -> Config {
+        Config::default()
+    }
+
+    /// Set `mode`.
+    pub fn mode(mut self, mode: Mode) -> Config {
+        self.mode = mode;
+        self
+    }
+
+    /// Set `src_base`.
+    pub fn src_base<P: Into<PathBuf>>(mut self, src_base: P) -> Config {
+        self.src_base = src_base.into();
+        self
+    }
+
+    /// Set `build_base`.
+    pub fn build_base<P: Into<PathBuf>>(mut self, build_base: P) -> Config {
+        self.build_base = build_base.into();
+        self
+    }
+
+    /// Set `rustc_path`.
+    pub fn rustc_path<P: Into<PathBuf>>(mut self, rustc_path: P) -> Config {
+        self.rustc_path = rustc_path.into();
+        self
+    }
+
+    /// Set `target`.
+    pub fn target<S: Into<String>>(mut self, target: S) -> Config {
+        self.target = target.into();
+        self
+    }
+
+    /// Set `host`.
+    pub fn host<S: Into<String>>(mut self, host: S) -> Config {
+        self.host = host.into();
+        self
+    }
+
+    /// Set `target_rustcflags`.
+    pub fn target_rustcflags<S: Into<String>>(mut self, flags: S) -> Config {
+        self.target_rustcflags = Some(flags.into());
+        self
+    }
+
+    /// Set `host_rustcflags`.
+    pub fn host_rustcflags<S: Into<String>>(mut self, flags: S) -> Config {
+        self.host_rustcflags = Some(flags.into());
+        self
+    }
+
+    /// Set `filter`.
+    pub fn filter<S: Into<String>>(mut self, filter: S) -> Config {
+        self.filter = Some(filter.into());
+        self
+    }
+
+    /// Set `verbose`.
+    pub fn verbose(mut self, verbose: bool) -> Config {
+        self.verbose = verbose;
+        self
+    }
+
+    /// What is wrong with the fields every configuration needs, if anything:
+    /// `src_base` has to be a directory, and `build_base`, `rustc_path`,
+    /// `target`, `host` and `stage_id` mustn't be empty.
+    pub fn missing_fields(&self) -> Vec<String> {
+        let mut missing = Vec::new();
+        if !self.src_base.is_dir() {
+            missing.push(format!("src_base (`{}` is not a directory)", self.src_base.display()));
+        }
+        let empty = [
+            ("build_base", self.build_base.as_os_str().is_empty()),
+            ("rustc_path", self.rustc_path.as_os_str().is_empty()),
+            ("target", self.target.is_empty()),
+            ("host", self.host.is_empty()),
+            ("stage_id", self.stage_id.is_empty()),
+        ];
+        for &(field, is_empty) in &empty {
+            if is_empty {
+                missing.push(format!("{} (empty)", field));
+            }
+        }
+        missing
+    }
+
     /// Add rustc flags to link with the crate's dependencies in addition to the crate itself
+    ///
+    /// Meant to be called from a test run by Cargo: Cargo's `deps` directory
+    /// (see `cargo_deps_dir`) and `target/<profile>` above it are passed as
+    /// `-L <dir>` to both `target_rustcflags` and `host_rustcflags`, quoted
+    /// as needed. Outside of Cargo this only prints a warning.
     pub fn link_deps(&mut self) {
-        let varname = dylib_env_var();
+        let deps_dir = match cargo_deps_dir() {
+            Some(dir) => dir,
+            None => {
+                println!("warning: not running under Cargo, `link_deps` can't find the \
+                          dependencies of the crate");
+                return;
+            }
+        };
 
-        // Dependencies can be found in the environment variable. Throw everything there into the
-        // link flags
-        let lib_paths = env::var(varname).unwrap_or_else(|e| {
-            panic!("Cannot link to dependencies. Problem with env var '{}': {:?}", varname, e)
-        });
+        // Not `-L dependency=`, that isn't searched for `extern crate`.
+        let mut flags = format!(" -L {}", util::quote_arg(&deps_dir.to_string_lossy()));
+        // Libraries copied out of `deps` are only found in `target/<profile>`.
+        if let Some(profile_dir) = deps_dir.parent() {
+            flags += &format!(" -L {}", util::quote_arg(&profile_dir.to_string_lossy()));
+        }
 
         // Append to current flags if any are set, otherwise make new String
-        let mut flags = self.target_rustcflags.take().unwrap_or_else(String::new);
-        for p in env::split_paths(&lib_paths) {
-            flags += " -L ";
-            flags += p.to_str().unwrap(); // Can't fail. We already know this is unicode
+        for rustcflags in &mut [&mut self.target_rustcflags, &mut self.host_rustcflags] {
+            let mut current = rustcflags.take().unwrap_or_else(String::new);
+            current += &flags;
+            **rustcflags = Some(current);
+        }
+    }
+
+    /// Resolve `rustc_path`, if it's still the default `rustc`, and
+    /// `rustdoc_path`, if it's unset, to the toolchain Cargo picked. Each is
+    /// looked up in the `RUSTC` (`RUSTDOC`) environment variable, then with
+    /// `rustup which` (which respects `RUSTUP_TOOLCHAIN`) and finally in
+    /// `PATH`. Where they were found is printed if `verbose` is set.
+    ///
+    /// Fails with `NotFound`, naming all the places tried, if rustc (or
+    /// rustdoc in `rustdoc` mode) can't be found. `run_tests` calls this.
+    pub fn resolve_tool_paths(&mut self) -> io::Result<()> {
+        if self.rustc_path == Path::new("rustc") {
+            self.rustc_path = self.resolve_tool("rustc", "RUSTC")?;
+        }
+        if self.rustdoc_path.is_none() {
+            match self.resolve_tool("rustdoc", "RUSTDOC") {
+                Ok(rustdoc) => self.rustdoc_path = Some(rustdoc),
+                Err(e) => if self.mode == Rustdoc {
+                    return Err(e);
+                },
+            }
+        }
+        Ok(())
+    }
+
+    fn resolve_tool(&self, name: &str, env_var: &str) -> io::Result<PathBuf> {
+        let mut tried = Vec::new();
+
+        match env::var_os(env_var) {
+            Some(ref path) if !path.is_empty() => match find_executable(Path::new(path)) {
+                Some(path) => {
+                    return Ok(self.log_tool(name, path, format!("`{}`", env_var)));
+                }
+                None => tried.push(format!("`{}` (`{}` doesn't exist)",
+                                           env_var, Path::new(path).display())),
+            },
+            _ => tried.push(format!("`{}` (not set)", env_var)),
         }
 
-        self.target_rustcflags = Some(flags);
+        match Command::new("rustup").args(["which", name]).output() {
+            Ok(ref output) if output.status.success() => {
+                let path = PathBuf::from(String::from_utf8_lossy(&output.stdout).trim());
+                if path.is_file() {
+                    return Ok(self.log_tool(name, path, "rustup".to_owned()));
+                }
+                tried.push(format!("`rustup which {}` (`{}` doesn't exist)",
+                                   name, path.display()));
+            }
+            Ok(ref output) => tried.push(format!("`rustup which {}` ({})", name,
+                                                 String::from_utf8_lossy(&output.stderr).trim())),
+            Err(e) => tried.push(format!("`rustup which {}` ({})", name, e)),
+        }
+
+        match find_executable(Path::new(name)) {
+            Some(path) => Ok(self.log_tool(name, path, "`PATH`".to_owned())),
+            None => {
+                tried.push("`PATH`".to_owned());
+                Err(io::Error::new(io::ErrorKind::NotFound,
+                                   format!("can't find {}, tried {}", name, tried.join(", "))))
+            }
+        }
+    }
+
+    fn log_tool(&self, name: &str, path: PathBuf, source: String) -> PathBuf {
+        if self.verbose {
+            println!("using {} `{}` (found through {})", name, path.display(), source);
+        }
+        path
+    }
+
+    /// Check whether an Android device is attached (through `adb_path`) and
+    /// set `adb_device_status` accordingly. Does nothing for other targets.
+    pub fn detect_adb_device(&mut self) {
+        if !self.target.contains("android") {
+            return;
+        }
+        self.adb_device_status = match Command::new(&self.adb_path).arg("devices").output() {
+            Ok(output) => {
+                // The first line is a header, devices which are ready look
+                // like `<serial>\tdevice`.
+                String::from_utf8_lossy(&output.stdout)
+                    .lines()
+                    .skip(1)
+                    .any(|line| line.trim().ends_with("\tdevice"))
+            }
+            Err(_) => false,
+        };
     }
 
-    /// Remove rmeta files from target `deps` directory
+    /// A configuration for testing with a compiler built by rust's bootstrap
+    /// (`./x.py build --stage N`) in `build_dir`, usually `build` in the rust
+    /// checkout. Everything else is left at the defaults.
+    ///
+    /// The compiler, rustdoc and their libraries are taken from
+    /// `build_dir/<host>/stage<N>`, where the host is the default one, and
+    /// the libraries test programs need from the standard library for
+    /// `target` in there. The compiler finds its sysroot (the stage
+    /// directory) by itself. If the build built LLVM, the LLVM version,
+    /// components, cxxflags and FileCheck are taken from it too.
     ///
-    /// These files are created by `cargo check`, and conflict with
-    /// `cargo build` rlib files, causing E0464 for tests which use
-    /// the parent crate.
+    /// Fails with `NotFound` if the compiler or the standard library for
+    /// `target` wasn't built for the stage.
+    pub fn for_local_rustc_build(build_dir: &Path, stage: u8, target: &str)
+                                 -> io::Result<Config> {
+        let mut config = Config::default();
+        let host_dir = build_dir.join(&config.host);
+        let stage_dir = host_dir.join(format!("stage{}", stage));
+        let exe = |dir: &Path, name: &str| {
+            dir.join(format!("{}{}", name, env::consts::EXE_SUFFIX))
+        };
+        let not_built = |what: String, path: &Path, build: String| {
+            io::Error::new(io::ErrorKind::NotFound,
+                           format!("{} wasn't built for stage {}, `{}` doesn't exist \
+                                    (run `./x.py build --stage {}{}`)",
+                                   what, stage, path.display(), stage, build))
+        };
+
+        let rustc = exe(&stage_dir.join("bin"), "rustc");
+        if !rustc.is_file() {
+            return Err(not_built("the compiler".to_owned(), &rustc, String::new()));
+        }
+        let target_lib = stage_dir.join("lib").join("rustlib").join(target).join("lib");
+        if !target_lib.is_dir() {
+            return Err(not_built(format!("the standard library for `{}`", target),
+                                 &target_lib,
+                                 format!(" --target {}", target)));
+        }
+        let rustdoc = exe(&stage_dir.join("bin"), "rustdoc");
+        if rustdoc.is_file() {
+            config.rustdoc_path = Some(rustdoc);
+        }
+        config.rustc_path = rustc;
+        config.compile_lib_path = stage_dir.join("lib");
+        config.run_lib_path = target_lib;
+        config.target = target.to_owned();
+        config.stage_id = format!("stage{}-{}", stage, target);
+
+        let llvm_dir = host_dir.join("llvm");
+        let llvm_config = exe(&llvm_dir.join("bin"), "llvm-config");
+        let query = |arg: &str| {
+            let output = Command::new(&llvm_config).arg(arg).output().ok()?;
+            if output.status.success() {
+                String::from_utf8(output.stdout).ok().map(|s| s.trim().to_owned())
+            } else {
+                None
+            }
+        };
+        if let Some(version) = query("--version") {
+            config.llvm_version = Some(version);
+            if let Some(components) = query("--components") {
+                config.llvm_components = components;
+            }
+            if let Some(cxxflags) = query("--cxxflags") {
+                config.llvm_cxxflags = cxxflags;
+            }
+        }
+        let filecheck = exe(&llvm_dir.join("build").join("bin"), "FileCheck");
+        if filecheck.is_file() {
+            config.llvm_filecheck = Some(filecheck);
+        }
+        Ok(config)
+    }
+
+    /// Remove stale rmeta files from the `deps` directories in `target_rustcflags`
+    ///
+    /// These files are left behind by `cargo check` (or builds with other
+    /// flags), and conflict with `cargo build` rlib files of the same crate,
+    /// causing E0464 for tests which use the parent crate. An rmeta file is
+    /// removed if there's an rlib of the same crate with another hash which
+    /// isn't older, so call this after `link_deps`, which adds Cargo's `deps`
+    /// directory. Other directories (like the sysroot's, where every rlib
+    /// comes with an rmeta file) are left alone.
     pub fn clean_rmeta(&self) {
-        if self.target_rustcflags.is_some() {
-            for directory in self.target_rustcflags
-                .as_ref()
-                .unwrap()
-                .split_whitespace()
-                .filter(|s| s.ends_with("/deps"))
-            {
-                if let Ok(mut entries) = read_dir(directory) {
-                    while let Some(Ok(entry)) = entries.next() {
-                        if entry.file_name().to_string_lossy().ends_with(".rmeta") {
-                            let _ = remove_file(entry.path());
-                        }
-                    }
+        let flags = match self.target_rustcflags {
+            Some(ref flags) => util::split_args(flags),
+            None => return,
+        };
+        let mut directories = Vec::new();
+        let mut args = flags.iter();
+        while let Some(arg) = args.next() {
+            let path = if arg == "-L" {
+                match args.next() {
+                    Some(path) => path,
+                    None => break,
+                }
+            } else if let Some(path) = arg.strip_prefix("-L") {
+                path
+            } else {
+                continue;
+            };
+            // Drop the kind of `-L dependency=<dir>` and the like.
+            let path = match path.find('=') {
+                Some(i) if !path[..i].contains(['/', '\\']) => &path[i + 1..],
+                _ => path,
+            };
+            let path = PathBuf::from(path);
+            if path.file_name() == Some("deps".as_ref()) {
+                directories.push(path);
+            }
+        }
+
+        for directory in directories {
+            let entries = match read_dir(&directory) {
+                Ok(entries) => entries.filter_map(Result::ok).collect::<Vec<_>>(),
+                Err(_) => continue,
+            };
+            let modified = |entry: &fs::DirEntry| entry.metadata().and_then(|m| m.modified()).ok();
+            let rlibs = entries.iter()
+                .filter_map(|entry| {
+                    let name = entry.file_name().into_string().ok()?;
+                    let crate_name = library_crate_name(&name, ".rlib")?.to_owned();
+                    let stem = name[..name.len() - ".rlib".len()].to_owned();
+                    Some((crate_name, stem, modified(entry)?))
+                })
+                .collect::<Vec<_>>();
+            for entry in &entries {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                let crate_name = match library_crate_name(&name, ".rmeta") {
+                    Some(crate_name) => crate_name,
+                    None => continue,
+                };
+                let rmeta_modified = match modified(entry) {
+                    Some(time) => time,
+                    None => continue,
+                };
+                let stem = &name[..name.len() - ".rmeta".len()];
+                let stale = rlibs.iter().any(|&(ref rlib, ref rlib_stem, time)| {
+                    rlib == crate_name && rlib_stem != stem && time >= rmeta_modified
+                });
+                if stale && remove_file(entry.path()).is_ok() && self.verbose {
+                    println!("removed stale `{}`", entry.path().display());
+                }
+            }
+        }
+    }
+
+    /// The stage name `ignore-stageN` style directives are matched against,
+    /// or `None` if `stage_id` doesn't name a stage at all.
+    pub fn stage_name(&self) -> Option<&str> {
+        let name = self.stage_id.split('-').next().unwrap();
+        if name.starts_with("stage") {
+            Some(name)
+        } else {
+            None
+        }
+    }
+
+    /// Warn about a `stage_id` that doesn't follow the `stageN-<target>`
+    /// convention, since it is used in output file names and for matching
+    /// stage directives.
+    pub fn check_stage_id(&self) {
+        let problem = if self.stage_id.is_empty() {
+            Some("it is empty")
+        } else if self.stage_name().is_none() {
+            Some("it doesn't start with `stage`, so stage directives like `ignore-stage1` \
+                  will never match")
+        } else if !is_conventional_stage_id(&self.stage_id) {
+            Some("it doesn't follow the `stageN-<target>` convention")
+        } else {
+            None
+        };
+        if let Some(problem) = problem {
+            println!("warning: unusual stage_id `{}`: {} (the default is `stage-dev-{}`)",
+                     self.stage_id, problem, self.target);
+        }
+    }
+
+    /// Whether tests are only smoke checked, see `smoke_mode`.
+    pub fn is_smoke_check(&self) -> bool {
+        self.smoke_mode && matches!(self.mode, CompileFail | Ui)
+    }
+
+    /// The source of the auxiliary crate `rel` (as in `aux-build: rel`) of
+    /// `testfile`: `rel` in the `auxiliary` directory next to the test, which
+    /// it may leave with `..`, or else in `aux_base`.
+    pub fn aux_file(&self, testfile: &Path, rel: &str) -> Option<PathBuf> {
+        let aux_dir = testfile.parent().expect("test file path has no parent").join("auxiliary");
+        let local = join_lexically(&aux_dir, rel);
+        if local.exists() {
+            return Some(local);
+        }
+        self.aux_base.as_ref().map(|base| join_lexically(base, rel)).filter(|path| path.exists())
+    }
+
+    /// Why test programs can't be run for this configuration, if they can't.
+    pub fn missing_runner(&self) -> Option<&'static str> {
+        if self.target.contains("emscripten") {
+            if self.nodejs.is_none() {
+                return Some("emscripten tests need nodejs to run");
+            }
+        } else if self.target.contains("wasm32") &&
+                  self.wasm_runner.is_none() && self.nodejs.is_none() {
+            return Some("wasm32 tests need a wasm_runner or nodejs to run");
+        }
+        None
+    }
+
+    /// Whether programs built with `-Zsanitizer=<kind>` can run on the
+    /// target. Unless `sanitizers_supported` is set, this is probed once per
+    /// compiler and target by building an empty program with the sanitizer.
+    pub fn supports_sanitizer(&self, kind: &str) -> bool {
+        if let Some(ref supported) = self.sanitizers_supported {
+            return supported.iter().any(|s| s == kind);
+        }
+        let key = (self.rustc_path.clone(), self.target.clone(), kind.to_owned());
+        let mut probes = SANITIZER_PROBES.lock().unwrap();
+        let probes = probes.get_or_insert_with(HashMap::new);
+        *probes.entry(key).or_insert_with(|| self.probe_sanitizer(kind))
+    }
+
+    /// Whether the profiler runtime is available for the target. Unless
+    /// `profiler_support` is set, this is looked up once per compiler and
+    /// target.
+    pub fn supports_profiler(&self) -> bool {
+        if let Some(supported) = self.profiler_support {
+            return supported;
+        }
+        let key = (self.rustc_path.clone(), self.target.clone());
+        let mut probes = PROFILER_PROBES.lock().unwrap();
+        let probes = probes.get_or_insert_with(HashMap::new);
+        *probes.entry(key).or_insert_with(|| self.probe_profiler())
+    }
+
+    /// Whether test programs are built with debug assertions. Unless
+    /// `debug_assertions` is set, this is probed once per compiler, target
+    /// and `target_rustcflags` by looking for `debug_assertions` in the cfgs
+    /// printed by `rustc --print cfg`.
+    pub fn has_debug_assertions(&self) -> bool {
+        if let Some(debug_assertions) = self.debug_assertions {
+            return debug_assertions;
+        }
+        let key = (self.rustc_path.clone(), self.target.clone(), self.target_rustcflags.clone());
+        let mut probes = DEBUG_ASSERTIONS_PROBES.lock().unwrap();
+        let probes = probes.get_or_insert_with(HashMap::new);
+        *probes.entry(key).or_insert_with(|| self.probe_debug_assertions())
+    }
+
+    /// The version of the compiler, as printed by `rustc --version`, e.g.
+    /// `rustc 1.31.0-nightly (abc 2018-10-01)`. This is only run once per
+    /// compiler.
+    pub fn rustc_version(&self) -> Option<String> {
+        let mut versions = RUSTC_VERSIONS.lock().unwrap();
+        let versions = versions.get_or_insert_with(HashMap::new);
+        versions.entry(self.rustc_path.clone()).or_insert_with(|| {
+            let output = Command::new(&self.rustc_path).arg("--version").output().ok()?;
+            if !output.status.success() {
+                return None;
+            }
+            Some(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+        }).clone()
+    }
+
+    /// The sysroot of the compiler, as printed by `rustc --print sysroot`.
+    /// This is only run once per compiler, a warning is printed if it fails
+    /// (e.g. for a custom driver which doesn't support it).
+    pub fn sysroot(&self) -> Option<PathBuf> {
+        let mut sysroots = SYSROOTS.lock().unwrap();
+        let sysroots = sysroots.get_or_insert_with(HashMap::new);
+        sysroots.entry(self.rustc_path.clone()).or_insert_with(|| {
+            let sysroot = Command::new(&self.rustc_path)
+                .args(["--print", "sysroot"])
+                .output()
+                .ok()
+                .filter(|output| output.status.success())
+                .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_owned())
+                .filter(|sysroot| !sysroot.is_empty() && !sysroot.contains('\n'));
+            if sysroot.is_none() {
+                println!("warning: `{} --print sysroot` failed, `{{{{sysroot}}}}` won't be \
+                          expanded", self.rustc_path.display());
+            }
+            sysroot.map(PathBuf::from)
+        }).clone()
+    }
+
+    /// The release channel of the compiler, see `channel`.
+    pub fn release_channel(&self) -> String {
+        if let Some(ref channel) = self.channel {
+            return channel.clone();
+        }
+        match self.rustc_version() {
+            // `rustc 1.31.0-nightly (abc 2018-10-01)`, `rustc 1.30.0-beta.12`
+            // or `rustc 1.29.1 (b801ae664 2018-09-20)`
+            Some(version) => {
+                let release = version.split_whitespace().nth(1).unwrap_or("");
+                match release.split_once('-').map(|(_, pre)| pre) {
+                    Some(pre) => pre.split('.').next().unwrap().to_owned(),
+                    None => "stable".to_owned(),
                 }
             }
+            None if cfg!(feature = "stable") => "stable".to_owned(),
+            None => "nightly".to_owned(),
+        }
+    }
+
+    fn probe_sanitizer(&self, kind: &str) -> bool {
+        let dir = self.build_base.join("sanitizer-probes").join(&self.target).join(kind);
+        let src = dir.join("probe.rs");
+        if fs::create_dir_all(&dir).and_then(|_| fs::write(&src, "fn main() {}\n")).is_err() {
+            return false;
+        }
+        Command::new(&self.rustc_path)
+            .arg(&src)
+            .arg(format!("-Zsanitizer={}", kind))
+            .args(["--target", &self.target])
+            .arg("--out-dir").arg(&dir)
+            .output()
+            .map(|output| output.status.success())
+            .unwrap_or(false)
+    }
+
+    fn probe_profiler(&self) -> bool {
+        let sysroot = match Command::new(&self.rustc_path).args(["--print", "sysroot"]).output() {
+            Ok(ref output) if output.status.success() => {
+                PathBuf::from(String::from_utf8_lossy(&output.stdout).trim())
+            }
+            _ => return false,
+        };
+        let lib_dir = sysroot.join("lib").join("rustlib").join(&self.target).join("lib");
+        match read_dir(lib_dir) {
+            Ok(entries) => entries.filter_map(Result::ok).any(|entry| {
+                entry.file_name().to_string_lossy().starts_with("libprofiler_builtins-")
+            }),
+            Err(_) => false,
+        }
+    }
+
+    fn probe_debug_assertions(&self) -> bool {
+        let mut cmd = Command::new(&self.rustc_path);
+        cmd.args(["--print", "cfg", "--target", &self.target]);
+        if let Some(ref flags) = self.target_rustcflags {
+            cmd.args(util::split_args(flags));
+        }
+        match cmd.output() {
+            Ok(ref output) if output.status.success() => {
+                String::from_utf8_lossy(&output.stdout).lines().any(|l| l == "debug_assertions")
+            }
+            _ => {
+                println!("warning: `{} --print cfg` failed, assuming no debug assertions",
+                         self.rustc_path.display());
+                false
+            }
         }
     }
 
@@ -292,6 +967,65 @@ impl Config {
     }
 }
 
+/// The name of the crate in a library file like `libfoo-0123abcd.rlib`, if
+/// the file has the given extension.
+fn library_crate_name<'a>(file_name: &'a str, extension: &str) -> Option<&'a str> {
+    if !file_name.starts_with("lib") || !file_name.ends_with(extension) {
+        return None;
+    }
+    let stem = &file_name[3..file_name.len() - extension.len()];
+    Some(stem.rsplitn(2, '-').last().unwrap())
+}
+
+/// Results of `Config::rustc_version`, by compiler.
+static RUSTC_VERSIONS: Mutex<Option<HashMap<PathBuf, Option<String>>>> = Mutex::new(None);
+
+/// Results of `Config::sysroot`, by compiler.
+static SYSROOTS: Mutex<Option<HashMap<PathBuf, Option<PathBuf>>>> = Mutex::new(None);
+
+/// Results of probing the compiler for what it supports.
+type Probes<K> = Mutex<Option<HashMap<K, bool>>>;
+
+/// Results of `Config::probe_sanitizer`, by compiler, target and sanitizer.
+static SANITIZER_PROBES: Probes<(PathBuf, String, String)> = Mutex::new(None);
+
+/// Results of `Config::probe_profiler`, by compiler and target.
+static PROFILER_PROBES: Mutex<Option<HashMap<(PathBuf, String), bool>>> = Mutex::new(None);
+/// Results of `Config::probe_debug_assertions`, by compiler, target and
+/// `target_rustcflags`.
+static DEBUG_ASSERTIONS_PROBES: Probes<(PathBuf, String, Option<String>)> = Mutex::new(None);
+
+/// Join `rel` to `base`, resolving its `..` without looking at the file
+/// system, so that `auxiliary/../shared` works without an `auxiliary`
+/// directory.
+fn join_lexically(base: &Path, rel: &str) -> PathBuf {
+    let mut path = base.to_path_buf();
+    for component in Path::new(rel).components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if path.file_name().is_some() => {
+                path.pop();
+            }
+            component => path.push(component),
+        }
+    }
+    path
+}
+
+/// Whether `stage_id` looks like `stageN-<target>` or `stage-dev-<target>`.
+fn is_conventional_stage_id(stage_id: &str) -> bool {
+    let mut parts = stage_id.splitn(2, '-');
+    let stage = parts.next().unwrap();
+    let rest = parts.next().unwrap_or("");
+    if stage == "stage" {
+        rest.starts_with("dev-") && rest.len() > "dev-".len()
+    } else {
+        stage.len() > "stage".len() &&
+            stage["stage".len()..].chars().all(|c| c.is_ascii_digit()) &&
+            !rest.is_empty()
+    }
+}
+
 #[cfg(feature = "tmp")]
 mod config_tempdir {
     use tempfile;
@@ -322,6 +1056,10 @@ impl Default for Config {
     fn default() -> Config {
         #[cfg(not(feature = "norustc"))]
         let platform = rustc::session::config::host_triple().to_string();
+        #[cfg(not(feature = "norustc"))]
+        let stage_id = format!("stage-dev-{}", platform);
+        #[cfg(feature = "norustc")]
+        let stage_id = format!("stage-dev-{}", env!("TARGET"));
 
         Config {
             compile_lib_path: PathBuf::from(""),
@@ -330,14 +1068,39 @@ impl Default for Config {
             rustdoc_path: None,
             lldb_python: "python".to_owned(),
             docck_python: "docck-python".to_owned(),
+            docck_script: None,
             valgrind_path: None,
             force_valgrind: false,
             llvm_filecheck: None,
             src_base: PathBuf::from("tests/run-pass"),
+            aux_base: None,
             build_base: env::temp_dir(),
-            stage_id: "stage-id".to_owned(),
+            stage_id,
             mode: Mode::RunPass,
             run_ignored: false,
+            strict_collection: false,
+            force_check_only: false,
+            track_snapshot_versions: false,
+            forbid_alternatives: false,
+            bless: false,
+            blessed: Blessed::default(),
+            run: None,
+            test_extensions: vec![],
+            test_file_filter: None,
+            max_artifacts_size: None,
+            isolate_process_group: true,
+            edition: None,
+            ignored_report: None,
+            smoke_mode: false,
+            codegen_backend: None,
+            channel: None,
+            dry_run: false,
+            skip_up_to_date: false,
+            changed_since: None,
+            fail_on_known_bug_pass: false,
+            strict_whitespace: false,
+            strict_headers: false,
+            legacy_directives: true,
             filter: None,
             filter_exact: false,
             logfile: None,
@@ -359,8 +1122,8 @@ impl Default for Config {
             llvm_version: None,
             system_llvm: false,
             android_cross_path: PathBuf::from("android-cross-path"),
-            adb_path: "adb-path".to_owned(),
-            adb_test_dir: "adb-test-dir/target".to_owned(),
+            adb_path: "adb".to_owned(),
+            adb_test_dir: "/data/local/tmp/work".to_owned(),
             adb_device_status: false,
             lldb_python_dir: None,
             verbose: false,
@@ -375,6 +1138,105 @@ impl Default for Config {
             llvm_components: "llvm-components".to_string(),
             llvm_cxxflags: "llvm-cxxflags".to_string(),
             nodejs: None,
+            nodejs_args: vec![],
+            wasm_shim: None,
+            wasm_runner: None,
+            sanitizers_supported: None,
+            llvm_symbolizer: None,
+            profiler_support: None,
+            debug_assertions: None,
+            compare_mode: None,
+        }
+    }
+}
+
+/// The executable `path` names: `path` itself if it has more than one
+/// component, otherwise the first match in `PATH`.
+fn find_executable(path: &Path) -> Option<PathBuf> {
+    let with_suffix = |path: PathBuf| {
+        if path.is_file() {
+            Some(path)
+        } else if !env::consts::EXE_SUFFIX.is_empty() && path.extension().is_none() {
+            Some(path.with_extension(&env::consts::EXE_SUFFIX[1..])).filter(|p| p.is_file())
+        } else {
+            None
+        }
+    };
+    if path.components().count() > 1 {
+        return with_suffix(path.to_owned());
+    }
+    env::split_paths(&env::var_os("PATH")?).filter_map(|dir| with_suffix(dir.join(path))).next()
+}
+
+/// The directory Cargo puts the dependencies of the crate being tested in
+/// (`target/<profile>/deps`), if we're running under Cargo. It's looked up in
+/// the dynamic library environment variable Cargo sets for tests, then next to
+/// the test executable, and finally derived from `OUT_DIR` or
+/// `CARGO_MANIFEST_DIR`.
+fn cargo_deps_dir() -> Option<PathBuf> {
+    env::var_os("CARGO_MANIFEST_DIR")?;
+
+    let is_deps_dir = |dir: &Path| dir.file_name() == Some("deps".as_ref()) && dir.is_dir();
+    if let Some(lib_paths) = env::var_os(dylib_env_var()) {
+        if let Some(dir) = env::split_paths(&lib_paths).find(|p| is_deps_dir(p)) {
+            return Some(dir);
+        }
+    }
+    if let Some(dir) = env::current_exe().ok().and_then(|exe| exe.parent().map(Path::to_owned)) {
+        if is_deps_dir(&dir) {
+            return Some(dir);
         }
     }
+    // `OUT_DIR` is `target/<profile>/build/<crate>-<hash>/out`.
+    if let Some(out_dir) = env::var_os("OUT_DIR") {
+        if let Some(profile_dir) = Path::new(&out_dir).ancestors().nth(3) {
+            let dir = profile_dir.join("deps");
+            if is_deps_dir(&dir) {
+                return Some(dir);
+            }
+        }
+    }
+    let target_dir = env::var_os("CARGO_TARGET_DIR").map(PathBuf::from).unwrap_or_else(|| {
+        Path::new(&env::var_os("CARGO_MANIFEST_DIR").unwrap()).join("target")
+    });
+    let profile = if cfg!(debug_assertions) { "debug" } else { "release" };
+    let dir = target_dir.join(profile).join("deps");
+    if is_deps_dir(&dir) {
+        Some(dir)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_conventional_stage_id, Config};
+
+    #[test]
+    fn stage_name() {
+        let mut config = Config::default();
+        assert!(config.stage_id.starts_with("stage-dev-"));
+        for &(stage_id, name) in &[("stage1-x86_64-unknown-linux-gnu", Some("stage1")),
+                                   ("stage1", Some("stage1")),
+                                   ("stage-dev-x86_64-unknown-linux-gnu", Some("stage")),
+                                   ("stage-id", Some("stage")),
+                                   ("custom-stage1", None),
+                                   ("stage1x", Some("stage1x")),
+                                   ("", None)] {
+            config.stage_id = stage_id.to_owned();
+            assert_eq!(config.stage_name(), name, "{}", stage_id);
+        }
+    }
+
+    #[test]
+    fn conventional_stage_id() {
+        assert!(is_conventional_stage_id("stage1-x86_64-unknown-linux-gnu"));
+        assert!(is_conventional_stage_id("stage-dev-x86_64-unknown-linux-gnu"));
+        assert!(!is_conventional_stage_id("stage1"));
+        assert!(!is_conventional_stage_id("stage-dev-"));
+        assert!(!is_conventional_stage_id("stage-id"));
+        assert!(!is_conventional_stage_id("stage1x-x86_64-unknown-linux-gnu"));
+        assert!(!is_conventional_stage_id("custom-stage1"));
+        assert!(!is_conventional_stage_id(""));
+    }
 }
@@ -9,6 +9,7 @@
 // except according to those terms.
 use self::WhichLine::*;
 
+use std::collections::HashSet;
 use std::fmt;
 use std::fs::File;
 use std::io::BufReader;
@@ -16,6 +17,9 @@ use std::io::prelude::*;
 use std::path::Path;
 use std::str::FromStr;
 
+/// The kind of a compiler message, as written in annotations (`ERROR`,
+/// `WARN` or `WARNING`, `HELP`, `NOTE`, `SUGGESTION`) and as reported in the
+/// `level` of JSON diagnostics.
 #[derive(Clone, Debug, PartialEq)]
 pub enum ErrorKind {
     Help,
@@ -54,13 +58,40 @@ impl fmt::Display for ErrorKind {
     }
 }
 
+/// A message of the compiler: either one expected by an annotation of a test
+/// (see `load_errors`), or one the compiler actually reported (see
+/// `json::parse_output`).
 #[derive(Debug)]
 pub struct Error {
+    /// The line the message is expected on, or 0 for messages which aren't
+    /// tied to a line of the test file (see `//~?`), which may be reported
+    /// on any line.
     pub line_num: usize,
+    /// For expected errors, the column the message has to start at, if the
+    /// annotation says so with `//~ ERROR@12 msg`. For actual messages, the
+    /// column their span starts at, if they have one.
+    pub column: Option<usize>,
+    /// The error code of the message, like `E0308`. For expected errors,
+    /// only if the annotation says so with `//~ ERROR[E0308] msg` or
+    /// `//~ ERROR E0308: msg`.
+    pub code: Option<String>,
     /// What kind of message we expect (e.g. warning, error, suggestion).
     /// `None` if not specified or unknown message kind.
     pub kind: Option<ErrorKind>,
+    /// For expected errors, a part of the message; for actual ones, the
+    /// whole message, including the span and error code.
     pub msg: String,
+    /// For expected errors, how many times the message has to be reported,
+    /// 1 unless the annotation says otherwise with `//~ ERROR msg (x2)`.
+    /// Always 1 for actual messages.
+    pub count: usize,
+    /// For expected errors, how many spans in the test file (primary ones
+    /// and those with a label) the message has to have, if the annotation
+    /// says so with `//~ ERROR[spans=2] msg`.
+    pub span_count: Option<usize>,
+    /// For actual messages, the lines of those spans. Empty for expected
+    /// errors and for the continuation lines of multi-line messages.
+    pub span_lines: Vec<usize>,
 }
 
 #[derive(PartialEq, Debug)]
@@ -68,6 +99,8 @@ enum WhichLine {
     ThisLine,
     FollowPrevious(usize),
     AdjustBackward(usize),
+    AdjustForward(usize),
+    FileLevel,
 }
 
 /// Looks for either "//~| KIND MESSAGE" or "//~^^... KIND MESSAGE"
@@ -78,8 +111,24 @@ enum WhichLine {
 /// and also //~^ ERROR message one for the preceding line, and
 ///          //~| ERROR message two for that same line.
 ///
-/// If cfg is not None (i.e., in an incremental test), then we look
-/// for `//[X]~` instead, where `X` is the current `cfg`.
+/// Likewise, "//~vv... KIND MESSAGE" goes that many lines down, for
+/// annotations above the code they are about.
+///
+/// Messages without a span in the test file (e.g. about command line flags
+/// or from the linker) are expected with "//~? KIND MESSAGE", which may
+/// appear anywhere in the file and gets a `line_num` of 0. Those also match
+/// messages on any line which no other annotation expects.
+///
+/// The kind may be followed by qualifiers in brackets, see
+/// `split_qualifiers`: `spans=N` sets `span_count`, and an error code like
+/// `E0308` sets `code`. After that, `@COL` (`//~ ERROR@12 msg`) or `@LINE:COL`
+/// pins the column of the message, and with the latter also its line
+/// instead of the line of the annotation.
+///
+/// If cfg is not None (i.e., in a test with revisions), then we also look
+/// for `//[X]~`, where `X` is the current `cfg`, or a comma separated list
+/// of revisions including it (`//[X,Y]~`). Annotations without a revision
+/// apply to all of them.
 pub fn load_errors(testfile: &Path, cfg: Option<&str>) -> Vec<Error> {
     let rdr = BufReader::new(File::open(testfile).unwrap());
 
@@ -93,18 +142,30 @@ pub fn load_errors(testfile: &Path, cfg: Option<&str>) -> Vec<Error> {
     // updating it in the map callback below.)
     let mut last_nonfollow_error = None;
 
-    let tag = match cfg {
-        Some(rev) => format!("//[{}]~", rev),
-        None => "//~".to_string(),
-    };
+    let lines: Vec<String> = rdr.lines().map(|line| line.unwrap()).collect();
+    // Annotations inside string literals are ignored, unless the test asks
+    // for them.
+    let in_strings = lines.iter()
+        .any(|line| {
+            let line = line.trim();
+            line.starts_with("// allow-annotation-in-string") ||
+                line.starts_with("//@ allow-annotation-in-string")
+        });
 
-    rdr.lines()
+    lines.iter()
         .enumerate()
         .filter_map(|(line_num, line)| {
-            parse_expected(last_nonfollow_error, line_num + 1, &line.unwrap(), &tag)
+            let comment = if in_strings {
+                Some(0)
+            } else {
+                find_annotation(line, "//")
+            };
+            comment.and_then(|comment| find_tag(line, comment, cfg)).and_then(|(start, tag)| {
+                parse_expected(last_nonfollow_error, line_num + 1, lines.len(), line, start, tag)
+            })
                 .map(|(which, error)| {
                     match which {
-                        FollowPrevious(_) => {}
+                        FollowPrevious(_) | FileLevel => {}
                         _ => last_nonfollow_error = Some(error.line_num),
                     }
                     error
@@ -113,30 +174,144 @@ pub fn load_errors(testfile: &Path, cfg: Option<&str>) -> Vec<Error> {
         .collect()
 }
 
+/// Find the first annotation for revision `cfg` in `line`, at or after
+/// `from`: either `//~` or `//[revs]~` where `revs` names `cfg`. Returns
+/// where it starts and the tag itself.
+fn find_tag<'a>(line: &'a str, from: usize, cfg: Option<&str>) -> Option<(usize, &'a str)> {
+    line[from..].match_indices("//").filter_map(|(i, _)| {
+        let start = from + i;
+        let rest = &line[start + 2..];
+        if rest.starts_with('~') {
+            return Some((start, &line[start..start + 3]));
+        }
+        let revs_end = match rest.find("]~") {
+            Some(end) if rest.starts_with('[') => end,
+            _ => return None,
+        };
+        if cfg.map_or(false, |cfg| rest[1..revs_end].split(',').any(|rev| rev.trim() == cfg)) {
+            Some((start, &line[start..start + 2 + revs_end + 2]))
+        } else {
+            None
+        }
+    }).next()
+}
+
+/// Find `tag` in `line`, skipping over string and character literals. This
+/// only looks at the line itself, so the rest of a string starting on an
+/// earlier line isn't recognized as such.
+fn find_annotation(line: &str, tag: &str) -> Option<usize> {
+    let bytes = line.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        let rest = &line[i..];
+        if rest.starts_with(tag) {
+            return Some(i);
+        }
+        if rest.starts_with("//") {
+            // The rest of the line is a comment.
+            return rest.find(tag).map(|start| i + start);
+        }
+        let after_ident = i > 0 && (bytes[i - 1].is_ascii_alphanumeric() || bytes[i - 1] == b'_');
+        match bytes[i] {
+            b'"' => i = skip_string(bytes, i + 1),
+            b'r' if !after_ident || (i == 1 && bytes[0] == b'b') ||
+                    (i > 1 && bytes[i - 1] == b'b' &&
+                     !(bytes[i - 2].is_ascii_alphanumeric() || bytes[i - 2] == b'_')) => {
+                let hashes = bytes[i + 1..].iter().take_while(|&&b| b == b'#').count();
+                if bytes.get(i + 1 + hashes) == Some(&b'"') {
+                    let mut closing = String::from("\"");
+                    closing.extend((0..hashes).map(|_| '#'));
+                    let body = i + 2 + hashes;
+                    i = line[body..].find(&closing).map_or(bytes.len(), |end| {
+                        body + end + closing.len()
+                    });
+                } else {
+                    i += 1;
+                }
+            }
+            b'\'' => {
+                // A character literal like `'"'` or `'\''`, or a lifetime.
+                if bytes.get(i + 1) == Some(&b'\\') {
+                    i = line[i + 2..].find('\'').map_or(bytes.len(), |end| i + 2 + end + 1);
+                } else {
+                    let len = rest[1..].chars().next().map_or(0, char::len_utf8);
+                    if bytes.get(i + 1 + len) == Some(&b'\'') {
+                        i += len + 2;
+                    } else {
+                        i += 1;
+                    }
+                }
+            }
+            _ => i += 1,
+        }
+        // Stay on character boundaries after skipping.
+        while i < bytes.len() && !line.is_char_boundary(i) {
+            i += 1;
+        }
+    }
+    None
+}
+
+/// The index after the string literal whose contents start at `start`.
+fn skip_string(bytes: &[u8], start: usize) -> usize {
+    let mut i = start;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 2,
+            b'"' => return i + 1,
+            _ => i += 1,
+        }
+    }
+    bytes.len()
+}
+
 fn parse_expected(last_nonfollow_error: Option<usize>,
                   line_num: usize,
+                  num_lines: usize,
                   line: &str,
+                  start: usize,
                   tag: &str)
                   -> Option<(WhichLine, Error)> {
-    let start = match line.find(tag) {
-        Some(i) => i,
-        None => return None,
-    };
-    let (follow, adjusts) = if line[start + tag.len()..].chars().next().unwrap() == '|' {
+    let next_char = line[start + tag.len()..].chars().next();
+    let file_level = next_char == Some('?');
+    let (follow, adjusts) = if next_char == Some('|') {
         (true, 0)
     } else {
         (false, line[start + tag.len()..].chars().take_while(|c| *c == '^').count())
     };
-    let kind_start = start + tag.len() + adjusts + (follow as usize);
+    let downs = if adjusts == 0 {
+        line[start + tag.len()..].chars().take_while(|c| *c == 'v').count()
+    } else {
+        0
+    };
+    let kind_start = start + tag.len() + adjusts + downs + (follow as usize) +
+        (file_level as usize);
     let (kind, msg);
-    match line[kind_start..]
+    let mut span_count = None;
+    let mut code = None;
+    let first_word = line[kind_start..]
         .split_whitespace()
         .next()
-        .expect("Encountered unexpected empty comment")
-        .parse::<ErrorKind>() {
+        .expect("Encountered unexpected empty comment");
+    let (kind_word, position) = split_position(first_word, line_num);
+    let (kind_word, qualifiers) = split_qualifiers(kind_word);
+    match kind_word.parse::<ErrorKind>() {
         Ok(k) => {
             // If we find `//~ ERROR foo` or something like that:
             kind = Some(k);
+            for (name, value) in qualifiers {
+                match name {
+                    "spans" => {
+                        span_count = Some(value.parse().unwrap_or_else(|_| {
+                            panic!("invalid span count `{}` on line {}", value, line_num)
+                        }));
+                    }
+                    _ if value.is_empty() && is_error_code(name) => {
+                        code = Some(name.to_owned());
+                    }
+                    _ => panic!("unknown qualifier `{}` on line {}", name, line_num),
+                }
+            }
             let letters = line[kind_start..].chars();
             msg = letters.skip_while(|c| c.is_whitespace())
                 .skip_while(|c| !c.is_whitespace())
@@ -150,19 +325,40 @@ fn parse_expected(last_nonfollow_error: Option<usize>,
                 .collect::<String>();
         }
     }
-    let msg = msg.trim().to_owned();
+    let (msg, count) = split_count(msg.trim(), line_num);
+    let mut msg = msg.to_owned();
+    // `//~ ERROR E0308: msg` is the same as `//~ ERROR[E0308] msg`.
+    if kind.is_some() && code.is_none() {
+        let prefix = msg.find(':').map(|colon| (colon, msg[..colon].to_owned()));
+        if let Some((colon, prefix)) = prefix.filter(|(_, prefix)| is_error_code(prefix)) {
+            msg = msg[colon + 1..].trim().to_owned();
+            code = Some(prefix);
+        }
+    }
 
-    let (which, line_num) = if follow {
+    let (which, line_num) = if let Some((Some(pinned), _)) = position {
+        (ThisLine, pinned)
+    } else if file_level {
+        (FileLevel, 0)
+    } else if follow {
         assert_eq!(adjusts, 0, "use either //~| or //~^, not both.");
         let line_num = last_nonfollow_error.expect("encountered //~| without \
                                                     preceding //~^ line.");
         (FollowPrevious(line_num), line_num)
+    } else if downs > 0 {
+        if line_num + downs > num_lines {
+            panic!("annotation on line {} points past end of file", line_num);
+        }
+        (AdjustForward(downs), line_num + downs)
     } else {
         let which = if adjusts > 0 {
             AdjustBackward(adjusts)
         } else {
             ThisLine
         };
+        if adjusts >= line_num {
+            panic!("annotation on line {} points before start of file", line_num);
+        }
         let line_num = line_num - adjusts;
         (which, line_num)
     };
@@ -173,10 +369,288 @@ fn parse_expected(last_nonfollow_error: Option<usize>,
            which,
            kind,
            msg);
+    let column = position.map(|(_, column)| column);
     Some((which,
           Error {
         line_num,
+        column,
+        code,
         kind,
         msg,
+        count,
+        span_count,
+        span_lines: vec![],
     }))
 }
+
+/// Split the position off the kind of an annotation: `ERROR@12` is the kind
+/// `ERROR` at column 12, `ERROR@3:12` at line 3, column 12. Words without
+/// `@` after a kind have no position.
+fn split_position(word: &str, line_num: usize) -> (&str, Option<(Option<usize>, usize)>) {
+    let at = match word.rfind('@') {
+        Some(at) if split_qualifiers(&word[..at]).0.parse::<ErrorKind>().is_ok() => at,
+        _ => return (word, None),
+    };
+    let invalid = || -> ! {
+        panic!("invalid position `{}` on line {}, expected `@COL` or `@LINE:COL`",
+               &word[at..], line_num)
+    };
+    let mut parts = word[at + 1..].splitn(2, ':');
+    let first = parts.next().unwrap().parse().unwrap_or_else(|_| invalid());
+    let position = match parts.next() {
+        Some(column) => (Some(first), column.parse().unwrap_or_else(|_| invalid())),
+        None => (None, first),
+    };
+    (&word[..at], Some(position))
+}
+
+/// Split the count off the message of an annotation: `msg (x2)` is `msg`
+/// expected twice.
+fn split_count(msg: &str, line_num: usize) -> (&str, usize) {
+    let open = match msg.rfind("(x") {
+        Some(open) if msg.ends_with(')') && (open == 0 || msg[..open].ends_with(' ')) => open,
+        _ => return (msg, 1),
+    };
+    match msg[open + 2..msg.len() - 1].parse() {
+        Ok(0) => panic!("invalid count `{}` on line {}", &msg[open..], line_num),
+        Ok(count) => (msg[..open].trim_end(), count),
+        Err(_) => (msg, 1),
+    }
+}
+
+/// Whether `s` is an error code like `E0308`.
+fn is_error_code(s: &str) -> bool {
+    s.len() == 5 && s.starts_with('E') && s[1..].chars().all(|c| c.is_ascii_digit())
+}
+
+/// Split the qualifiers off the kind of an annotation: `ERROR[spans=2]` is
+/// the kind `ERROR` with the qualifier `spans` set to `2`. Several
+/// qualifiers are separated by commas, and one without a value is set to
+/// the empty string. Words which don't end with `]` have no qualifiers.
+fn split_qualifiers(word: &str) -> (&str, Vec<(&str, &str)>) {
+    let open = match word.find('[') {
+        Some(open) if word.ends_with(']') => open,
+        _ => return (word, vec![]),
+    };
+    let qualifiers = word[open + 1..word.len() - 1]
+        .split(',')
+        .filter(|qualifier| !qualifier.is_empty())
+        .map(|qualifier| {
+            let mut parts = qualifier.splitn(2, '=');
+            (parts.next().unwrap(), parts.next().unwrap_or(""))
+        })
+        .collect();
+    (&word[..open], qualifiers)
+}
+
+/// Remove the actual messages of the compiler which repeat an earlier one on
+/// the same line, of the same kind and with the same message. The compiler
+/// reports some diagnostics once per monomorphization or macro expansion.
+/// Returns the remaining messages and how many were removed.
+pub fn dedup_errors(actual: Vec<Error>) -> (Vec<Error>, usize) {
+    let total = actual.len();
+    let mut deduped: Vec<Error> = Vec::with_capacity(total);
+    for error in actual {
+        let duplicate = deduped.iter().any(|e| {
+            e.line_num == error.line_num && e.kind == error.kind && e.msg == error.msg
+        });
+        if !duplicate {
+            deduped.push(error);
+        }
+    }
+    let removed = total - deduped.len();
+    (deduped, removed)
+}
+
+/// A crude similarity score between two diagnostic messages: the fraction of
+/// words that the two messages have in common (0.0 to 1.0).
+pub fn message_similarity(a: &str, b: &str) -> f64 {
+    let words_a: HashSet<&str> = a.split_whitespace().collect();
+    let words_b: HashSet<&str> = b.split_whitespace().collect();
+    let union = words_a.union(&words_b).count();
+    if union == 0 {
+        return 0.0;
+    }
+    words_a.intersection(&words_b).count() as f64 / union as f64
+}
+
+/// The outcome of matching the actual messages of the compiler against the
+/// expected errors of a test, see `match_errors`.
+#[derive(Debug)]
+pub struct MatchResult<'a> {
+    /// Expected errors together with the actual message matching them.
+    pub matched: Vec<(&'a Error, &'a Error)>,
+    /// Actual messages which weren't expected, but should have been.
+    pub unexpected: Vec<&'a Error>,
+    /// Expected errors for which there was no actual message.
+    pub not_found: Vec<&'a Error>,
+    /// Matched expected errors with a `span_count` the actual message
+    /// doesn't have.
+    pub wrong_span_count: Vec<(&'a Error, &'a Error)>,
+    /// Matched expected errors with a `column` the actual message doesn't
+    /// start at.
+    pub wrong_column: Vec<(&'a Error, &'a Error)>,
+    /// Expected errors with a `count` which were found fewer times than
+    /// that (but at least once), with how many times they were found.
+    pub wrong_count: Vec<(&'a Error, usize)>,
+}
+
+/// Match the `actual` messages of the compiler (see `json::parse_output`)
+/// against the `expected` errors of a test (see `load_errors`).
+///
+/// An actual message matches an expected error on the same line, of the
+/// same kind (unless the annotation has none) and with the same error code
+/// (likewise), which contains the expected message. Actual messages which
+/// don't match any are then matched against
+/// the `//~?` errors the same way, but no matter what line they are on.
+/// Each expected error matches as many actual messages as its `count`, at most.
+///
+/// Unmatched errors and warnings are always unexpected. Unmatched help
+/// messages and notes are only unexpected if the test expects at least one
+/// of them, and messages without a line only if the test has a `//~?`
+/// annotation. Suggestions and messages of unknown kind never are.
+pub fn match_errors<'a>(expected: &'a [Error], actual: &'a [Error]) -> MatchResult<'a> {
+    let expect_help = expected.iter().any(|ee| ee.kind == Some(ErrorKind::Help));
+    let expect_note = expected.iter().any(|ee| ee.kind == Some(ErrorKind::Note));
+    let expect_file_level = expected.iter().any(|ee| ee.line_num == 0);
+
+    let mut result = MatchResult {
+        matched: vec![],
+        unexpected: vec![],
+        not_found: vec![],
+        wrong_span_count: vec![],
+        wrong_column: vec![],
+        wrong_count: vec![],
+    };
+    // How many actual messages each expected error matched
+    let mut found = vec![0; expected.len()];
+    // Expected errors tied to a line get the first pick, `//~?` ones only
+    // match the messages left over.
+    let anchored = actual.iter()
+        .map(|actual_error| find_expected(expected, &mut found, actual_error, false))
+        .collect::<Vec<_>>();
+    for (actual_error, anchored) in actual.iter().zip(anchored) {
+        let opt_index = anchored.or_else(|| {
+            find_expected(expected, &mut found, actual_error, true)
+        });
+
+        match opt_index {
+            Some(index) => {
+                result.matched.push((&expected[index], actual_error));
+                if expected[index].span_count
+                    .map_or(false, |count| count != actual_error.span_lines.len()) {
+                    result.wrong_span_count.push((&expected[index], actual_error));
+                }
+                if expected[index].column.map_or(false, |column| {
+                    actual_error.column != Some(column)
+                }) {
+                    result.wrong_column.push((&expected[index], actual_error));
+                }
+            }
+            None => {
+                let file_level_ok = actual_error.line_num != 0 ||
+                    (expect_file_level && !actual_error.msg.starts_with("aborting due to"));
+                let kind_checked = match actual_error.kind {
+                    Some(ErrorKind::Help) => expect_help,
+                    Some(ErrorKind::Note) => expect_note,
+                    Some(ErrorKind::Error) |
+                    Some(ErrorKind::Warning) => true,
+                    Some(ErrorKind::Suggestion) |
+                    None => false,
+                };
+                if file_level_ok && kind_checked {
+                    result.unexpected.push(actual_error);
+                }
+            }
+        }
+    }
+
+    for (expected_error, found) in expected.iter().zip(found) {
+        if found == 0 {
+            result.not_found.push(expected_error);
+        } else if found < expected_error.count {
+            result.wrong_count.push((expected_error, found));
+        }
+    }
+    result
+}
+
+/// The first expected error not `found` as often as its `count` yet which
+/// matches `actual_error`, which is then counted as found once more. That is one on the same line unless
+/// `file_level`, and a `//~?` one on any line if it is.
+fn find_expected(expected: &[Error],
+                 found: &mut [usize],
+                 actual_error: &Error,
+                 file_level: bool)
+                 -> Option<usize> {
+    let index = expected.iter().enumerate().position(|(index, expected_error)| {
+        let line_matches = if file_level {
+            expected_error.line_num == 0
+        } else {
+            expected_error.line_num != 0 && expected_error.line_num == actual_error.line_num
+        };
+        found[index] < expected_error.count && line_matches &&
+            (expected_error.kind.is_none() || actual_error.kind == expected_error.kind) &&
+            (expected_error.code.is_none() || actual_error.code == expected_error.code) &&
+            actual_error.msg.contains(&expected_error.msg)
+    })?;
+    found[index] += 1;
+    Some(index)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use super::{find_annotation, load_errors, message_similarity, ErrorKind};
+
+    #[test]
+    fn similarity() {
+        assert_eq!(message_similarity("mismatched types", "mismatched types"), 1.0);
+        assert_eq!(message_similarity("mismatched types", "cannot find value"), 0.0);
+        assert_eq!(message_similarity("", ""), 0.0);
+        // Two of the four distinct words are shared.
+        assert_eq!(message_similarity("cannot find value", "cannot find type"), 0.5);
+        assert_eq!(message_similarity("a b", "b a"), message_similarity("b a", "a b"));
+
+        let expected = "cannot find value `x` in this scope";
+        let closer = message_similarity(expected, "cannot find value `y` in this scope");
+        let farther = message_similarity(expected, "cannot find type `x`");
+        assert!(closer > farther && farther > 0.0);
+    }
+
+    #[test]
+    fn annotations_in_strings() {
+        let annotation = |line| find_annotation(line, "//~");
+        assert_eq!(annotation(r#"let _ = "not an annotation //~ ERROR nope";"#), None);
+        assert_eq!(annotation(r###"let _ = r#"neither "//~ ERROR" nor this"#;"###), None);
+        assert_eq!(annotation(r#"let _ = "an escaped \" quote //~ ERROR nope";"#), None);
+        assert_eq!(annotation(r#"let _ = (b"bytes //~ ERROR nope", br"raw //~ ERROR nope");"#),
+                   None);
+        assert_eq!(annotation(r#"let _ = '"'; //~ ERROR found"#), Some(13));
+        assert_eq!(annotation(r#"let _: u32 = "\\"; //~ ERROR found"#), Some(19));
+        assert_eq!(annotation("fn f<'a>(x: &'a str) {} //~ ERROR found"), Some(24));
+        // The `r` ending an identifier doesn't start a raw string.
+        assert_eq!(annotation(r#"let _ = bar"x"; //~ ERROR found"#), Some(16));
+        assert_eq!(annotation("// a comment //~ ERROR found"), Some(13));
+    }
+
+    #[test]
+    fn annotations_in_strings_allowed() {
+        let dir = env::temp_dir().join("compiletest-errors-tests");
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("strings-allowed.rs");
+        fs::write(&file, "// allow-annotation-in-string\n\
+                          \n\
+                          fn main() {\n\
+                          \x20   let _ = \"\n\
+                          \x20       //~ ERROR mismatched types\n\
+                          \x20   \";\n\
+                          }\n").unwrap();
+        let found = load_errors(&file, None).into_iter()
+            .map(|e| (e.line_num, e.kind, e.msg))
+            .collect::<Vec<_>>();
+        assert_eq!(found, [(5, Some(ErrorKind::Error), "mismatched types".to_owned())]);
+    }
+}
@@ -14,9 +14,11 @@ use std::io::BufReader;
 use std::io::prelude::*;
 use std::path::{Path, PathBuf};
 
+use regex::Regex;
+
 use common::Config;
 use common;
-use util;
+use util::{self, merge_env};
 
 use extract_gdb_version;
 
@@ -24,34 +26,60 @@ use extract_gdb_version;
 /// the test.
 pub struct EarlyProps {
     pub ignore: bool,
+    /// The directives which caused the test to be ignored.
+    pub ignored_by: Vec<String>,
     pub should_fail: bool,
     pub aux: Vec<String>,
+    pub exclusive: bool,
 }
 
 impl EarlyProps {
     pub fn from_file(config: &Config, testfile: &Path) -> Self {
         let mut props = EarlyProps {
             ignore: false,
+            ignored_by: Vec::new(),
             should_fail: false,
             aux: Vec::new(),
+            exclusive: false,
         };
 
-        iter_header(testfile,
-                    None,
-                    &mut |ln| {
-            props.ignore =
-                props.ignore ||
+        let ignored = iter_header(testfile,
+                                  None,
+                                  config,
+                                  &mut |_, ln, _| {
+            // `only-foo` ignores the test unless `ignore-foo` would match,
+            // so several of them all have to match.
+            let ignored_here =
                 config.parse_cfg_name_directive(ln, "ignore") ||
+                (ln.starts_with("only-") && !config.parse_cfg_name_directive(ln, "only")) ||
                 ignore_gdb(config, ln) ||
                 ignore_lldb(config, ln) ||
-                ignore_llvm(config, ln);
+                ignore_llvm(config, ln) ||
+                ignore_debug_assertions(config, ln) ||
+                ignore_rust_version(config, ln);
+            if ignored_here {
+                props.ignore = true;
+                props.ignored_by.push(ln.to_owned());
+            } else if let Some(missing) = missing_capability(config, ln) {
+                props.ignore = true;
+                props.ignored_by.push(format!("{} ({})", ln, missing));
+            } else if ignore_compare_mode(config, ln) {
+                props.ignore = true;
+                props.ignored_by.push(format!("{} (running in compare mode `{}`)",
+                                              ln, config.compare_mode.as_ref().unwrap()));
+            }
 
             if let Some(s) = config.parse_aux_build(ln) {
                 props.aux.push(s);
             }
 
             props.should_fail = props.should_fail || config.parse_name_directive(ln, "should-fail");
+            props.exclusive = props.exclusive || config.parse_name_directive(ln, "exclusive");
         });
+        for line_num in ignored {
+            println!("warning: {}:{}: ignoring the directive after the first item",
+                     testfile.display(), line_num);
+        }
 
         return props;
 
@@ -137,27 +165,94 @@ impl EarlyProps {
             }
         }
 
+        fn ignore_rust_version(config: &Config, line: &str) -> bool {
+            let (directive, min) = if let Some(v) =
+                config.parse_name_value_directive(line, "min-rust-version") {
+                (v, true)
+            } else if let Some(v) = config.parse_name_value_directive(line, "ignore-rust-version") {
+                (v, false)
+            } else {
+                return false;
+            };
+            let wanted = parse_rust_version(&directive).unwrap_or_else(|| {
+                panic!("malformed rust version directive `{}`", line)
+            });
+            let actual = match config.rustc_version().as_ref().and_then(|v| {
+                // `rustc 1.31.0-nightly (abc 2018-10-01)`
+                v.split_whitespace().nth(1).and_then(parse_rust_version)
+            }) {
+                Some(actual) => actual,
+                None => return false,
+            };
+            if min {
+                actual < wanted
+            } else {
+                // Only the components given have to match, so `1.28`
+                // ignores all `1.28.x` releases.
+                actual.iter().zip(&wanted).all(|(a, w)| w.map_or(true, |w| *a == Some(w)))
+            }
+        }
+
+        // What a `needs-*` directive asks for which isn't available, if
+        // anything.
+        fn missing_capability(config: &Config, line: &str) -> Option<String> {
+            if let Some(kind) = line.strip_prefix("needs-sanitizer-") {
+                let kind = kind.trim();
+                if !config.supports_sanitizer(kind) {
+                    return Some(format!("the {} sanitizer isn't supported for `{}`",
+                                        kind, config.target));
+                }
+            } else if config.parse_name_directive(line, "needs-profiler-support") &&
+                      !config.supports_profiler() {
+                return Some(format!("the profiler runtime isn't available for `{}`",
+                                    config.target));
+            }
+            None
+        }
+
+        fn ignore_compare_mode(config: &Config, line: &str) -> bool {
+            match config.compare_mode {
+                Some(ref mode) if line.starts_with("ignore-compare-mode-") => {
+                    // Like the other `ignore-` directives, a reason may follow.
+                    line["ignore-compare-mode-".len()..]
+                        .split(|c: char| c == ':' || c.is_whitespace())
+                        .next() == Some(&**mode)
+                }
+                _ => false,
+            }
+        }
+
+        fn ignore_debug_assertions(config: &Config, line: &str) -> bool {
+            if config.parse_name_directive(line, "ignore-debug") {
+                config.has_debug_assertions()
+            } else if config.parse_name_directive(line, "ignore-release") {
+                !config.has_debug_assertions()
+            } else {
+                false
+            }
+        }
+
         fn ignore_llvm(config: &Config, line: &str) -> bool {
             if config.system_llvm && line.starts_with("no-system-llvm") {
                     return true;
             }
             if let Some(ref actual_version) = config.llvm_version {
+                let actual = || parse_llvm_version(actual_version).unwrap_or_else(|| {
+                    panic!("malformed llvm version `{}`", actual_version)
+                });
                 if line.starts_with("min-llvm-version") {
-                    let min_version = line.trim_right()
-                        .rsplit(' ')
-                        .next()
-                        .expect("Malformed llvm version directive");
                     // Ignore if actual version is smaller the minimum required
                     // version
-                    &actual_version[..] < min_version
+                    actual() < llvm_version_directive(line, "min-llvm-version")
+                } else if line.starts_with("max-llvm-version") {
+                    // Ignore if actual version is greater the maximum
+                    // supported version
+                    actual() > llvm_version_directive(line, "max-llvm-version")
                 } else if line.starts_with("min-system-llvm-version") {
-                    let min_version = line.trim_right()
-                        .rsplit(' ')
-                        .next()
-                        .expect("Malformed llvm version directive");
                     // Ignore if using system LLVM and actual version
                     // is smaller the minimum required version
-                    !(config.system_llvm && &actual_version[..] < min_version)
+                    config.system_llvm &&
+                        actual() < llvm_version_directive(line, "min-system-llvm-version")
                 } else {
                     false
                 }
@@ -165,6 +260,36 @@ impl EarlyProps {
                 false
             }
         }
+
+        /// The version of `min-llvm-version: 10.0` (or `min-llvm-version 10.0`).
+        fn llvm_version_directive(line: &str, directive: &str) -> [u32; 3] {
+            let version = line[directive.len()..].trim_start_matches(':').trim();
+            parse_llvm_version(version).unwrap_or_else(|| {
+                panic!("malformed llvm version directive `{}`", line)
+            })
+        }
+    }
+}
+
+/// A custom normalization rule of a test.
+#[derive(Clone, Debug)]
+pub enum Normalization {
+    /// `normalize-stderr-test: "from" -> "to"` replaces each occurrence of
+    /// `from` with `to`.
+    Literal(String, String),
+    /// `normalize-stderr-regex-test: "from" -> "to"` replaces each match of
+    /// the regex `from` with `to`, in which `$1` or `${name}` refer to the
+    /// captures of the match.
+    Regex(Regex, String),
+}
+
+impl Normalization {
+    /// Apply the rule to `output`.
+    pub fn apply(&self, output: &str) -> String {
+        match *self {
+            Normalization::Literal(ref from, ref to) => output.replace(from, to),
+            Normalization::Regex(ref from, ref to) => from.replace_all(output, &**to).into_owned(),
+        }
     }
 }
 
@@ -172,10 +297,14 @@ impl EarlyProps {
 pub struct TestProps {
     // Lines that should be expected, in order, on standard out
     pub error_patterns: Vec<String>,
+    // Regexes matching lines that should be expected, in order, like
+    // `error_patterns` (`// error-pattern-regex: ...`)
+    pub error_pattern_regexes: Vec<Regex>,
     // Extra flags to pass to the compiler
     pub compile_flags: Vec<String>,
-    // Extra flags to pass when the compiled code is run (such as --bench)
-    pub run_flags: Option<String>,
+    // Extra flags to pass when the compiled code is run (such as --bench),
+    // from all `run-flags` lines in order, revision specific ones last
+    pub run_flags: Vec<String>,
     // If present, the name of a file that this test should match when
     // pretty-printed
     pub pp_exact: Option<PathBuf>,
@@ -183,8 +312,15 @@ pub struct TestProps {
     // directory as the test, but for backwards compatibility reasons
     // we also check the auxiliary directory)
     pub aux_builds: Vec<String>,
+    // Auxiliary crates built like `aux_builds`, which are passed to the test
+    // with `--extern name=...` (from `// aux-crate: name=foo.rs`)
+    pub aux_crates: Vec<(String, String)>,
     // Environment settings to use for compiling
     pub rustc_env: Vec<(String, String)>,
+    // Environment variables to remove for the compiler
+    pub unset_rustc_env: Vec<String>,
+    // Environment variables to remove for the test program
+    pub unset_exec_env: Vec<String>,
     // Environment settings to use during execution
     pub exec_env: Vec<(String, String)>,
     // Lines to check if they appear in the expected debugger output
@@ -197,6 +333,8 @@ pub struct TestProps {
     pub check_stdout: bool,
     // Don't force a --crate-type=dylib flag on the command line
     pub no_prefer_dynamic: bool,
+    // Use `-C prefer-dynamic` even if the target doesn't support dylibs
+    pub force_prefer_dynamic: bool,
     // Run --pretty expanded when running pretty printing tests
     pub pretty_expanded: bool,
     // Which pretty mode are we testing with, default to 'normal'
@@ -205,6 +343,8 @@ pub struct TestProps {
     pub pretty_compare_only: bool,
     // Patterns which must not appear in the output of a cfail test.
     pub forbid_output: Vec<String>,
+    // Regexes which must not match any line of the output of a cfail test.
+    pub forbid_output_regexes: Vec<Regex>,
     // Revisions to test for incremental compilation.
     pub revisions: Vec<String>,
     // Directory (if any) to use for incremental compilation.  This is
@@ -212,47 +352,169 @@ pub struct TestProps {
     // testing harness and used when generating compilation
     // arguments. (In particular, it propagates to the aux-builds.)
     pub incremental_dir: Option<PathBuf>,
+    // Compile with an incremental cache, which is emptied before the test
+    // runs and shared by all of its revisions.
+    pub incremental: bool,
     // Specifies that a cfail test must actually compile without errors.
     pub must_compile_successfully: bool,
+    // The compiler has to crash with an internal compiler error. Expected
+    // errors and error patterns are optional for such tests.
+    pub should_ice: bool,
     // rustdoc will test the output of the `--test` option
     pub check_test_line_numbers_match: bool,
     // The test must be compiled and run successfully. Only used in UI tests for
     // now.
     pub run_pass: bool,
+    // The test must type check (`check-pass`, emitting metadata only) or
+    // build (`build-pass`) successfully, without being run. Only used in UI
+    // tests.
+    pub check_pass: bool,
+    pub build_pass: bool,
     // customized normalization rules
-    pub normalize_stdout: Vec<(String, String)>,
-    pub normalize_stderr: Vec<(String, String)>,
+    pub normalize_stdout: Vec<Normalization>,
+    pub normalize_stderr: Vec<Normalization>,
+    // What to emit in assembly tests, `emit-asm` by default
+    pub assembly_output: Option<String>,
+    // Only build the test program, don't run it
+    pub no_run: bool,
+    // Skip this revision, set by e.g. `//[foo] ignore-test`
+    pub ignore: bool,
+    // The edition to compile with, `config.edition` if not set
+    pub edition: Option<String>,
+    // Proc macro crates (in the `auxiliary` directory) which are built for
+    // the host and passed to the test with `--extern`
+    pub proc_macros: Vec<String>,
+    // The exit code expected from a failing compiler or test program, 101
+    // (the code of a panic) if not given. Ui `run-pass` tests have to exit
+    // successfully unless it is given.
+    pub failure_status: Option<i32>,
+    // The run-fail test returns an error from `main`: it has to exit with 1
+    // (unless `failure_status` says otherwise) and print `Error: ...`, error
+    // patterns are optional
+    pub run_fail_result: bool,
+    // Apply the compiler's machine applicable suggestions to the test,
+    // compare the result with `foo.fixed` and check that it compiles
+    pub run_rustfix: bool,
+    // Compile with colors and compare stderr, escape sequences included,
+    // with `foo.colored.stderr`
+    pub check_color_output: bool,
+    // Compare the output of the program a ui `run-pass` test runs with
+    // `foo.run.stdout` and `foo.run.stderr`
+    pub check_run_results: bool,
+    // Compare the full type names the compiler wrote to `.long-type-N.txt`
+    // files with `foo.long-type.txt`
+    pub check_long_type_file: bool,
+    // Don't compare the stdout or stderr of the compiler in ui tests
+    pub dont_check_compiler_stdout: bool,
+    pub dont_check_compiler_stderr: bool,
+    // Compile with `config.codegen_backend`. Always true for tests, aux
+    // crates have to opt in with `// aux-codegen-backend: custom`
+    pub custom_codegen_backend: bool,
+    // Collapse identical diagnostics (on the same line, of the same kind and
+    // with the same message) before matching them with the annotations
+    pub dedup_diagnostics: bool,
+    // The bug (e.g. `#1234`) whose wrong behavior the test documents, see
+    // `Config::fail_on_known_bug_pass`
+    pub known_bug: Option<String>,
 }
 
 impl TestProps {
     pub fn new() -> Self {
         TestProps {
             error_patterns: vec![],
+            error_pattern_regexes: vec![],
             compile_flags: vec![],
-            run_flags: None,
+            run_flags: vec![],
             pp_exact: None,
             aux_builds: vec![],
+            aux_crates: vec![],
             revisions: vec![],
             rustc_env: vec![],
+            unset_rustc_env: vec![],
+            unset_exec_env: vec![],
             exec_env: vec![],
             check_lines: vec![],
             build_aux_docs: false,
             force_host: false,
             check_stdout: false,
             no_prefer_dynamic: false,
+            force_prefer_dynamic: false,
             pretty_expanded: false,
             pretty_mode: "normal".to_string(),
             pretty_compare_only: false,
             forbid_output: vec![],
+            forbid_output_regexes: vec![],
             incremental_dir: None,
             must_compile_successfully: false,
+            should_ice: false,
             check_test_line_numbers_match: false,
             run_pass: false,
+            check_pass: false,
+            build_pass: false,
             normalize_stdout: vec![],
             normalize_stderr: vec![],
+            assembly_output: None,
+            proc_macros: vec![],
+            no_run: false,
+            ignore: false,
+            edition: None,
+            failure_status: None,
+            run_fail_result: false,
+            run_rustfix: false,
+            check_color_output: false,
+            check_run_results: false,
+            check_long_type_file: false,
+            incremental: false,
+            dont_check_compiler_stdout: false,
+            dont_check_compiler_stderr: false,
+            custom_codegen_backend: true,
+            dedup_diagnostics: false,
+            known_bug: None,
         }
     }
 
+    /// Whether the test checks its output with `error-pattern` or
+    /// `error-pattern-regex` directives.
+    pub fn has_error_patterns(&self) -> bool {
+        !self.error_patterns.is_empty() || !self.error_pattern_regexes.is_empty()
+    }
+
+    /// Whether the test's `compile-flags` set the level of the `unused` lint
+    /// group or of one of its lints, like `-D unused` or `--warn
+    /// unused-variables`.
+    pub fn sets_unused_lint_level(&self) -> bool {
+        const LEVELS: &[&str] = &["-A", "-W", "-D", "-F",
+                                  "--allow", "--warn", "--deny", "--forbid", "--force-warn"];
+        let flags = &self.compile_flags;
+        flags.iter().enumerate().filter_map(|(i, flag)| {
+            if LEVELS.contains(&&**flag) {
+                flags.get(i + 1).map(|lint| &**lint)
+            } else if flag.len() > 2 && LEVELS[..4].contains(&&flag[..2]) {
+                Some(&flag[2..])
+            } else {
+                let mut parts = flag.splitn(2, '=');
+                match (parts.next(), parts.next()) {
+                    (Some(level), Some(lint)) if LEVELS[4..].contains(&level) => Some(lint),
+                    _ => None,
+                }
+            }
+        }).any(|lint| {
+            lint == "unused" || lint.starts_with("unused-") || lint.starts_with("unused_")
+        })
+    }
+
+    /// The error format the test's `compile-flags` ask for, if any.
+    pub fn error_format(&self) -> Option<&str> {
+        let flags = &self.compile_flags;
+        flags.iter().enumerate().filter_map(|(i, flag)| {
+            if flag == "--error-format" {
+                flags.get(i + 1).map(|format| &**format)
+            } else {
+                flag.strip_prefix("--error-format=")
+            }
+        }).next_back()
+    }
+
     pub fn from_aux_file(&self,
                          testfile: &Path,
                          cfg: Option<&str>,
@@ -262,6 +524,8 @@ impl TestProps {
 
         // copy over select properties to the aux build:
         props.incremental_dir = self.incremental_dir.clone();
+        props.edition = self.edition.clone();
+        props.custom_codegen_backend = false;
         props.load_from(testfile, cfg, config);
 
         props
@@ -281,24 +545,38 @@ impl TestProps {
                  testfile: &Path,
                  cfg: Option<&str>,
                  config: &Config) {
+        // Environment settings for all revisions and for this one
+        let mut exec_env = (vec![], vec![]);
+        let mut rustc_env = (vec![], vec![]);
+        // Run flags for all revisions and for this one
+        let mut run_flags = (vec![], vec![]);
+
         iter_header(testfile,
                     cfg,
-                    &mut |ln| {
+                    config,
+                    &mut |revision, ln, line_num| {
+            if config.strict_headers {
+                check_directive(testfile, line_num, ln);
+            }
+
             if let Some(ep) = config.parse_error_pattern(ln) {
                 self.error_patterns.push(ep);
             }
 
+            if let Some(regex) = config.parse_regex_directive(ln, "error-pattern-regex") {
+                self.error_pattern_regexes.push(regex);
+            }
+
             if let Some(flags) = config.parse_compile_flags(ln) {
-                self.compile_flags.extend(flags.split_whitespace()
-                    .map(|s| s.to_owned()));
+                self.compile_flags.extend(flags);
             }
 
             if let Some(r) = config.parse_revisions(ln) {
                 self.revisions.extend(r);
             }
 
-            if self.run_flags.is_none() {
-                self.run_flags = config.parse_run_flags(ln);
+            if let Some(flags) = config.parse_run_flags(ln) {
+                if revision.is_some() { &mut run_flags.1 } else { &mut run_flags.0 }.extend(flags);
             }
 
             if self.pp_exact.is_none() {
@@ -317,10 +595,19 @@ impl TestProps {
                 self.check_stdout = config.parse_check_stdout(ln);
             }
 
+            if !self.dont_check_compiler_stdout {
+                self.dont_check_compiler_stdout =
+                    config.parse_name_directive(ln, "dont-check-compiler-stdout");
+            }
+
             if !self.no_prefer_dynamic {
                 self.no_prefer_dynamic = config.parse_no_prefer_dynamic(ln);
             }
 
+            if !self.force_prefer_dynamic {
+                self.force_prefer_dynamic = config.parse_name_directive(ln, "force-prefer-dynamic");
+            }
+
             if !self.pretty_expanded {
                 self.pretty_expanded = config.parse_pretty_expanded(ln);
             }
@@ -337,12 +624,28 @@ impl TestProps {
                 self.aux_builds.push(ab);
             }
 
+            if let Some(ac) = config.parse_aux_crate(ln) {
+                self.aux_crates.push(ac);
+            }
+
+            if let Some(pm) = config.parse_proc_macro(ln) {
+                self.proc_macros.push(pm);
+            }
+
             if let Some(ee) = config.parse_env(ln, "exec-env") {
-                self.exec_env.push(ee);
+                if revision.is_some() { &mut exec_env.1 } else { &mut exec_env.0 }.push(ee);
             }
 
             if let Some(ee) = config.parse_env(ln, "rustc-env") {
-                self.rustc_env.push(ee);
+                if revision.is_some() { &mut rustc_env.1 } else { &mut rustc_env.0 }.push(ee);
+            }
+
+            if let Some(var) = config.parse_name_value_directive(ln, "unset-rustc-env") {
+                self.unset_rustc_env.push(var.trim().to_owned());
+            }
+
+            if let Some(var) = config.parse_name_value_directive(ln, "unset-exec-env") {
+                self.unset_exec_env.push(var.trim().to_owned());
             }
 
             if let Some(cl) = config.parse_check_line(ln) {
@@ -353,10 +656,18 @@ impl TestProps {
                 self.forbid_output.push(of);
             }
 
+            if let Some(regex) = config.parse_regex_directive(ln, "forbid-output-regex") {
+                self.forbid_output_regexes.push(regex);
+            }
+
             if !self.must_compile_successfully {
                 self.must_compile_successfully = config.parse_must_compile_successfully(ln);
             }
 
+            if !self.should_ice {
+                self.should_ice = config.parse_name_directive(ln, "should-ice");
+            }
+
             if !self.check_test_line_numbers_match {
                 self.check_test_line_numbers_match = config.parse_check_test_line_numbers_match(ln);
             }
@@ -365,57 +676,346 @@ impl TestProps {
                 self.run_pass = config.parse_run_pass(ln);
             }
 
-            if let Some(rule) = config.parse_custom_normalization(ln, "normalize-stdout") {
+            if !self.check_pass {
+                self.check_pass = config.parse_name_directive(ln, "check-pass");
+            }
+
+            if !self.build_pass {
+                self.build_pass = config.parse_name_directive(ln, "build-pass");
+            }
+
+            if !self.no_run {
+                self.no_run = config.parse_name_directive(ln, "no-run");
+            }
+
+            // Only has an effect for revisions, since tests with such a
+            // directive outside of a revision are ignored completely.
+            if !self.ignore {
+                self.ignore = config.parse_cfg_name_directive(ln, "ignore");
+            }
+
+            if let Some((from, to)) = config.parse_custom_normalization(ln, "normalize-stdout") {
+                self.normalize_stdout.push(Normalization::Literal(from, to));
+            }
+            if let Some((from, to)) = config.parse_custom_normalization(ln, "normalize-stderr") {
+                self.normalize_stderr.push(Normalization::Literal(from, to));
+            }
+            if let Some(rule) = config.parse_regex_normalization(ln, "normalize-stdout-regex") {
                 self.normalize_stdout.push(rule);
             }
-            if let Some(rule) = config.parse_custom_normalization(ln, "normalize-stderr") {
+            if let Some(rule) = config.parse_regex_normalization(ln, "normalize-stderr-regex") {
                 self.normalize_stderr.push(rule);
             }
-        });
 
-        for key in &["RUST_TEST_NOCAPTURE", "RUST_TEST_THREADS"] {
-            if let Ok(val) = env::var(key) {
-                if self.exec_env.iter().find(|&&(ref x, _)| x == key).is_none() {
-                    self.exec_env.push(((*key).to_owned(), val))
-                }
+            if let Some(edition) = config.parse_edition(ln) {
+                self.edition = Some(edition);
+            }
+
+            if self.assembly_output.is_none() {
+                self.assembly_output = config.parse_assembly_output(ln);
+            }
+
+            if !self.run_rustfix {
+                self.run_rustfix = config.parse_name_directive(ln, "run-rustfix");
+            }
+
+            if !self.check_color_output {
+                self.check_color_output = config.parse_name_directive(ln, "check-color-output");
+            }
+
+            if !self.check_run_results {
+                self.check_run_results = config.parse_name_directive(ln, "check-run-results");
+            }
+
+            if !self.check_long_type_file {
+                self.check_long_type_file =
+                    config.parse_name_directive(ln, "check-long-type-file");
+            }
+
+            if !self.incremental {
+                self.incremental = config.parse_name_directive(ln, "incremental");
+            }
+
+            if !self.dont_check_compiler_stderr {
+                self.dont_check_compiler_stderr =
+                    config.parse_name_directive(ln, "dont-check-compiler-stderr");
+            }
+
+            if let Some(backend) = config.parse_name_value_directive(ln, "aux-codegen-backend") {
+                self.custom_codegen_backend = match backend.trim() {
+                    "custom" => true,
+                    "default" => false,
+                    other => panic!("invalid aux-codegen-backend `{}`, \
+                                     expected `default` or `custom`", other),
+                };
+            }
+
+            if let Some(code) = config.parse_failure_status(ln) {
+                self.failure_status = Some(code);
+            }
+
+            if !self.run_fail_result {
+                self.run_fail_result = config.parse_name_directive(ln, "run-fail-result");
+            }
+
+            if !self.dedup_diagnostics {
+                self.dedup_diagnostics = config.parse_name_directive(ln, "dedup-diagnostics");
+            }
+
+            if let Some(bug) = config.parse_name_value_directive(ln, "known-bug") {
+                self.known_bug = Some(bug.trim().to_owned());
             }
+        });
+
+        // Directives override the forwarded variables, revision specific ones
+        // override those for all revisions, and later ones earlier ones.
+        let unset_exec_env = &self.unset_exec_env;
+        let forwarded = ["RUST_TEST_NOCAPTURE", "RUST_TEST_THREADS"].iter()
+            .filter(|key| !unset_exec_env.iter().any(|var| var == *key))
+            .filter_map(|key| env::var(key).ok().map(|val| ((*key).to_owned(), val)));
+        let all_exec_env = forwarded
+            .chain(self.exec_env.drain(..))
+            .chain(exec_env.0)
+            .chain(exec_env.1)
+            .collect();
+        self.exec_env = merge_env(config, all_exec_env);
+        let all_rustc_env = self.rustc_env.drain(..)
+            .chain(rustc_env.0)
+            .chain(rustc_env.1)
+            .collect();
+        self.rustc_env = merge_env(config, all_rustc_env);
+        self.run_flags.extend(run_flags.0);
+        self.run_flags.extend(run_flags.1);
+    }
+}
+
+/// Parse a rust version like `1.30`, `1.31.0` or `1.31.0-nightly` into its
+/// major, minor and patch numbers, `None` for those which are missing.
+fn parse_rust_version(version: &str) -> Option<[Option<u32>; 3]> {
+    let version = version.trim().split('-').next().unwrap();
+    let mut components = [None; 3];
+    for (i, component) in version.split('.').enumerate() {
+        if i == components.len() {
+            return None;
         }
+        components[i] = Some(component.parse().ok()?);
+    }
+    Some(components)
+}
+
+/// Parse an LLVM version like `9.0`, `9.0.1svn` or `10.0.0-rust-1.43.0-stable`
+/// into its major, minor and patch numbers, which are 0 if missing. Anything
+/// after the numbers is ignored.
+pub fn parse_llvm_version(version: &str) -> Option<[u32; 3]> {
+    let version = version.trim();
+    let end = version.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(version.len());
+    let mut components = [0; 3];
+    for (i, component) in version[..end].split('.').enumerate() {
+        if i == components.len() {
+            return None;
+        }
+        components[i] = component.parse().ok()?;
+    }
+    Some(components)
+}
+
+/// Whether `testfile` has the `exclusive` directive, without going through
+/// the others like `EarlyProps::from_file`.
+pub fn is_exclusive(config: &Config, testfile: &Path) -> bool {
+    let mut exclusive = false;
+    if testfile.is_file() {
+        iter_header(testfile, None, config, &mut |_, ln, _| {
+            exclusive = exclusive || config.parse_name_directive(ln, "exclusive");
+        });
     }
+    exclusive
 }
 
-fn iter_header(testfile: &Path, cfg: Option<&str>, it: &mut FnMut(&str)) {
+/// Call `it` with each directive of `testfile` which applies to `cfg`, the
+/// revision it is specific to, if any, and its line number. Directives are
+/// comments starting with `//@` (`//@[foo]` for revision `foo`), or just `//`
+/// unless `config.legacy_directives` is unset.
+///
+/// Directives have to come before the first item, although they may follow
+/// attributes, comments, `use`, `extern crate` and `mod foo;` declarations.
+/// Returns the line numbers of the directives after it, which are ignored.
+fn iter_header(testfile: &Path,
+               cfg: Option<&str>,
+               config: &Config,
+               it: &mut dyn FnMut(Option<&str>, &str, usize)) -> Vec<usize> {
+    let mut ignored = Vec::new();
     if testfile.is_dir() {
-        return;
+        return ignored;
     }
     let rdr = BufReader::new(File::open(testfile).unwrap());
-    for ln in rdr.lines() {
-        // Assume that any directives will be found before the first
-        // module or function. This doesn't seem to be an optimization
-        // with a warm page cache. Maybe with a cold one.
+    // How deep the attribute being skipped is nested in brackets, and the
+    // block comment in block comments, when they span several lines
+    let mut attr_depth = 0;
+    let mut comment_depth = 0;
+    // Whether a declaration spans several lines
+    let mut in_declaration = false;
+    let mut in_header = true;
+    for (line_num, ln) in rdr.lines().enumerate() {
         let ln = ln.unwrap();
         let ln = ln.trim();
-        if ln.starts_with("fn") || ln.starts_with("mod") {
-            return;
-        } else if ln.starts_with("//[") {
+        if !in_header {
+            if directive_name(ln, config).map_or(false, is_known_directive) {
+                ignored.push(line_num + 1);
+            }
+            continue;
+        }
+        if comment_depth > 0 || ln.starts_with("/*") {
+            comment_depth += ln.matches("/*").count() as isize -
+                             ln.matches("*/").count() as isize;
+            continue;
+        }
+        if attr_depth > 0 || ln.starts_with("#[") || ln.starts_with("#![") {
+            attr_depth += ln.matches('[').count() as isize - ln.matches(']').count() as isize;
+            continue;
+        }
+        if in_declaration || ln.starts_with("use ") || ln.starts_with("pub use ") ||
+           ln.starts_with("extern crate ") || (ln.starts_with("mod ") && ln.ends_with(';')) {
+            in_declaration = !ln.ends_with(';');
+            continue;
+        }
+        let directive = if ln.is_empty() || ln.starts_with("//!") ||
+                           (line_num == 0 && ln.starts_with("#!")) {
+            // Blank lines, doc comments, or a shebang.
+            continue;
+        } else if let Some(directive) = ln.strip_prefix("//@") {
+            directive
+        } else if let Some(directive) = ln.strip_prefix("//") {
+            if !config.legacy_directives {
+                continue;
+            }
+            directive
+        } else {
+            in_header = false;
+            continue;
+        };
+        if directive.starts_with('[') {
             // A comment like `//[foo]` is specific to revision `foo`
-            if let Some(close_brace) = ln.find(']') {
-                let lncfg = &ln[3..close_brace];
+            if let Some(close_brace) = directive.find(']') {
+                let lncfg = &directive[1..close_brace];
                 let matches = match cfg {
                     Some(s) => s == &lncfg[..],
                     None => false,
                 };
                 if matches {
-                    it(ln[(close_brace + 1) ..].trim_left());
+                    it(Some(lncfg), directive[(close_brace + 1) ..].trim_start(), line_num + 1);
                 }
             } else {
                 panic!("malformed condition directive: expected `//[foo]`, found `{}`",
                        ln)
             }
-        } else if ln.starts_with("//") {
-            it(ln[2..].trim_left());
+        } else {
+            it(None, directive.trim_start(), line_num + 1);
         }
     }
-    return;
+    ignored
+}
+
+/// The name of the directive the comment `ln` would be, if it were in the
+/// header, like `compile-flags` of `//[foo] compile-flags: -O`.
+fn directive_name<'a>(ln: &'a str, config: &Config) -> Option<&'a str> {
+    let directive = match ln.strip_prefix("//@") {
+        Some(directive) => directive,
+        None if config.legacy_directives => ln.strip_prefix("//")?,
+        None => return None,
+    };
+    let directive = match directive.strip_prefix('[') {
+        Some(directive) => &directive[directive.find(']')? + 1..],
+        None => directive,
+    };
+    let directive = directive.trim_start();
+    let name_len = directive.find(|c: char| c == ':' || c.is_whitespace())
+                            .unwrap_or(directive.len());
+    Some(&directive[..name_len])
+}
+
+/// Every directive there is. Those ending with `-` are prefixes, like
+/// `ignore-` of `ignore-windows`. The functions parsing directives check
+/// that theirs are listed here, so that `Config::strict_headers` doesn't
+/// reject them.
+const DIRECTIVES: &[&str] = &[
+    "allow-annotation-in-string", "assembly-output", "aux-build", "aux-codegen-backend",
+    "aux-crate", "build-aux-docs", "build-pass", "check", "check-color-output",
+    "check-long-type-file", "check-pass", "check-run-results", "check-stdout",
+    "check-test-line-numbers-match", "compile-flags", "dedup-diagnostics",
+    "dont-check-compiler-stderr", "dont-check-compiler-stdout", "edition", "error-pattern",
+    "error-pattern-regex", "exclusive", "exec-env", "failure-status", "forbid-output",
+    "forbid-output-regex", "force-host", "force-prefer-dynamic", "gdb-check", "gdb-command",
+    "gdbg-check", "gdbg-command", "gdbr-check", "gdbr-command", "ignore-", "ignore-debug",
+    "ignore-gdb-version", "ignore-release", "ignore-rust-version", "incremental",
+    "known-bug", "lldb-check", "lldb-command", "max-llvm-version", "min-gdb-version",
+    "min-lldb-version", "min-llvm-version", "min-rust-version", "min-system-llvm-version",
+    "must-compile-successfully", "needs-profiler-support", "needs-sanitizer-",
+    "no-prefer-dynamic", "no-run", "no-system-llvm", "normalize-stderr-",
+    "normalize-stderr-regex-", "normalize-stdout-", "normalize-stdout-regex-", "only-",
+    "pp-exact", "pretty-compare-only", "pretty-expanded", "pretty-mode", "proc-macro",
+    "revisions", "run-fail-result", "run-flags", "run-pass", "run-rustfix", "rustc-env",
+    "should-fail", "should-ice", "unset-exec-env", "unset-rustc-env",
+];
+
+/// Whether `name` is a directive, or starts with a directive prefix.
+fn is_known_directive(name: &str) -> bool {
+    DIRECTIVES.iter().any(|&directive| if directive.ends_with('-') {
+        name.starts_with(directive) && name.len() > directive.len()
+    } else {
+        name == directive
+    })
+}
+
+/// Panic if the header comment `ln` of `testfile` looks like a directive
+/// (`foo-bar: ...` or just `foo-bar`, but not a URL) but isn't any, for
+/// `Config::strict_headers`.
+fn check_directive(testfile: &Path, line_num: usize, ln: &str) {
+    let name_len = ln.find(|c: char| c == ':' || c.is_whitespace()).unwrap_or(ln.len());
+    let name = &ln[..name_len];
+    let looks_like_directive =
+        name.starts_with(|c: char| c.is_ascii_lowercase()) &&
+        name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() ||
+                             c == '-' || c == '_' || c == '.') &&
+        (ln[name_len..].starts_with(':') || (name_len == ln.len() && name.contains('-'))) &&
+        !ln[name_len..].starts_with("://");
+    if !looks_like_directive || is_known_directive(name) {
+        return;
+    }
+    let suggestion = DIRECTIVES.iter()
+        .map(|&directive| {
+            // Only the prefix of `ignre-windows` is compared with `ignore-`.
+            let compared = if directive.ends_with('-') {
+                name.get(..directive.len()).unwrap_or(name)
+            } else {
+                name
+            };
+            (edit_distance(compared, directive), directive)
+        })
+        .min()
+        .filter(|&(distance, _)| distance <= 2.max(name.len() / 3));
+    match suggestion {
+        Some((_, directive)) => {
+            panic!("{}:{}: unknown directive `{}`, did you mean `{}`?",
+                   testfile.display(), line_num, name, directive)
+        }
+        None => panic!("{}:{}: unknown directive `{}`", testfile.display(), line_num, name),
+    }
+}
+
+/// The number of characters to insert, remove or replace to turn `a` into
+/// `b` (the Levenshtein distance).
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..b.len() + 1).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, &cb) in b.iter().enumerate() {
+            let substitution = previous[j] + if ca == cb { 0 } else { 1 };
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
 }
 
 impl Config {
@@ -423,6 +1023,14 @@ impl Config {
         self.parse_name_value_directive(line, "error-pattern")
     }
 
+    fn parse_regex_directive(&self, line: &str, directive: &str) -> Option<Regex> {
+        self.parse_name_value_directive(line, directive).map(|pattern| {
+            Regex::new(pattern.trim()).unwrap_or_else(|e| {
+                panic!("invalid regex in `{}`: {}", line, e)
+            })
+        })
+    }
+
     fn parse_forbid_output(&self, line: &str) -> Option<String> {
         self.parse_name_value_directive(line, "forbid-output")
     }
@@ -431,8 +1039,23 @@ impl Config {
         self.parse_name_value_directive(line, "aux-build")
     }
 
-    fn parse_compile_flags(&self, line: &str) -> Option<String> {
-        self.parse_name_value_directive(line, "compile-flags")
+    fn parse_aux_crate(&self, line: &str) -> Option<(String, String)> {
+        self.parse_name_value_directive(line, "aux-crate").map(|ac| {
+            let mut parts = ac.trim().splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some(name), Some(path)) => (name.trim().to_owned(), path.trim().to_owned()),
+                _ => panic!("malformed aux-crate directive `{}`, expected `name=path`", ac),
+            }
+        })
+    }
+
+    fn parse_proc_macro(&self, line: &str) -> Option<String> {
+        self.parse_name_value_directive(line, "proc-macro")
+            .map(|s| s.trim().to_string())
+    }
+
+    fn parse_compile_flags(&self, line: &str) -> Option<Vec<String>> {
+        self.parse_flags(line, "compile-flags")
     }
 
     fn parse_revisions(&self, line: &str) -> Option<Vec<String>> {
@@ -440,8 +1063,19 @@ impl Config {
             .map(|r| r.split_whitespace().map(|t| t.to_string()).collect())
     }
 
-    fn parse_run_flags(&self, line: &str) -> Option<String> {
-        self.parse_name_value_directive(line, "run-flags")
+    fn parse_run_flags(&self, line: &str) -> Option<Vec<String>> {
+        self.parse_flags(line, "run-flags")
+    }
+
+    /// Parse flags separated by whitespace (see `util::split_args` for
+    /// quoting), expanding variables in each of them so that paths containing
+    /// spaces stay one flag.
+    fn parse_flags(&self, line: &str, name: &str) -> Option<Vec<String>> {
+        self.parse_raw_name_value_directive(line, name).map(|flags| {
+            util::split_args(&flags).into_iter()
+                .map(|flag| expand_variables(flag, self))
+                .collect()
+        })
     }
 
     fn parse_check_line(&self, line: &str) -> Option<String> {
@@ -488,21 +1122,32 @@ impl Config {
         self.parse_name_directive(line, "run-pass")
     }
 
+    fn parse_failure_status(&self, line: &str) -> Option<i32> {
+        self.parse_name_value_directive(line, "failure-status").map(|code| {
+            code.trim().parse().unwrap_or_else(|_| {
+                panic!("invalid failure-status `{}`, expected an exit code", code.trim())
+            })
+        })
+    }
+
+    fn parse_edition(&self, line: &str) -> Option<String> {
+        self.parse_name_value_directive(line, "edition")
+            .map(|e| e.trim().to_string())
+    }
+
+    fn parse_assembly_output(&self, line: &str) -> Option<String> {
+        self.parse_name_value_directive(line, "assembly-output")
+            .map(|r| r.trim().to_string())
+    }
+
     fn parse_env(&self, line: &str, name: &str) -> Option<(String, String)> {
-        self.parse_name_value_directive(line, name).map(|nv| {
-            // nv is either FOO or FOO=BAR
-            let mut strs: Vec<String> = nv.splitn(2, '=')
-                .map(str::to_owned)
-                .collect();
-
-            match strs.len() {
-                1 => (strs.pop().unwrap(), "".to_owned()),
-                2 => {
-                    let end = strs.pop().unwrap();
-                    (strs.pop().unwrap(), end)
-                }
-                n => panic!("Expected 1 or 2 strings, not {}", n),
-            }
+        self.parse_raw_name_value_directive(line, name).map(|nv| {
+            // nv is either FOO or FOO=BAR. Variables are only expanded in the
+            // value, after splitting, since they may expand to anything.
+            let mut strs = nv.trim_start().splitn(2, '=');
+            let key = strs.next().unwrap().to_owned();
+            let value = strs.next().unwrap_or("").to_owned();
+            (key, expand_variables(value, self))
         })
     }
 
@@ -532,9 +1177,20 @@ impl Config {
         }
     }
 
+    fn parse_regex_normalization(&self, line: &str, prefix: &str) -> Option<Normalization> {
+        self.parse_custom_normalization(line, prefix).map(|(from, to)| {
+            match Regex::new(&from) {
+                Ok(regex) => Normalization::Regex(regex, to),
+                Err(e) => panic!("invalid regex in `{}`: {}", line, e),
+            }
+        })
+    }
+
     /// Parses a name-value directive which contains config-specific information, e.g. `ignore-x86`
     /// or `normalize-stderr-32bit`. Returns `true` if the line matches it.
     fn parse_cfg_name_directive(&self, line: &str, prefix: &str) -> bool {
+        debug_assert!(DIRECTIVES.contains(&&*format!("{}-", prefix)),
+                      "`{}-` is missing from `DIRECTIVES`", prefix);
         if line.starts_with(prefix) && line.as_bytes().get(prefix.len()) == Some(&b'-') {
             let name = line[prefix.len()+1 ..].split(&[':', ' '][..]).next().unwrap();
 
@@ -542,7 +1198,8 @@ impl Config {
                 util::matches_os(&self.target, name) ||             // target
                 name == util::get_arch(&self.target) ||             // architecture
                 name == util::get_pointer_width(&self.target) ||    // pointer width
-                name == self.stage_id.split('-').next().unwrap() || // stage
+                name == util::get_endian(&self.target) ||           // endianness
+                Some(name) == self.stage_name() ||                  // stage
                 Some(name) == util::get_env(&self.target) ||        // env
                 match self.mode {
                     common::DebugInfoGdb => name == "gdb",
@@ -550,13 +1207,18 @@ impl Config {
                     common::Pretty => name == "pretty",
                     _ => false,
                 } ||
-                (self.target != self.host && name == "cross-compile")
+                (self.target != self.host && name == "cross-compile") ||
+                (self.remote_test_client.is_some() && name == "remote") ||
+                (["stable", "beta", "nightly", "dev"].contains(&name) &&
+                 name == self.release_channel())
         } else {
             false
         }
     }
 
     fn parse_name_directive(&self, line: &str, directive: &str) -> bool {
+        debug_assert!(DIRECTIVES.contains(&directive),
+                      "`{}` is missing from `DIRECTIVES`", directive);
         // Ensure the directive is a whole word. Do not match "ignore-x86" when
         // the line says "ignore-x86_64".
         line.starts_with(directive) && match line.as_bytes().get(directive.len()) {
@@ -566,11 +1228,21 @@ impl Config {
     }
 
     pub fn parse_name_value_directive(&self, line: &str, directive: &str) -> Option<String> {
+        self.parse_raw_name_value_directive(line, directive)
+            .map(|value| expand_variables(value, self))
+    }
+
+    /// Like `parse_name_value_directive`, without expanding `{{cwd}}`,
+    /// `{{src-base}}` and `{{build-base}}`, for values which are split up
+    /// first.
+    fn parse_raw_name_value_directive(&self, line: &str, directive: &str) -> Option<String> {
+        debug_assert!(DIRECTIVES.contains(&directive),
+                      "`{}` is missing from `DIRECTIVES`", directive);
         let colon = directive.len();
         if line.starts_with(directive) && line.as_bytes().get(colon) == Some(&b':') {
             let value = line[(colon + 1) ..].to_owned();
             debug!("{}: {}", directive, value);
-            Some(expand_variables(value, self))
+            Some(value)
         } else {
             None
         }
@@ -600,6 +1272,9 @@ fn expand_variables(mut value: String, config: &Config) -> String {
     const CWD: &'static str = "{{cwd}}";
     const SRC_BASE: &'static str = "{{src-base}}";
     const BUILD_BASE: &'static str = "{{build-base}}";
+    const TARGET: &'static str = "{{target}}";
+    const HOST: &'static str = "{{host}}";
+    const SYSROOT: &'static str = "{{sysroot}}";
 
     if value.contains(CWD) {
         let cwd = env::current_dir().unwrap();
@@ -614,6 +1289,21 @@ fn expand_variables(mut value: String, config: &Config) -> String {
         value = value.replace(BUILD_BASE, &config.build_base.to_string_lossy());
     }
 
+    if value.contains(TARGET) {
+        value = value.replace(TARGET, &config.target);
+    }
+
+    if value.contains(HOST) {
+        value = value.replace(HOST, &config.host);
+    }
+
+    if value.contains(SYSROOT) {
+        // Left as is if the compiler can't tell, `Config::sysroot` warns.
+        if let Some(sysroot) = config.sysroot() {
+            value = value.replace(SYSROOT, &sysroot.to_string_lossy());
+        }
+    }
+
     value
 }
 
@@ -642,3 +1332,175 @@ fn parse_normalization_string(line: &mut &str) -> Option<String> {
     *line = &line[end+1..];
     Some(result)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::panic;
+    use std::path::PathBuf;
+
+    use common::Config;
+    use super::{iter_header, parse_llvm_version, EarlyProps, TestProps};
+
+    /// Writes a test file for the directive parsers to read.
+    fn test_file(name: &str, contents: &str) -> PathBuf {
+        let dir = env::temp_dir().join("compiletest-header-tests");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn stage_directives() {
+        let file = test_file("ignore-stage1.rs", "// ignore-stage1\n\nfn main() {}\n");
+        let mut config = Config::default();
+        for &(stage_id, ignore) in &[("stage1-x86_64-unknown-linux-gnu", true),
+                                     ("stage1", true),
+                                     ("stage2-x86_64-unknown-linux-gnu", false),
+                                     ("stage-dev-x86_64-unknown-linux-gnu", false),
+                                     ("custom-stage1", false),
+                                     ("stage1x", false),
+                                     ("", false)] {
+            config.stage_id = stage_id.to_owned();
+            assert_eq!(EarlyProps::from_file(&config, &file).ignore, ignore, "{}", stage_id);
+        }
+    }
+
+    #[test]
+    fn env_precedence() {
+        let file = test_file("env-precedence.rs",
+                             "// revisions: a b\n\
+                              //[a] exec-env:OVERRIDDEN=a\n\
+                              // exec-env:SHARED=first\n\
+                              // exec-env:OVERRIDDEN=all\n\
+                              // exec-env:SHARED=second\n\
+                              //[b] rustc-env:COMPILE_TIME=b\n\
+                              // rustc-env:COMPILE_TIME=all\n");
+        let env = |vars: &[(&str, &str)]| {
+            vars.iter().map(|&(k, v)| (k.to_owned(), v.to_owned())).collect::<Vec<_>>()
+        };
+        let config = Config::default();
+        // Revision specific directives win over those for all revisions,
+        // wherever they are.
+        let props = TestProps::from_file(&file, Some("a"), &config);
+        assert!(props.exec_env.contains(&("OVERRIDDEN".to_owned(), "a".to_owned())));
+        assert!(props.exec_env.contains(&("SHARED".to_owned(), "second".to_owned())));
+        assert_eq!(props.rustc_env, env(&[("COMPILE_TIME", "all")]));
+        let props = TestProps::from_file(&file, Some("b"), &config);
+        assert!(props.exec_env.contains(&("OVERRIDDEN".to_owned(), "all".to_owned())));
+        assert_eq!(props.rustc_env, env(&[("COMPILE_TIME", "b")]));
+    }
+
+    #[test]
+    fn endian_directives() {
+        let file = test_file("little-endian-only.rs", "// ignore-endian-big\n\nfn main() {}\n");
+        let mut config = Config::default();
+        config.target = "s390x-unknown-linux-gnu".to_owned();
+        assert!(EarlyProps::from_file(&config, &file).ignore);
+        config.target = "x86_64-unknown-linux-gnu".to_owned();
+        assert!(!EarlyProps::from_file(&config, &file).ignore);
+    }
+
+    #[test]
+    fn llvm_version() {
+        assert_eq!(parse_llvm_version("3.9"), Some([3, 9, 0]));
+        assert_eq!(parse_llvm_version("9.0.1svn"), Some([9, 0, 1]));
+        assert_eq!(parse_llvm_version("10.0.0-rust-1.43.0-stable"), Some([10, 0, 0]));
+        assert!(parse_llvm_version("3.9") < parse_llvm_version("10.0"));
+        assert!(parse_llvm_version("9.0") < parse_llvm_version("9.0.1"));
+        for &malformed in &["", "svn", "9.", "x.9", "1..2", "1.2.3.4"] {
+            assert_eq!(parse_llvm_version(malformed), None, "{}", malformed);
+        }
+    }
+
+    #[test]
+    fn llvm_version_directives() {
+        let file = test_file("min-llvm-version.rs", "// min-llvm-version 10.0\n\nfn main() {}\n");
+        let mut config = Config::default();
+        for &(version, ignore) in &[("9.0.1", true), ("3.9", true),
+                                    ("10.0.0-rust-1.43.0", false), ("11.0", false)] {
+            config.llvm_version = Some(version.to_owned());
+            assert_eq!(EarlyProps::from_file(&config, &file).ignore, ignore, "{}", version);
+        }
+
+        // A range is given with both directives.
+        let file = test_file("llvm-version-range.rs",
+                             "// min-llvm-version: 9.0\n// max-llvm-version: 10.0\n\n\
+                              fn main() {}\n");
+        for &(version, ignore) in &[("8.0", true), ("9.0.1", false),
+                                    ("10.0.0-rust-1.43.0", false), ("10.0.1", true),
+                                    ("11.0", true)] {
+            config.llvm_version = Some(version.to_owned());
+            assert_eq!(EarlyProps::from_file(&config, &file).ignore, ignore, "{}", version);
+        }
+        config.llvm_version = None;
+        assert!(!EarlyProps::from_file(&config, &file).ignore);
+
+        config.llvm_version = Some("svn".to_owned());
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            EarlyProps::from_file(&config, &file)
+        }));
+        assert!(result.is_err());
+    }
+
+    /// The directives in the header of `contents` with their line numbers,
+    /// and the line numbers of the ignored ones after it.
+    fn header(name: &str, contents: &str) -> (Vec<(usize, String)>, Vec<usize>) {
+        let file = test_file(name, contents);
+        let mut directives = Vec::new();
+        let ignored = iter_header(&file, None, &Config::default(), &mut |_, ln, line_num| {
+            directives.push((line_num, ln.to_owned()));
+        });
+        (directives, ignored)
+    }
+
+    #[test]
+    fn header_after_comments() {
+        let (directives, ignored) = header("header-after-comments.rs",
+                                           "/* Licensed under the\n\
+                                            \x20  same terms /* as Rust */\n\
+                                            \x20  // run-flags: --in-comment\n\
+                                            */\n\
+                                            //! Docs.\n\
+                                            // run-flags: --first\n\
+                                            \n\
+                                            fn main() {}\n");
+        assert_eq!(directives, [(6, "run-flags: --first".to_owned())]);
+        assert!(ignored.is_empty());
+    }
+
+    #[test]
+    fn header_after_attributes_and_declarations() {
+        let (directives, ignored) = header("header-after-declarations.rs",
+                                           "#![feature(\n\
+                                            \x20   rustc_attrs,\n\
+                                            )]\n\
+                                            extern crate core;\n\
+                                            // exec-env: A=1\n\
+                                            #[allow(unused_imports)]\n\
+                                            use std::{\n\
+                                            \x20   env,\n\
+                                            };\n\
+                                            mod aux;\n\
+                                            // exec-env: B=1\n\
+                                            macro_rules! nothing { () => {} }\n\
+                                            // run-flags: --after-item\n\
+                                            // not a directive\n");
+        assert_eq!(directives, [(5, "exec-env: A=1".to_owned()),
+                                (11, "exec-env: B=1".to_owned())]);
+        assert_eq!(ignored, [13]);
+    }
+
+    #[test]
+    fn header_ends_at_outer_attribute_item() {
+        let (directives, ignored) = header("header-ends-at-item.rs",
+                                           "// run-flags: --first\n\
+                                            #[derive(Debug)]\n\
+                                            struct S;\n\
+                                            // run-flags: --after-item\n");
+        assert_eq!(directives, [(1, "run-flags: --first".to_owned())]);
+        assert_eq!(ignored, [4]);
+    }
+}
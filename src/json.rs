@@ -10,9 +10,10 @@
 
 use errors::{Error, ErrorKind};
 use serde_json;
+use std::fmt;
 use std::str::FromStr;
 use std::path::Path;
-use runtest::ProcRes;
+use runtest::{FailureKind, ProcRes, TestFailure};
 
 // These structs are a subset of the ones found in
 // `syntax::json`.
@@ -29,6 +30,8 @@ struct Diagnostic {
 #[derive(Deserialize, Clone)]
 struct DiagnosticSpan {
     file_name: String,
+    byte_start: usize,
+    byte_end: usize,
     line_start: usize,
     line_end: usize,
     column_start: usize,
@@ -36,6 +39,7 @@ struct DiagnosticSpan {
     is_primary: bool,
     label: Option<String>,
     suggested_replacement: Option<String>,
+    suggestion_applicability: Option<String>,
     expansion: Option<Box<DiagnosticSpanMacroExpansion>>,
 }
 
@@ -56,35 +60,66 @@ struct DiagnosticCode {
     explanation: Option<String>,
 }
 
-pub fn parse_output(file_name: &str, output: &str, proc_res: &ProcRes) -> Vec<Error> {
-    output.lines()
-        .flat_map(|line| parse_line(file_name, line, output, proc_res))
-        .collect()
+/// A line of compiler output which looked like JSON but couldn't be decoded.
+#[derive(Debug)]
+pub struct ParseOutputError {
+    /// The offending line.
+    pub line: String,
+    /// Why it couldn't be decoded.
+    pub message: String,
 }
 
-fn parse_line(file_name: &str, line: &str, output: &str, proc_res: &ProcRes) -> Vec<Error> {
-    // The compiler sometimes intermingles non-JSON stuff into the
-    // output.  This hack just skips over such lines. Yuck.
-    if line.starts_with('{') {
+impl fmt::Display for ParseOutputError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "failed to decode compiler output as json: `{}`\nline: {}",
+               self.message, self.line)
+    }
+}
+
+/// Extract the messages for `file_name` from the JSON diagnostics in the
+/// `output` of the compiler (`--error-format json`), in the form
+/// `errors::match_errors` compares with the expected errors of a test.
+///
+/// Lines which aren't JSON are skipped, since the compiler sometimes mixes
+/// other output in.
+pub fn parse_output(file_name: &str, output: &str) -> Result<Vec<Error>, ParseOutputError> {
+    let mut errors = vec![];
+    for diagnostic in diagnostics(output)? {
+        push_expected_errors(&mut errors, &diagnostic, &[], file_name);
+    }
+    Ok(errors)
+}
+
+/// Decode the diagnostics in the `output` of the compiler.
+///
+/// The compiler sometimes intermingles non-JSON stuff into the output, and
+/// drivers or wrappers around it may print a banner before it starts. Lines
+/// which don't start with `{` are skipped, and so are lines which do but
+/// can't be decoded as long as no diagnostic came yet. After that, such a
+/// line most likely means the compiler crashed while writing it, which is an
+/// error; as is an undecodable line mentioning an internal compiler error.
+/// The skipped lines are still part of the output used for error patterns
+/// and shown when a test fails.
+fn diagnostics(output: &str) -> Result<Vec<Diagnostic>, ParseOutputError> {
+    let mut diagnostics = vec![];
+    for line in output.lines().filter(|line| line.starts_with('{')) {
         match serde_json::from_str::<Diagnostic>(line) {
-            Ok(diagnostic) => {
-                let mut expected_errors = vec![];
-                push_expected_errors(&mut expected_errors, &diagnostic, &[], file_name);
-                expected_errors
-            }
+            Ok(diagnostic) => diagnostics.push(diagnostic),
+            Err(_) if diagnostics.is_empty() && !line.contains(ICE_MARKER) => {}
             Err(error) => {
-                proc_res.fatal(Some(&format!("failed to decode compiler output as json: \
-                                              `{}`\noutput: {}\nline: {}",
-                                             error,
-                                             line,
-                                             output)));
+                return Err(ParseOutputError {
+                    line: line.to_owned(),
+                    message: error.to_string(),
+                });
             }
         }
-    } else {
-        vec![]
     }
+    Ok(diagnostics)
 }
 
+/// How the compiler starts its message when it crashes.
+const ICE_MARKER: &str = "internal compiler error";
+
 fn push_expected_errors(expected_errors: &mut Vec<Error>,
                         diagnostic: &Diagnostic,
                         default_spans: &[&DiagnosticSpan],
@@ -138,6 +173,14 @@ fn push_expected_errors(expected_errors: &mut Vec<Error>,
         }
     };
 
+    let code = diagnostic.code.as_ref().map(|code| code.code.clone());
+
+    // The spans an annotation can count, see `Error::span_count`.
+    let span_lines: Vec<_> = spans_in_this_file.iter()
+        .filter(|span| span.is_primary || span.label.is_some())
+        .map(|span| span.line_start)
+        .collect();
+
     // Convert multi-line messages into multiple expected
     // errors. We expect to replace these with something
     // more structured shortly anyhow.
@@ -148,8 +191,13 @@ fn push_expected_errors(expected_errors: &mut Vec<Error>,
             let kind = ErrorKind::from_str(&diagnostic.level).ok();
             expected_errors.push(Error {
                 line_num: span.line_start,
+                column: Some(span.column_start),
+                code: code.clone(),
                 kind,
                 msg,
+                count: 1,
+                span_count: None,
+                span_lines: span_lines.clone(),
             });
         }
     }
@@ -157,8 +205,13 @@ fn push_expected_errors(expected_errors: &mut Vec<Error>,
         for span in primary_spans {
             expected_errors.push(Error {
                 line_num: span.line_start,
+                column: Some(span.column_start),
+                code: code.clone(),
                 kind: None,
                 msg: with_code(span, next_line),
+                count: 1,
+                span_count: None,
+                span_lines: vec![],
             });
         }
     }
@@ -169,8 +222,13 @@ fn push_expected_errors(expected_errors: &mut Vec<Error>,
             for (index, line) in suggested_replacement.lines().enumerate() {
                 expected_errors.push(Error {
                     line_num: span.line_start + index,
+                    column: None,
+                    code: None,
                     kind: Some(ErrorKind::Suggestion),
                     msg: line.to_string(),
+                    count: 1,
+                    span_count: None,
+                    span_lines: vec![],
                 });
             }
         }
@@ -188,8 +246,32 @@ fn push_expected_errors(expected_errors: &mut Vec<Error>,
         .filter(|span| span.label.is_some()) {
         expected_errors.push(Error {
             line_num: span.line_start,
+            column: Some(span.column_start),
+            code: None,
             kind: Some(ErrorKind::Note),
             msg: span.label.clone().unwrap(),
+            count: 1,
+            span_count: None,
+            span_lines: vec![],
+        });
+    }
+
+    // Diagnostics without a primary span in this file can only be matched
+    // by `//~?` annotations, which don't have a line.
+    if primary_spans.is_empty() {
+        let msg = match diagnostic.code {
+            Some(ref code) => format!("{} [{}]", diagnostic.message, code.code),
+            None => diagnostic.message.clone(),
+        };
+        expected_errors.push(Error {
+            line_num: 0,
+            column: None,
+            code: code.clone(),
+            kind: ErrorKind::from_str(&diagnostic.level).ok(),
+            msg,
+            count: 1,
+            span_count: None,
+            span_lines,
         });
     }
 
@@ -205,8 +287,13 @@ fn push_backtrace(expected_errors: &mut Vec<Error>,
     if Path::new(&expansion.span.file_name) == Path::new(&file_name) {
         expected_errors.push(Error {
             line_num: expansion.span.line_start,
+            column: Some(expansion.span.column_start),
+            code: None,
             kind: Some(ErrorKind::Note),
             msg: format!("in this expansion of {}", expansion.macro_decl_name),
+            count: 1,
+            span_count: None,
+            span_lines: vec![],
         });
     }
 
@@ -214,3 +301,72 @@ fn push_backtrace(expected_errors: &mut Vec<Error>,
         push_backtrace(expected_errors, previous_expansion, file_name);
     }
 }
+
+/// Apply the machine applicable suggestions for `file_name` in the JSON
+/// `output` of the compiler to `source`, the contents of that file.
+///
+/// The spans of a suggestion are replaced together. Suggestions overlapping
+/// one applied earlier are left out, like rustfix does.
+pub fn apply_suggestions(file_name: &str, source: &str, output: &str, proc_res: &ProcRes)
+                         -> Result<String, Box<TestFailure>> {
+    let diagnostics = diagnostics(output)
+        .map_err(|e| proc_res.failure(FailureKind::Compile, &e.to_string()))?;
+    let mut suggestions = vec![];
+    for diagnostic in &diagnostics {
+        push_suggestions(&mut suggestions, diagnostic, file_name);
+    }
+
+    let mut replacements: Vec<(usize, usize, String)> = vec![];
+    for suggestion in suggestions {
+        let overlaps = suggestion.iter().any(|&(start, end, _)| {
+            replacements.iter().any(|&(s, e, _)| start < e && s < end)
+        });
+        if !overlaps {
+            replacements.extend(suggestion);
+        }
+    }
+    replacements.sort_by_key(|&(start, _, _)| start);
+    replacements.dedup();
+
+    let mut fixed = String::with_capacity(source.len());
+    let mut pos = 0;
+    for (start, end, replacement) in replacements {
+        if start < pos || end > source.len() {
+            return Err(proc_res.failure(FailureKind::Comparison,
+                                        &format!("suggestion for bytes {}..{} doesn't fit \
+                                                  the source",
+                                                 start, end)));
+        }
+        fixed.push_str(&source[pos..start]);
+        fixed.push_str(&replacement);
+        pos = end;
+    }
+    fixed.push_str(&source[pos..]);
+    Ok(fixed)
+}
+
+/// Collect the replacements of each machine applicable suggestion (a child
+/// diagnostic with suggested replacements) in the diagnostic.
+fn push_suggestions(suggestions: &mut Vec<Vec<(usize, usize, String)>>,
+                    diagnostic: &Diagnostic,
+                    file_name: &str) {
+    let replacements: Vec<_> = diagnostic.spans
+        .iter()
+        .filter(|span| Path::new(&span.file_name) == Path::new(&file_name))
+        .filter(|span| {
+            // Compilers which don't tell only emit applicable suggestions.
+            span.suggestion_applicability.as_ref()
+                .map_or(true, |a| a == "MachineApplicable")
+        })
+        .filter_map(|span| {
+            span.suggested_replacement.as_ref()
+                .map(|r| (span.byte_start, span.byte_end, r.clone()))
+        })
+        .collect();
+    if !replacements.is_empty() {
+        suggestions.push(replacements);
+    }
+    for child in &diagnostic.children {
+        push_suggestions(suggestions, child, file_name);
+    }
+}
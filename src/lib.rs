@@ -29,21 +29,28 @@ extern crate test;
 extern crate log;
 extern crate filetime;
 extern crate diff;
+extern crate regex;
 extern crate serde_json;
 #[macro_use]
 extern crate serde_derive;
 
+use std::collections::HashSet;
 use std::env;
 use std::ffi::OsString;
 use std::fs;
 use std::io;
+use std::io::Write;
 use std::path::{Path, PathBuf};
+use std::panic;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
 use common::{Mode, TestPaths};
 use common::{Pretty, DebugInfoGdb, DebugInfoLldb};
 
 use self::header::EarlyProps;
 
 pub mod uidiff;
+mod artifacts;
 pub mod util;
 mod json;
 pub mod header;
@@ -51,10 +58,118 @@ pub mod runtest;
 pub mod common;
 pub mod errors;
 mod read2;
+mod procgroup;
+mod uptodate;
 
 pub use common::Config;
+pub use json::{parse_output, ParseOutputError};
 
 pub fn run_tests(config: &Config) {
+    let config = checked_config(config).unwrap_or_else(|e| panic!("{}", e));
+    match run_checked_tests(&config) {
+        Ok(ref outcomes) if outcomes.iter().all(|o| !o.status.is_failure()) => {}
+        Ok(_) => panic!("Some tests failed"),
+        Err(e) => {
+            println!("I/O failure during tests: {:?}", e);
+        }
+    }
+}
+
+/// Like `run_tests`, but returns what happened instead of panicking when
+/// tests fail. Fails if the configuration is invalid (see
+/// `Config::missing_fields` and `Config::resolve_tool_paths`) or if writing
+/// the test output failed.
+pub fn run_tests_result(config: &Config) -> io::Result<TestSummary> {
+    let outcomes = run_tests_collect(config)?;
+    let mut summary = TestSummary::default();
+    for outcome in outcomes {
+        match outcome.status {
+            TestStatus::Passed => summary.passed += 1,
+            TestStatus::Ignored => summary.ignored += 1,
+            TestStatus::Failed | TestStatus::Error => {
+                summary.failed += 1;
+                summary.failures.push(outcome.name);
+            }
+        }
+    }
+    Ok(summary)
+}
+
+/// Like `run_tests_result`, but returns the outcome of each test, sorted by
+/// name.
+pub fn run_tests_collect(config: &Config) -> io::Result<Vec<TestOutcome>> {
+    run_checked_tests(&checked_config(config)?)
+}
+
+/// The outcome of `run_tests_result`. Tests which were filtered out aren't
+/// counted.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TestSummary {
+    pub passed: usize,
+    /// Including the tests which couldn't be run (`TestStatus::Error`).
+    pub failed: usize,
+    pub ignored: usize,
+    /// The names of the failed tests, as libtest shows them.
+    pub failures: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TestStatus {
+    Passed,
+    Failed,
+    Ignored,
+    /// The test couldn't be run for reasons outside of it, so that running
+    /// it again may well work, see `runtest::TestFailure::retryable`.
+    Error,
+}
+
+impl TestStatus {
+    pub fn is_failure(self) -> bool {
+        self == TestStatus::Failed || self == TestStatus::Error
+    }
+}
+
+/// The outcome of a single test, see `run_tests_collect`.
+#[derive(Clone, Debug)]
+pub struct TestOutcome {
+    /// The name of the test, as libtest shows it.
+    pub name: String,
+    pub paths: TestPaths,
+    pub status: TestStatus,
+    /// How long the test ran (zero for ignored tests).
+    pub duration: Duration,
+    /// Why the test failed.
+    pub message: Option<String>,
+    /// The files the stdout and stderr of the last command the failed test
+    /// ran were dumped to (`foo.out` and `foo.err`), if any.
+    pub stdout_file: Option<PathBuf>,
+    pub stderr_file: Option<PathBuf>,
+}
+
+/// A copy of `config` ready for running tests, see `run_tests_result`.
+fn checked_config(config: &Config) -> io::Result<Config> {
+    let missing = config.missing_fields();
+    if !missing.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                  format!("invalid compiletest configuration, these fields \
+                                           have to be set: {}", missing.join(", "))));
+    }
+    let mut config = config.clone();
+    config.resolve_tool_paths()?;
+    // Set but empty counts as unset, like `BLESS= cargo test`.
+    let blesses = |var| match env::var(var) {
+        Ok(v) => !v.is_empty() && v != "0",
+        Err(_) => false,
+    };
+    if ["BLESS", "COMPILETEST_BLESS"].iter().any(blesses) {
+        config.bless = true;
+    }
+    // Other runs may be going on at the same time with clones of `config`.
+    config.blessed = Default::default();
+    Ok(config)
+}
+
+fn run_checked_tests(config: &Config) -> io::Result<Vec<TestOutcome>> {
     if config.target.contains("android") {
         if let DebugInfoGdb = config.mode {
             println!("{} debug-info test uses tcp 5039 port.\
@@ -75,7 +190,15 @@ pub fn run_tests(config: &Config) {
     }
 
     let opts = test_opts(config);
-    let tests = make_tests(config);
+    let (tests, report) = make_tests_with_paths(config);
+    if !report.is_empty() {
+        report.print();
+    }
+    // Each run collects outcomes of its own, so that several suites can run
+    // in one process.
+    let outcomes = Arc::new(Mutex::new(ignored_outcomes(&opts, &tests)));
+    let tests = tests.into_iter().map(|(paths, test)| record_outcome(test, paths, &outcomes))
+        .collect();
     // sadly osx needs some file descriptor limits raised for running tests in
     // parallel (especially when we have lots and lots of child processes).
     // For context, see #8904
@@ -83,14 +206,99 @@ pub fn run_tests(config: &Config) {
     // Prevent issue #21352 UAC blocking .exe containing 'patch' etc. on Windows
     // If #11207 is resolved (adding manifest to .exe) this becomes unnecessary
     env::set_var("__COMPAT_LAYER", "RunAsInvoker");
-    let res = test::run_tests_console(&opts, tests.into_iter().collect());
-    match res {
-        Ok(true) => {}
-        Ok(false) => panic!("Some tests failed"),
-        Err(e) => {
-            println!("I/O failure during tests: {:?}", e);
-        }
+    let res = test::run_tests_console(&opts, tests);
+    if config.is_smoke_check() {
+        print_smoke_summary();
+    }
+    if config.bless {
+        print_bless_report(config);
     }
+    res?;
+    let mut outcomes = outcomes.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    outcomes.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(outcomes)
+}
+
+/// The outcomes of the `tests` libtest will report as ignored with `opts`.
+fn ignored_outcomes(opts: &test::TestOpts, tests: &[(TestPaths, test::TestDescAndFn)])
+                    -> Vec<TestOutcome> {
+    let descs = tests.iter()
+        .map(|(_, test)| test::TestDescAndFn {
+            desc: test.desc.clone(),
+            testfn: test::StaticTestFn(|| {}),
+        })
+        .collect();
+    let ignored: HashSet<String> = test::filter_tests(opts, descs).into_iter()
+        .filter(|test| test.desc.ignore)
+        .map(|test| test.desc.name.to_string())
+        .collect();
+    tests.iter()
+        .filter(|(_, test)| ignored.contains(&test.desc.name.to_string()))
+        .map(|(paths, test)| TestOutcome {
+            name: test.desc.name.to_string(),
+            paths: paths.clone(),
+            status: TestStatus::Ignored,
+            duration: Duration::from_secs(0),
+            message: None,
+            stdout_file: None,
+            stderr_file: None,
+        })
+        .collect()
+}
+
+/// Make `test` add its outcome to `outcomes` when it runs.
+fn record_outcome(test: test::TestDescAndFn,
+                  paths: TestPaths,
+                  outcomes: &Arc<Mutex<Vec<TestOutcome>>>)
+                  -> test::TestDescAndFn {
+    let testfn = match test.testfn {
+        test::DynTestFn(mut testfn) => {
+            let outcomes = outcomes.clone();
+            let name = test.desc.name.to_string();
+            let should_panic = test.desc.should_panic != test::ShouldPanic::No;
+            test::DynTestFn(Box::new(move || {
+                let start = Instant::now();
+                let result = panic::catch_unwind(panic::AssertUnwindSafe(&mut testfn));
+                let mut outcome = TestOutcome {
+                    name: name.clone(),
+                    paths: paths.clone(),
+                    status: TestStatus::Passed,
+                    duration: start.elapsed(),
+                    message: None,
+                    stdout_file: None,
+                    stderr_file: None,
+                };
+                match result {
+                    Ok(()) if should_panic => {
+                        outcome.status = TestStatus::Failed;
+                        outcome.message = Some("test did not panic as expected".to_owned());
+                    }
+                    Err(ref payload) if !should_panic => {
+                        outcome.status = TestStatus::Failed;
+                        if let Some(failure) = payload.downcast_ref::<runtest::TestFailure>() {
+                            if failure.retryable {
+                                outcome.status = TestStatus::Error;
+                            }
+                            outcome.message = Some(failure.to_string());
+                            outcome.stdout_file = failure.stdout_file.clone();
+                            outcome.stderr_file = failure.stderr_file.clone();
+                        } else if let Some(message) = payload.downcast_ref::<&str>() {
+                            outcome.message = Some((*message).to_owned());
+                        } else if let Some(message) = payload.downcast_ref::<String>() {
+                            outcome.message = Some(message.clone());
+                        }
+                    }
+                    _ => {}
+                }
+                outcomes.lock().unwrap_or_else(|e| e.into_inner()).push(outcome);
+                if let Err(payload) = result {
+                    panic::resume_unwind(payload);
+                }
+            }))
+        }
+        testfn => testfn,
+    };
+    test::TestDescAndFn { desc: test.desc, testfn }
 }
 
 pub fn test_opts(config: &Config) -> test::TestOpts {
@@ -115,40 +323,238 @@ pub fn test_opts(config: &Config) -> test::TestOpts {
 }
 
 pub fn make_tests(config: &Config) -> Vec<test::TestDescAndFn> {
+    let (tests, report) = make_tests_with_report(config);
+    if !report.is_empty() {
+        report.print();
+    }
+    tests
+}
+
+/// Like `make_tests`, but also returns a report of the directories that were
+/// skipped while collecting the tests.
+pub fn make_tests_with_report(config: &Config)
+                              -> (Vec<test::TestDescAndFn>, CollectionReport) {
+    let (tests, report) = make_tests_with_paths(config);
+    (tests.into_iter().map(|(_, test)| test).collect(), report)
+}
+
+/// Like `make_tests_with_report`, but also returns the paths of each test.
+fn make_tests_with_paths(config: &Config)
+                         -> (Vec<(TestPaths, test::TestDescAndFn)>, CollectionReport) {
     debug!("making tests from {:?}",
            config.src_base.display());
-    let mut tests = Vec::new();
+    config.check_stage_id();
+    if let Some(reason) = config.missing_runner() {
+        if runs_test_programs(config) {
+            println!("note: ignoring all tests: {}", reason);
+        }
+    }
+    if config.run == Some(false) && config.mode == Mode::RunFail {
+        println!("note: ignoring all tests: run-fail tests can't be checked without running them");
+    }
+    let mut report = CollectionReport::default();
+    let mut found = collect_test_paths(config, &mut report);
+    if let Some(time) = config.changed_since {
+        let total = found.iter().filter(|paths| matches_filter(config, paths)).count();
+        found.retain(|paths| uptodate::changed_since(config, paths, time));
+        let selected = found.iter().filter(|paths| matches_filter(config, paths)).count();
+        println!("note: selected {} of {} tests which changed since the given time",
+                 selected, total);
+    }
+    let tests: Vec<_> = found.into_iter()
+        .map(|paths| {
+            let test = make_collected_test(config, &paths, &mut report);
+            (paths, test)
+        })
+        .collect();
+    if config.skip_up_to_date {
+        let skipped = report.ignored.iter()
+            .filter(|test| test.reasons.iter().any(|r| r == uptodate::UP_TO_DATE))
+            .count();
+        println!("note: skipping {} of {} tests which are up to date", skipped, tests.len());
+    }
+    if let Some(ref path) = config.ignored_report {
+        write_ignored_report(config, path, &report.ignored);
+    }
+    (tests, report)
+}
+
+/// The names of the tests (as libtest shows them) made of any of `paths`,
+/// see `Config::changed_since` for what a test is made of. Only tests
+/// matching `config.filter` are considered.
+pub fn tests_touching_paths(config: &Config, paths: &[PathBuf]) -> Vec<String> {
+    let canonical = |path: &Path| path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    let paths: HashSet<PathBuf> = paths.iter().map(|path| canonical(path)).collect();
+
+    let mut report = CollectionReport::default();
+    let found = collect_test_paths(config, &mut report);
+    if !report.is_empty() {
+        report.print();
+    }
+    let candidates: Vec<_> = found.iter().filter(|paths| matches_filter(config, paths)).collect();
+    let names: Vec<String> = candidates.iter()
+        .filter(|testpaths| {
+            uptodate::test_inputs(config, testpaths).iter()
+                .any(|input| paths.contains(&canonical(input)))
+        })
+        .map(|testpaths| make_test_name(config, testpaths).to_string())
+        .collect();
+    println!("note: selected {} of {} tests touching the given paths",
+             names.len(), candidates.len());
+    names
+}
+
+fn collect_test_paths(config: &Config, report: &mut CollectionReport) -> Vec<TestPaths> {
+    let mut found = Vec::new();
+    let mut visited = HashSet::new();
     collect_tests_from_dir(config,
                            &config.src_base,
                            &config.src_base,
                            &PathBuf::new(),
-                           &mut tests)
-        .unwrap();
-    tests
+                           &mut found,
+                           report,
+                           &mut visited);
+    found
+}
+
+/// Whether libtest would run the test with `config.filter`.
+fn matches_filter(config: &Config, testpaths: &TestPaths) -> bool {
+    match config.filter {
+        Some(ref filter) => {
+            let name = make_test_name(config, testpaths).to_string();
+            if config.filter_exact { name == *filter } else { name.contains(&**filter) }
+        }
+        None => true,
+    }
+}
+
+/// Problems encountered while collecting tests, which caused parts of the
+/// test directory tree to be skipped.
+#[derive(Debug, Default)]
+pub struct CollectionReport {
+    /// Directories (or directory entries) which could not be read.
+    pub errors: Vec<(PathBuf, io::Error)>,
+    /// Directories which were not searched again because they were already
+    /// visited, e.g. through a symlink pointing to one of their parents.
+    pub cycles: Vec<PathBuf>,
+    /// Tests which were collected but will be ignored, with the reasons.
+    /// These are not problems, so `is_empty` and `print` leave them out.
+    pub ignored: Vec<IgnoredTest>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct IgnoredTest {
+    pub name: String,
+    /// The directives (or other reasons) causing the test to be ignored.
+    pub reasons: Vec<String>,
+}
+
+/// The contents of `config.ignored_report`.
+#[derive(Serialize)]
+struct IgnoredReport<'a> {
+    mode: String,
+    target: &'a str,
+    host: &'a str,
+    stage_id: &'a str,
+    llvm_version: Option<&'a str>,
+    gdb_version: Option<u32>,
+    lldb_version: Option<&'a str>,
+    ignored: &'a [IgnoredTest],
+}
+
+fn write_ignored_report(config: &Config, path: &Path, ignored: &[IgnoredTest]) {
+    let report = IgnoredReport {
+        mode: config.mode.to_string(),
+        target: &config.target,
+        host: &config.host,
+        stage_id: &config.stage_id,
+        llvm_version: config.llvm_version.as_deref(),
+        gdb_version: config.gdb_version,
+        lldb_version: config.lldb_version.as_deref(),
+        ignored,
+    };
+    let json = serde_json::to_string_pretty(&report).unwrap();
+    if let Err(e) = fs::File::create(path).and_then(|mut f| f.write_all(json.as_bytes())) {
+        panic!("failed to write the ignored tests report to `{}`: {}", path.display(), e);
+    }
+}
+
+impl CollectionReport {
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty() && self.cycles.is_empty()
+    }
+
+    pub fn print(&self) {
+        for (path, err) in &self.errors {
+            println!("warning: skipped `{}` while collecting tests: {}", path.display(), err);
+        }
+        for path in &self.cycles {
+            println!("warning: skipped `{}` while collecting tests: directory already visited \
+                      (symlink cycle?)", path.display());
+        }
+    }
+
+    fn record_error(&mut self, config: &Config, path: &Path, err: io::Error) {
+        if config.strict_collection {
+            panic!("failed to collect tests from `{}`: {}", path.display(), err);
+        }
+        debug!("skipping {:?}: {}", path.display(), err);
+        self.errors.push((path.to_path_buf(), err));
+    }
 }
 
 fn collect_tests_from_dir(config: &Config,
                           base: &Path,
                           dir: &Path,
                           relative_dir_path: &Path,
-                          tests: &mut Vec<test::TestDescAndFn>)
-                          -> io::Result<()> {
+                          tests: &mut Vec<TestPaths>,
+                          report: &mut CollectionReport,
+                          visited: &mut HashSet<PathBuf>) {
+    // Guard against symlink cycles by never entering the same directory twice.
+    match dir.canonicalize() {
+        Ok(canonical) => {
+            if !visited.insert(canonical) {
+                report.cycles.push(dir.to_path_buf());
+                return;
+            }
+        }
+        Err(e) => {
+            report.record_error(config, dir, e);
+            return;
+        }
+    }
+
+    let mut files = Vec::new();
+    match fs::read_dir(dir) {
+        Ok(entries) => {
+            for entry in entries {
+                match entry {
+                    Ok(entry) => files.push(entry),
+                    Err(e) => report.record_error(config, dir, e),
+                }
+            }
+        }
+        Err(e) => {
+            report.record_error(config, dir, e);
+            return;
+        }
+    }
+
     // Ignore directories that contain a file
     // `compiletest-ignore-dir`.
-    for file in try!(fs::read_dir(dir)) {
-        let file = try!(file);
+    for file in &files {
         let name = file.file_name();
         if name == *"compiletest-ignore-dir" {
-            return Ok(());
+            return;
         }
-        if name == *"Makefile" && config.mode == Mode::RunMake {
+        if (name == *"Makefile" || name == *"rmake.rs") && config.mode == Mode::RunMake {
             let paths = TestPaths {
                 file: dir.to_path_buf(),
                 base: base.to_path_buf(),
                 relative_dir: relative_dir_path.parent().unwrap().to_path_buf(),
             };
-            tests.push(make_test(config, &paths));
-            return Ok(())
+            tests.push(paths);
+            return
         }
     }
 
@@ -163,12 +569,10 @@ fn collect_tests_from_dir(config: &Config,
 
     // Add each `.rs` file as a test, and recurse further on any
     // subdirectories we find, except for `aux` directories.
-    let dirs = try!(fs::read_dir(dir));
-    for file in dirs {
-        let file = try!(file);
+    for file in files {
         let file_path = file.path();
         let file_name = file.file_name();
-        if is_test(&file_name) {
+        if is_test_file(config, &file_path) {
             debug!("found test file: {:?}", file_path.display());
             // output directory `$build/foo` so we can write
             // `$build/foo/bar` into it. We do this *now* in this
@@ -183,7 +587,7 @@ fn collect_tests_from_dir(config: &Config,
                 base: base.to_path_buf(),
                 relative_dir: relative_dir_path.to_path_buf(),
             };
-            tests.push(make_test(config, &paths))
+            tests.push(paths)
         } else if file_path.is_dir() {
             let relative_file_path = relative_dir_path.join(file.file_name());
             if &file_name == "auxiliary" {
@@ -196,17 +600,18 @@ fn collect_tests_from_dir(config: &Config,
                 fs::create_dir_all(&build_dir).unwrap();
             } else {
                 debug!("found directory: {:?}", file_path.display());
-                try!(collect_tests_from_dir(config,
+                collect_tests_from_dir(config,
                                        base,
                                        &file_path,
                                        &relative_file_path,
-                                       tests));
+                                       tests,
+                                       report,
+                                       visited);
             }
         } else {
             debug!("found other file/directory: {:?}", file_path.display());
         }
     }
-    Ok(())
 }
 
 pub fn is_test(file_name: &OsString) -> bool {
@@ -221,7 +626,51 @@ pub fn is_test(file_name: &OsString) -> bool {
     !invalid_prefixes.iter().any(|p| file_name.starts_with(p))
 }
 
+/// Like `is_test`, but also accepts the extensions in
+/// `config.test_extensions` and applies `config.test_file_filter`.
+pub fn is_test_file(config: &Config, path: &Path) -> bool {
+    let file_name = match path.file_name().and_then(|f| f.to_str()) {
+        Some(f) => f,
+        None => return false,
+    };
+
+    // `is_test` only knows about `.rs` files, so files with one of the other
+    // extensions are checked as if they had that one instead.
+    let is_test_name = |name: &str| is_test(&OsString::from(name));
+    let has_test_name = is_test_name(file_name) ||
+        config.test_extensions.iter().any(|ext| {
+            let ext = ext.strip_prefix('.').unwrap_or(ext);
+            file_name.ends_with(&format!(".{}", ext)) &&
+                is_test_name(&format!("{}.rs", &file_name[..file_name.len() - ext.len() - 1]))
+        });
+    if !has_test_name {
+        return false;
+    }
+
+    match config.test_file_filter {
+        Some(ref filter) => filter(path),
+        None => true,
+    }
+}
+
 pub fn make_test(config: &Config, testpaths: &TestPaths) -> test::TestDescAndFn {
+    make_test_with_ignore_reasons(config, testpaths).0
+}
+
+fn make_collected_test(config: &Config, testpaths: &TestPaths, report: &mut CollectionReport)
+                       -> test::TestDescAndFn {
+    let (test, ignore_reasons) = make_test_with_ignore_reasons(config, testpaths);
+    if test.desc.ignore {
+        report.ignored.push(IgnoredTest {
+            name: test.desc.name.to_string(),
+            reasons: ignore_reasons,
+        });
+    }
+    test
+}
+
+fn make_test_with_ignore_reasons(config: &Config, testpaths: &TestPaths)
+                                 -> (test::TestDescAndFn, Vec<String>) {
     let early_props = EarlyProps::from_file(config, &testpaths.file);
 
     // The `should-fail` annotation doesn't apply to pretty tests,
@@ -236,15 +685,46 @@ pub fn make_test(config: &Config, testpaths: &TestPaths) -> test::TestDescAndFn
         }
     };
 
-    test::TestDescAndFn {
+    let mut ignore_reasons = early_props.ignored_by;
+    // Tests which run the compiled program can't do so without a runner.
+    if runs_test_programs(config) {
+        if let Some(reason) = config.missing_runner() {
+            ignore_reasons.push(reason.to_owned());
+        }
+    }
+    if config.run == Some(false) && config.mode == Mode::RunFail {
+        ignore_reasons.push("run-fail tests can't be checked without running them".to_owned());
+    }
+    if let Some(ref backend) = config.codegen_backend {
+        if !backend.exists() {
+            ignore_reasons.push(format!("codegen backend `{}` not found", backend.display()));
+        }
+    }
+    if ignore_reasons.is_empty() && config.skip_up_to_date &&
+        uptodate::is_up_to_date(config, testpaths) {
+        ignore_reasons.push(uptodate::UP_TO_DATE.to_owned());
+    }
+    let ignore = early_props.ignore || !ignore_reasons.is_empty();
+
+    let test = test::TestDescAndFn {
         desc: test::TestDesc {
             name: make_test_name(config, testpaths),
-            ignore: early_props.ignore,
+            ignore,
             should_panic: should_panic,
             allow_fail: false,
         },
-        testfn: make_test_closure(config, testpaths),
-    }
+        testfn: if early_props.exclusive {
+            make_exclusive_test_closure(config, testpaths)
+        } else {
+            make_test_closure(config, testpaths)
+        },
+    };
+    (test, ignore_reasons)
+}
+
+fn runs_test_programs(config: &Config) -> bool {
+    config.run != Some(false) &&
+        matches!(config.mode, Mode::RunPass | Mode::RunFail | Mode::RunPassValgrind)
 }
 
 fn stamp(config: &Config, testpaths: &TestPaths) -> PathBuf {
@@ -257,6 +737,83 @@ fn stamp(config: &Config, testpaths: &TestPaths) -> PathBuf {
           .join(stamp_name)
 }
 
+/// Time spent on the tests smoke checked so far (see `Config::smoke_mode`).
+#[derive(Default)]
+struct SmokeTimes {
+    tests: usize,
+    elapsed: Duration,
+    /// How many of the tests ran fully before, and how long that took.
+    tests_with_full_run: usize,
+    elapsed_in_full_run: Duration,
+}
+
+static SMOKE_TIMES: Mutex<Option<SmokeTimes>> = Mutex::new(None);
+
+/// Record the time a smoke check took, and the time the last full run of the
+/// test took, if known.
+fn record_smoke_time(elapsed: Duration, full_run: Option<Duration>) {
+    let mut times = SMOKE_TIMES.lock().unwrap_or_else(|e| e.into_inner());
+    let times = times.get_or_insert_with(SmokeTimes::default);
+    times.tests += 1;
+    times.elapsed += elapsed;
+    if let Some(full_run) = full_run {
+        times.tests_with_full_run += 1;
+        times.elapsed_in_full_run += full_run;
+    }
+}
+
+fn print_smoke_summary() {
+    let times = SMOKE_TIMES.lock().unwrap_or_else(|e| e.into_inner());
+    let times = match *times {
+        Some(ref times) => times,
+        None => return,
+    };
+    println!("smoke: checked {} tests in {:.1}s (summed over all tests)",
+             times.tests, secs(times.elapsed));
+    if times.tests_with_full_run == times.tests {
+        let saving = secs(times.elapsed_in_full_run) - secs(times.elapsed);
+        println!("smoke: their last full runs took {:.1}s, saving {:.1}s",
+                 secs(times.elapsed_in_full_run), saving);
+    } else {
+        println!("smoke: {} of them never ran fully, so the saving is unknown",
+                 times.tests - times.tests_with_full_run);
+    }
+}
+
+/// What `Config::bless` did to an expected output.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BlessAction {
+    Created,
+    Modified,
+    Deleted,
+}
+
+/// List the expected outputs blessed during the run of `config`, so that they
+/// can be reviewed.
+fn print_bless_report(config: &Config) {
+    let mut blessed = config.blessed.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    if blessed.is_empty() {
+        println!("bless: all expected outputs in `{}` were up to date",
+                 config.src_base.display());
+        return;
+    }
+    blessed.sort();
+    println!("bless: updated {} expected outputs, review them before committing:",
+             blessed.len());
+    for (path, action) in blessed {
+        let action = match action {
+            BlessAction::Created => "created",
+            BlessAction::Modified => "modified",
+            BlessAction::Deleted => "deleted",
+        };
+        println!("    {:<8} {}", action, path.display());
+    }
+}
+
+fn secs(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + duration.subsec_nanos() as f64 / 1e9
+}
+
 pub fn make_test_name(config: &Config, testpaths: &TestPaths) -> test::TestName {
     // Convert a complete path to something like
     //
@@ -265,15 +822,38 @@ pub fn make_test_name(config: &Config, testpaths: &TestPaths) -> test::TestName
         PathBuf::from(config.src_base.file_name().unwrap())
         .join(&testpaths.relative_dir)
         .join(&testpaths.file.file_name().unwrap());
-    test::DynTestName(format!("[{}] {}", config.mode, path.display()))
+    let smoke = if config.is_smoke_check() { " smoke" } else { "" };
+    // Marked so that the report shows which tests ran on their own.
+    let exclusive = if header::is_exclusive(config, &testpaths.file) { " exclusive" } else { "" };
+    test::DynTestName(format!("[{}{}{}] {}", config.mode, smoke, exclusive, path.display()))
 }
 
+// Tests marked `// exclusive` hold this lock for writing while they run, all
+// other tests hold it for reading, so an exclusive test never overlaps with
+// any other test.
+static EXCLUSIVE: RwLock<()> = RwLock::new(());
+
 pub fn make_test_closure(config: &Config, testpaths: &TestPaths) -> test::TestFn {
     let config = config.clone();
     let testpaths = testpaths.clone();
     test::DynTestFn(Box::new(move || {
         #[cfg(feature = "stable")]
         let config = config.clone();  // FIXME: why is this needed?
+        // A panicking test poisons the lock, but the lock guards no data.
+        let _guard = EXCLUSIVE.read().unwrap_or_else(|e| e.into_inner());
+        runtest::run(config, &testpaths)
+    }))
+}
+
+/// Like `make_test_closure`, but the test waits for all running tests to
+/// finish and doesn't let any other test start until it is done.
+pub fn make_exclusive_test_closure(config: &Config, testpaths: &TestPaths) -> test::TestFn {
+    let config = config.clone();
+    let testpaths = testpaths.clone();
+    test::DynTestFn(Box::new(move || {
+        #[cfg(feature = "stable")]
+        let config = config.clone();  // FIXME: why is this needed?
+        let _guard = EXCLUSIVE.write().unwrap_or_else(|e| e.into_inner());
         runtest::run(config, &testpaths)
     }))
 }
@@ -386,3 +966,55 @@ fn extract_lldb_version(full_version_line: Option<String>) -> Option<String> {
 fn is_blacklisted_lldb_version(version: &str) -> bool {
     version == "350"
 }
+
+#[cfg(test)]
+mod tests {
+    use super::make_tests_with_report;
+    use common::Config;
+
+    #[cfg(unix)]
+    #[test]
+    fn collection_report() {
+        use std::fs;
+        use std::os::unix::fs::{symlink, PermissionsExt};
+        use std::panic;
+
+        let src = std::env::temp_dir().join("compiletest-collection-report");
+        if src.exists() {
+            fs::set_permissions(src.join("locked"), fs::Permissions::from_mode(0o755)).ok();
+            fs::remove_dir_all(&src).unwrap();
+        }
+        fs::create_dir_all(src.join("nested")).unwrap();
+        fs::write(src.join("nested/test.rs"), "fn main() {}\n").unwrap();
+        symlink(&src, src.join("nested/loop")).unwrap();
+
+        let mut config = Config::default();
+        config.mode = "run-pass".parse().unwrap();
+        config.src_base = src.clone();
+        let (tests, report) = make_tests_with_report(&config);
+        assert_eq!(tests.len(), 1);
+        assert_eq!(report.cycles, [src.join("nested/loop")]);
+        assert!(report.errors.is_empty());
+
+        let locked = src.join("locked");
+        fs::create_dir(&locked).unwrap();
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0)).unwrap();
+        // Permissions don't keep root out.
+        if fs::read_dir(&locked).is_err() {
+            let (tests, report) = make_tests_with_report(&config);
+            assert_eq!(tests.len(), 1);
+            assert_eq!(report.errors.len(), 1);
+            assert_eq!(report.errors[0].0, locked);
+            assert_eq!(report.errors[0].1.kind(), std::io::ErrorKind::PermissionDenied);
+
+            config.strict_collection = true;
+            let message = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+                make_tests_with_report(&config)
+            })).err().unwrap().downcast::<String>().unwrap();
+            assert!(message.starts_with(&format!("failed to collect tests from `{}`",
+                                                 locked.display())));
+        }
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+        fs::remove_dir_all(&src).unwrap();
+    }
+}
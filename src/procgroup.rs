@@ -0,0 +1,145 @@
+// Copyright 2012-2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Running test programs in a process group of their own, so that processes
+//! they spawn can't outlive them.
+//!
+//! `isolate` has to be called on the `Command` before spawning it, and
+//! `ProcessGroup::new` right after. `ProcessGroup::kill` kills everything
+//! left in the group once the test program exited, so that nothing keeps its
+//! pipes open, and dropping the `ProcessGroup` (also while unwinding) does the
+//! same. On Unix the program becomes the leader of a new session, on Windows
+//! it is put into a job object.
+
+pub use self::imp::{isolate, ProcessGroup};
+
+#[cfg(not(any(unix, windows)))]
+mod imp {
+    use std::process::{Child, Command};
+
+    pub fn isolate(_command: &mut Command) {}
+
+    pub struct ProcessGroup;
+
+    impl ProcessGroup {
+        pub fn new(_child: &Child) -> ProcessGroup {
+            ProcessGroup
+        }
+
+        pub fn kill(&self) {}
+    }
+}
+
+#[cfg(unix)]
+mod imp {
+    use std::io;
+    use std::os::unix::process::CommandExt;
+    use std::process::{Child, Command};
+    use libc;
+
+    pub fn isolate(command: &mut Command) {
+        unsafe {
+            command.pre_exec(|| {
+                if libc::setsid() == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+    }
+
+    pub struct ProcessGroup {
+        pgid: libc::pid_t,
+    }
+
+    impl ProcessGroup {
+        pub fn new(child: &Child) -> ProcessGroup {
+            // `setsid` made the child the leader of its own process group.
+            ProcessGroup { pgid: child.id() as libc::pid_t }
+        }
+
+        pub fn kill(&self) {
+            // The group outlives its leader as long as anything is left in
+            // it, so its id can't have been reused yet.
+            unsafe {
+                libc::kill(-self.pgid, libc::SIGKILL);
+            }
+        }
+    }
+
+    impl Drop for ProcessGroup {
+        fn drop(&mut self) {
+            self.kill();
+        }
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    extern crate winapi;
+
+    use std::mem;
+    use std::os::windows::io::AsRawHandle;
+    use std::process::{Child, Command};
+    use std::ptr;
+    use self::winapi::shared::minwindef::{DWORD, LPVOID};
+    use self::winapi::um::handleapi::CloseHandle;
+    use self::winapi::um::jobapi2::{AssignProcessToJobObject, CreateJobObjectW,
+                                    SetInformationJobObject, TerminateJobObject};
+    use self::winapi::um::winnt::{HANDLE, JOBOBJECT_EXTENDED_LIMIT_INFORMATION,
+                                  JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE,
+                                  JobObjectExtendedLimitInformation};
+
+    pub fn isolate(_command: &mut Command) {}
+
+    pub struct ProcessGroup {
+        job: HANDLE,
+    }
+
+    impl ProcessGroup {
+        pub fn new(child: &Child) -> ProcessGroup {
+            unsafe {
+                let job = CreateJobObjectW(ptr::null_mut(), ptr::null());
+                if job.is_null() {
+                    return ProcessGroup { job };
+                }
+                // Also kill the processes if we exit without dropping this.
+                let mut info: JOBOBJECT_EXTENDED_LIMIT_INFORMATION = mem::zeroed();
+                info.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+                SetInformationJobObject(job,
+                                        JobObjectExtendedLimitInformation,
+                                        &mut info as *mut _ as LPVOID,
+                                        mem::size_of_val(&info) as DWORD);
+                AssignProcessToJobObject(job, child.as_raw_handle() as HANDLE);
+                ProcessGroup { job }
+            }
+        }
+
+        pub fn kill(&self) {
+            if !self.job.is_null() {
+                unsafe {
+                    TerminateJobObject(self.job, 1);
+                }
+            }
+        }
+    }
+
+    impl Drop for ProcessGroup {
+        fn drop(&mut self) {
+            if self.job.is_null() {
+                return;
+            }
+            self.kill();
+            unsafe {
+                CloseHandle(self.job);
+            }
+        }
+    }
+}
@@ -10,6 +10,13 @@
 
 // FIXME: This is a complete copy of `cargo/src/cargo/util/read2.rs`
 // Consider unify the read2() in libstd, cargo and this to prevent further code duplication.
+//
+// Unlike cargo's, `read2` takes an `exited` callback telling whether the child
+// process exited, which is called while waiting for output. Processes it
+// spawned may inherit the pipes and keep them open after it exited, the
+// callback can kill them once it did. On Windows, the pipes are then given a
+// grace period to reach EOF, after which reading is abandoned with what was
+// read.
 
 pub use self::imp::read2;
 
@@ -20,7 +27,8 @@ mod imp {
 
     pub fn read2(out_pipe: ChildStdout,
                  err_pipe: ChildStderr,
-                 data: &mut FnMut(bool, &mut Vec<u8>, bool)) -> io::Result<()> {
+                 data: &mut dyn FnMut(bool, &mut Vec<u8>, bool),
+                 _exited: &mut dyn FnMut() -> bool) -> io::Result<()> {
         let mut buffer = Vec::new();
         out_pipe.read_to_end(&mut buffer)?;
         data(true, &mut buffer, true);
@@ -40,9 +48,13 @@ mod imp {
     use std::process::{ChildStdout, ChildStderr};
     use libc;
 
+    /// How often to check whether the child exited while the pipes are quiet.
+    const POLL_INTERVAL_MS: libc::c_int = 100;
+
     pub fn read2(mut out_pipe: ChildStdout,
                  mut err_pipe: ChildStderr,
-                 data: &mut FnMut(bool, &mut Vec<u8>, bool)) -> io::Result<()> {
+                 data: &mut dyn FnMut(bool, &mut Vec<u8>, bool),
+                 exited: &mut dyn FnMut() -> bool) -> io::Result<()> {
         unsafe {
             libc::fcntl(out_pipe.as_raw_fd(), libc::F_SETFL, libc::O_NONBLOCK);
             libc::fcntl(err_pipe.as_raw_fd(), libc::F_SETFL, libc::O_NONBLOCK);
@@ -50,6 +62,7 @@ mod imp {
 
         let mut out_done = false;
         let mut err_done = false;
+        let mut child_done = false;
         let mut out = Vec::new();
         let mut err = Vec::new();
 
@@ -59,8 +72,13 @@ mod imp {
         fds[1].fd = err_pipe.as_raw_fd();
         fds[1].events = libc::POLLIN;
         loop {
-            // wait for either pipe to become readable using `select`
-            let r = unsafe { libc::poll(fds.as_mut_ptr(), 2, -1) };
+            if !child_done {
+                child_done = exited();
+            }
+            // wait for either pipe to become readable using `select`, checking
+            // whether the child exited in between until it did
+            let timeout = if child_done { -1 } else { POLL_INTERVAL_MS };
+            let r = unsafe { libc::poll(fds.as_mut_ptr(), 2, timeout) };
             if r == -1 {
                 let err = io::Error::last_os_error();
                 if err.kind() == io::ErrorKind::Interrupted {
@@ -111,22 +129,32 @@ mod imp {
     use std::os::windows::prelude::*;
     use std::process::{ChildStdout, ChildStderr};
     use std::slice;
+    use std::time::{Duration, Instant};
 
     use self::miow::iocp::{CompletionPort, CompletionStatus};
     use self::miow::pipe::NamedPipe;
     use self::miow::Overlapped;
-    use self::winapi::shared::winerror::ERROR_BROKEN_PIPE;
+    use self::winapi::shared::winerror::{ERROR_BROKEN_PIPE, WAIT_TIMEOUT};
+    use self::winapi::um::ioapiset::CancelIoEx;
+    use self::winapi::um::winnt::HANDLE;
+
+    /// How often to check whether the child exited while the pipes are quiet.
+    const POLL_INTERVAL: Duration = Duration::from_millis(100);
+    /// How long to wait for EOF once the child exited.
+    const GRACE_PERIOD: Duration = Duration::from_secs(5);
 
     struct Pipe<'a> {
         dst: &'a mut Vec<u8>,
         overlapped: Overlapped,
         pipe: NamedPipe,
         done: bool,
+        abandoned: bool,
     }
 
     pub fn read2(out_pipe: ChildStdout,
                  err_pipe: ChildStderr,
-                 data: &mut FnMut(bool, &mut Vec<u8>, bool)) -> io::Result<()> {
+                 data: &mut dyn FnMut(bool, &mut Vec<u8>, bool),
+                 exited: &mut dyn FnMut() -> bool) -> io::Result<()> {
         let mut out = Vec::new();
         let mut err = Vec::new();
 
@@ -142,9 +170,26 @@ mod imp {
             err_pipe.read()?;
 
             let mut status = [CompletionStatus::zero(), CompletionStatus::zero()];
+            let mut exited_at = None;
 
             while !out_pipe.done || !err_pipe.done {
-                for status in port.get_many(&mut status, None)? {
+                let statuses = match port.get_many(&mut status, Some(POLL_INTERVAL)) {
+                    Ok(statuses) => statuses,
+                    Err(ref e) if e.raw_os_error() == Some(WAIT_TIMEOUT as i32) => {
+                        if exited_at.is_none() && exited() {
+                            exited_at = Some(Instant::now());
+                        }
+                        // Something else holds the pipes open, stop waiting
+                        // for it. The cancelled reads still complete.
+                        if exited_at.map_or(false, |at| at.elapsed() >= GRACE_PERIOD) {
+                            out_pipe.abandon();
+                            err_pipe.abandon();
+                        }
+                        continue;
+                    }
+                    Err(e) => return Err(e),
+                };
+                for status in statuses {
                     if status.token() == 0 {
                         out_pipe.complete(status);
                         data(true, out_pipe.dst, out_pipe.done);
@@ -168,10 +213,14 @@ mod imp {
                 pipe: NamedPipe::from_raw_handle(p.into_raw_handle()),
                 overlapped: Overlapped::zero(),
                 done: false,
+                abandoned: false,
             }
         }
 
         unsafe fn read(&mut self) -> io::Result<()> {
+            if self.abandoned {
+                return Ok(());
+            }
             let dst = slice_to_end(self.dst);
             match self.pipe.read_overlapped(dst, self.overlapped.raw()) {
                 Ok(_) => Ok(()),
@@ -189,10 +238,18 @@ mod imp {
         unsafe fn complete(&mut self, status: &CompletionStatus) {
             let prev = self.dst.len();
             self.dst.set_len(prev + status.bytes_transferred() as usize);
-            if status.bytes_transferred() == 0 {
+            if status.bytes_transferred() == 0 || self.abandoned {
                 self.done = true;
             }
         }
+
+        /// Cancel the pending read, if any, and don't start another one.
+        unsafe fn abandon(&mut self) {
+            if !self.done && !self.abandoned {
+                CancelIoEx(self.pipe.as_raw_handle() as HANDLE, self.overlapped.raw());
+            }
+            self.abandoned = true;
+        }
     }
 
     unsafe fn slice_to_end(v: &mut Vec<u8>) -> &mut [u8] {
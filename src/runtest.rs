@@ -8,28 +8,39 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use common::{Config, TestPaths};
+use common::{Config, Mode, TestPaths};
 use common::{CompileFail, ParseFail, Pretty, RunFail, RunPass, RunPassValgrind};
-use common::{Codegen, DebugInfoLldb, DebugInfoGdb, Rustdoc, CodegenUnits};
+use common::{Codegen, Assembly, DebugInfoLldb, DebugInfoGdb, Rustdoc, CodegenUnits};
 use common::{Incremental, RunMake, Ui, MirOpt};
+use artifacts::Artifacts;
 use diff;
-use errors::{self, ErrorKind, Error};
+use errors::{self, ErrorKind, Error, MatchResult, message_similarity};
 use filetime::FileTime;
 use json;
-use header::TestProps;
-use util::logv;
+use procgroup::{self, ProcessGroup};
+use regex::Regex;
+use serde_json;
+use header::{Normalization, TestProps};
+use uptodate;
+use util::{self, logv, merge_env};
 
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::collections::hash_map::DefaultHasher;
 use std::env;
 use std::ffi::OsString;
 use std::fs::{self, File, create_dir_all};
 use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::io::prelude::*;
 use std::io::{self, BufReader};
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
+use std::panic;
 use std::process::{Command, Output, ExitStatus, Stdio, Child};
 use std::str;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 
 use extract_gdb_version;
 
@@ -46,19 +57,95 @@ pub fn dylib_env_var() -> &'static str {
     }
 }
 
+/// The ways in which a test can fail.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FailureKind {
+    /// Compiling the test didn't go as expected.
+    Compile,
+    /// Running the compiled test didn't go as expected.
+    Run,
+    /// An output of the test didn't match what was expected of it.
+    Comparison,
+    /// The test couldn't be run at all, e.g. because a tool it needs is
+    /// missing or because of a broken header.
+    Infrastructure,
+}
+
+/// A failed test. Its details were already printed when it failed.
+#[derive(Debug)]
+#[must_use]
+pub struct TestFailure {
+    pub kind: FailureKind,
+    pub message: String,
+    /// The revision which failed, if the test has revisions.
+    pub revision: Option<String>,
+    /// The process whose result made the test fail, if any.
+    pub proc_res: Option<Box<ProcRes>>,
+    /// Whether the test failed for reasons outside of it, like losing the
+    /// connection to the device it runs on, so that running it again may
+    /// well work.
+    pub retryable: bool,
+    /// The bug the test documents, given by `known-bug`.
+    pub known_bug: Option<String>,
+    /// The files the stdout and stderr of the last command the test ran
+    /// were dumped to, if any.
+    pub stdout_file: Option<PathBuf>,
+    pub stderr_file: Option<PathBuf>,
+}
+
+impl TestFailure {
+    pub fn new(kind: FailureKind, message: &str) -> TestFailure {
+        TestFailure {
+            kind,
+            message: message.to_owned(),
+            revision: None,
+            proc_res: None,
+            retryable: false,
+            known_bug: None,
+            stdout_file: None,
+            stderr_file: None,
+        }
+    }
+}
+
+impl fmt::Display for TestFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.revision {
+            Some(ref rev) => write!(f, "error in revision `{}`: {}", rev, self.message)?,
+            None => write!(f, "error: {}", self.message)?,
+        }
+        match self.known_bug {
+            Some(ref bug) => write!(f, " (known bug {})", bug),
+            None => Ok(()),
+        }
+    }
+}
+
+type TestResult<T = ()> = Result<T, Box<TestFailure>>;
+
+/// Run a test, panicking if it fails as libtest expects. The panic carries
+/// the `TestFailure`, see `run_tests_collect`.
 pub fn run(config: Config, testpaths: &TestPaths) {
+    if let Err(failure) = try_run(config, testpaths) {
+        panic::resume_unwind(failure);
+    }
+}
+
+/// Run a test, returning how it failed instead of panicking.
+pub fn try_run(config: Config, testpaths: &TestPaths) -> TestResult {
     match &*config.target {
 
-        "arm-linux-androideabi" | "armv7-linux-androideabi" | "aarch64-linux-android" => {
+        target if target.contains("android") && config.remote_test_client.is_none() => {
             if !config.adb_device_status {
-                panic!("android device not available");
+                return Err(infrastructure_failure("android device not available"));
             }
         }
 
         _ => {
             // android has its own gdb handling
             if config.mode == DebugInfoGdb && config.gdb.is_none() {
-                panic!("gdb not available but debuginfo gdb debuginfo test requested");
+                return Err(infrastructure_failure(
+                    "gdb not available but debuginfo gdb debuginfo test requested"));
             }
         }
     }
@@ -68,34 +155,172 @@ pub fn run(config: Config, testpaths: &TestPaths) {
         print!("\n\n");
     }
     debug!("running {:?}", testpaths.file.display());
+    if config.is_smoke_check() {
+        return smoke_check(&config, testpaths);
+    }
+    let start = Instant::now();
     let base_props = TestProps::from_file(&testpaths.file, None, &config);
 
     let base_cx = TestCx { config: &config,
                            props: &base_props,
                            testpaths,
                            revision: None };
-    base_cx.init_all();
+    if !config.dry_run {
+        base_cx.artifacts().clear();
+    }
+    base_cx.init_all()?;
+    forget_shared_aux_builds(&testpaths.file);
+    if !config.dry_run {
+        uptodate::forget(&config, testpaths);
+    }
 
+    // The dep-info files written for the test, see `Config::skip_up_to_date`
+    let mut dep_infos = Vec::new();
     if base_props.revisions.is_empty() {
-        base_cx.run_revision()
+        let _ = fs::remove_file(base_cx.dep_info_path());
+        base_cx.check_known_bug(base_cx.run_revision())?;
+        dep_infos.push(base_cx.dep_info_path());
     } else {
         for revision in &base_props.revisions {
             let revision_props = TestProps::from_file(&testpaths.file,
                                                       Some(revision),
                                                       &config);
+            if revision_props.ignore {
+                println!("note: revision `{}` is ignored", revision);
+                continue;
+            }
             let rev_cx = TestCx {
                 config: &config,
                 props: &revision_props,
                 testpaths,
                 revision: Some(revision)
             };
-            rev_cx.run_revision();
+            let _ = fs::remove_file(rev_cx.dep_info_path());
+            rev_cx.check_known_bug(rev_cx.run_revision())?;
+            dep_infos.push(rev_cx.dep_info_path());
         }
     }
 
     base_cx.complete_all();
+    forget_shared_aux_builds(&testpaths.file);
+
+    if config.dry_run {
+        println!("dry-run ok");
+        return Ok(());
+    }
+    if config.skip_up_to_date {
+        uptodate::record(&config, testpaths, &dep_infos);
+    }
+    // The stamp records how long the test took, for smoke checks to compare
+    // with.
+    let elapsed = start.elapsed();
+    let elapsed_ms = elapsed.as_secs() * 1000 + u64::from(elapsed.subsec_millis());
+    File::create(::stamp(&config, testpaths))
+        .and_then(|mut f| write!(f, "{}", elapsed_ms))
+        .unwrap();
+    Ok(())
+}
+
+/// How many of `patterns` are found in order on the lines of `output`, each
+/// on a line after the previous one.
+fn count_found_in_order<P, F>(output: &str, patterns: &[P], matches: F) -> usize
+    where F: Fn(&str, &P) -> bool
+{
+    let mut found = 0;
+    for line in output.lines() {
+        if found == patterns.len() {
+            break;
+        }
+        if matches(line, &patterns[found]) {
+            found += 1;
+        }
+    }
+    found
+}
 
-    File::create(::stamp(&config, testpaths)).unwrap();
+/// Print what running `command` would do for `Config::dry_run`, and pretend
+/// it succeeded without output.
+fn print_dry_run(command: &Command, cmdline: String, input: Option<&str>) -> ProcRes {
+    println!("dry-run: {}", cmdline);
+    if let Some(dir) = command.get_current_dir() {
+        println!("  cwd: {}", dir.display());
+    }
+    for (key, value) in command.get_envs() {
+        match value {
+            Some(value) => println!("  env: {}={}", key.to_string_lossy(), value.to_string_lossy()),
+            None => println!("  env: unset {}", key.to_string_lossy()),
+        }
+    }
+    if let Some(input) = input {
+        println!("  stdin: {} bytes", input.len());
+    }
+    ProcRes {
+        status: success_status(),
+        stdout: String::new(),
+        stderr: String::new(),
+        cmdline,
+    }
+}
+
+#[cfg(unix)]
+fn success_status() -> ExitStatus {
+    use std::os::unix::process::ExitStatusExt;
+    ExitStatus::from_raw(0)
+}
+
+#[cfg(windows)]
+fn success_status() -> ExitStatus {
+    use std::os::windows::process::ExitStatusExt;
+    ExitStatus::from_raw(0)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn success_status() -> ExitStatus {
+    panic!("dry runs aren't supported on this platform")
+}
+
+fn infrastructure_failure(err: &str) -> Box<TestFailure> {
+    println!("\nerror: {}", err);
+    Box::new(TestFailure::new(FailureKind::Infrastructure, err))
+}
+
+/// Validate the headers and annotations of a test and compile it for metadata
+/// only, see `Config::smoke_mode`.
+fn smoke_check(config: &Config, testpaths: &TestPaths) -> TestResult {
+    let start = Instant::now();
+    let base_props = TestProps::from_file(&testpaths.file, None, config);
+    let revisions: Vec<Option<&str>> = if base_props.revisions.is_empty() {
+        vec![None]
+    } else {
+        base_props.revisions.iter().map(|rev| Some(&**rev)).collect()
+    };
+    for revision in revisions {
+        let props = TestProps::from_file(&testpaths.file, revision, config);
+        if props.ignore {
+            continue;
+        }
+        let cx = TestCx { config, props: &props, testpaths, revision };
+        cx.run_smoke_check()?;
+    }
+
+    let full_run = fs::read_to_string(::stamp(config, testpaths)).ok()
+        .and_then(|ms| ms.trim().parse().ok())
+        .map(Duration::from_millis);
+    ::record_smoke_time(start.elapsed(), full_run);
+    Ok(())
+}
+
+/// Compile commands (and source hashes) of the aux crates built by a test
+/// which are identical for all of its revisions, so that later revisions can
+/// skip building them again. Only valid while that test runs, since its aux
+/// directory is shared by all its revisions.
+static SHARED_AUX_BUILDS: Mutex<Option<HashMap<PathBuf, HashSet<String>>>> = Mutex::new(None);
+
+fn forget_shared_aux_builds(testfile: &Path) {
+    let mut built = SHARED_AUX_BUILDS.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(ref mut built) = *built {
+        built.remove(testfile);
+    }
 }
 
 struct TestCx<'test> {
@@ -113,16 +338,35 @@ struct DebuggerCommands {
 
 impl<'test> TestCx<'test> {
     /// invoked once before any revisions have been processed
-    fn init_all(&self) {
+    fn init_all(&self) -> TestResult {
         assert!(self.revision.is_none(), "init_all invoked for a revision");
         if let Incremental = self.config.mode {
-            self.init_incremental_test()
+            self.init_incremental_test()?;
+        } else if self.props.incremental {
+            self.init_incremental_dir();
         }
+        Ok(())
     }
 
     /// Code executed for each revision in turn (or, if there are no
     /// revisions, exactly once, with revision == None).
-    fn run_revision(&self) {
+    fn run_revision(&self) -> TestResult {
+        if self.props.incremental && self.props.incremental_dir.is_none() {
+            // Use the cache created by `init_all`.
+            let mut props = self.props.clone();
+            props.incremental_dir = Some(self.incremental_dir());
+            return TestCx { props: &props, ..*self }.run_revision();
+        }
+        if self.config.dry_run {
+            match self.config.mode {
+                CompileFail | ParseFail | RunFail | RunPass | RunPassValgrind | Pretty |
+                RunMake | Ui => {}
+                mode => {
+                    println!("dry-run: {} tests can't be dry run, skipped", mode);
+                    return Ok(());
+                }
+            }
+        }
         match self.config.mode {
             CompileFail |
             ParseFail => self.run_cfail_test(),
@@ -133,6 +377,7 @@ impl<'test> TestCx<'test> {
             DebugInfoGdb => self.run_debuginfo_gdb_test(),
             DebugInfoLldb => self.run_debuginfo_lldb_test(),
             Codegen => self.run_codegen_test(),
+            Assembly => self.run_assembly_test(),
             Rustdoc => self.run_rustdoc_test(),
             CodegenUnits => self.run_codegen_units_test(),
             Incremental => self.run_incremental_test(),
@@ -142,63 +387,124 @@ impl<'test> TestCx<'test> {
         }
     }
 
+    /// Tag the failure of a `known-bug` test with its bug. Unless
+    /// `Config::fail_on_known_bug_pass` is set, a test whose output no longer
+    /// matches the recorded wrong output passes with a note instead.
+    fn check_known_bug(&self, result: TestResult) -> TestResult {
+        let (failure, bug) = match (result, &self.props.known_bug) {
+            (Err(failure), Some(bug)) => (failure, bug),
+            (result, _) => return result,
+        };
+        if failure.kind == FailureKind::Comparison && !self.config.fail_on_known_bug_pass {
+            println!("note: the output of known bug {} changed, it may have been fixed; \
+                      update the test",
+                     bug);
+            return Ok(());
+        }
+        println!("note: the test documents known bug {}", bug);
+        Err(Box::new(TestFailure { known_bug: Some(bug.clone()), ..*failure }))
+    }
+
     /// Invoked after all revisions have executed.
     fn complete_all(&self) {
         assert!(self.revision.is_none(), "init_all invoked for a revision");
     }
 
-    fn run_cfail_test(&self) {
-        let proc_res = self.compile_test();
+    fn run_cfail_test(&self) -> TestResult {
+        let proc_res = self.compile_test()?;
+        if self.config.dry_run {
+            return Ok(());
+        }
+        if self.props.should_ice {
+            // An ICE which no longer reproduces is the thing to report.
+            self.check_no_compiler_crash(&proc_res)?;
+        }
 
         if self.props.must_compile_successfully {
             if !proc_res.status.success() {
-                self.fatal_proc_rec(
+                return Err(self.fatal_proc_rec(
+                    FailureKind::Compile,
                     "test compilation failed although it shouldn't!",
-                    &proc_res);
+                    &proc_res));
             }
         } else {
             if proc_res.status.success() {
-                self.fatal_proc_rec(
+                return Err(self.fatal_proc_rec(
+                    FailureKind::Compile,
                     &format!("{} test compiled successfully!", self.config.mode)[..],
-                    &proc_res);
+                    &proc_res));
             }
 
-            self.check_correct_failure_status(&proc_res);
+            self.check_correct_failure_status(&proc_res)?;
         }
 
         let output_to_check = self.get_output(&proc_res);
         let expected_errors = errors::load_errors(&self.testpaths.file, self.revision);
         if !expected_errors.is_empty() {
-            if !self.props.error_patterns.is_empty() {
-                self.fatal("both error pattern and expected errors specified");
+            if self.props.has_error_patterns() {
+                return Err(self.fatal("both error pattern and expected errors specified"));
             }
-            self.check_expected_errors(expected_errors, &proc_res);
-        } else {
-            self.check_error_patterns(&output_to_check, &proc_res);
+            match self.props.error_format() {
+                None | Some("json") => {}
+                Some(format) => {
+                    return Err(self.fatal(&format!(
+                        "expected errors are read from `--error-format json` output, but the \
+                         test asks for `--error-format {}`; use error patterns instead",
+                        format)));
+                }
+            }
+            self.check_expected_errors(expected_errors, &proc_res)?;
+        } else if !self.props.should_ice || self.props.has_error_patterns() {
+            self.check_error_patterns(&output_to_check, &proc_res)?;
         }
 
-        self.check_no_compiler_crash(&proc_res);
-        self.check_forbid_output(&output_to_check, &proc_res);
+        if !self.props.should_ice {
+            self.check_no_compiler_crash(&proc_res)?;
+        }
+        self.check_forbid_output(&output_to_check, &proc_res)
     }
 
-    fn run_rfail_test(&self) {
-        let proc_res = self.compile_test();
+    fn run_rfail_test(&self) -> TestResult {
+        let proc_res = self.compile_test()?;
 
         if !proc_res.status.success() {
-            self.fatal_proc_rec("compilation failed!", &proc_res);
+            return Err(self.fatal_proc_rec(FailureKind::Compile, "compilation failed!", &proc_res));
+        }
+
+        if !self.should_run() {
+            return Ok(());
         }
 
-        let proc_res = self.exec_compiled_test();
+        let proc_res = self.exec_compiled_test()?;
+        if self.config.dry_run {
+            return Ok(());
+        }
 
         // The value our Makefile configures valgrind to return on failure
         const VALGRIND_ERR: i32 = 100;
         if proc_res.status.code() == Some(VALGRIND_ERR) {
-            self.fatal_proc_rec("run-fail test isn't valgrind-clean!", &proc_res);
+            return Err(self.fatal_proc_rec(FailureKind::Run,
+                                           "run-fail test isn't valgrind-clean!",
+                                           &proc_res));
         }
 
         let output_to_check = self.get_output(&proc_res);
-        self.check_correct_failure_status(&proc_res);
-        self.check_error_patterns(&output_to_check, &proc_res);
+        self.check_correct_failure_status(&proc_res)?;
+        // The `Error: ` line is all a `// run-fail-result` test has to print.
+        if self.props.run_fail_result && !self.props.has_error_patterns() {
+            return Ok(());
+        }
+        self.check_error_patterns(&output_to_check, &proc_res)
+    }
+
+    /// Whether the compiled test program should be run, see `Config::run`
+    /// and `// no-run`.
+    fn should_run(&self) -> bool {
+        let run = self.config.run != Some(false) && !self.props.no_run;
+        if !run {
+            println!("not running the compiled test program (execution skipped)");
+        }
+        run
     }
 
     fn get_output(&self, proc_res: &ProcRes) -> String {
@@ -209,22 +515,44 @@ impl<'test> TestCx<'test> {
         }
     }
 
-    fn check_correct_failure_status(&self, proc_res: &ProcRes) {
-        // The value the rust runtime returns on failure
-        const RUST_ERR: i32 = 101;
-        if proc_res.status.code() != Some(RUST_ERR) {
-            self.fatal_proc_rec(
-                &format!("failure produced the wrong error: {}",
-                         proc_res.status),
-                proc_res);
+    fn check_correct_failure_status(&self, proc_res: &ProcRes) -> TestResult {
+        // 101 (what the rust runtime returns on a panic), or 1 (what it
+        // returns when `main` returns an error) for `// run-fail-result`
+        // tests, unless the test overrides it with `// failure-status`
+        let default_status = if self.props.run_fail_result { 1 } else { 101 };
+        let expected_status = self.props.failure_status.unwrap_or(default_status);
+        if proc_res.status.code() != Some(expected_status) {
+            return Err(self.fatal_proc_rec(
+                FailureKind::Run,
+                &format!("failure produced the wrong error: expected exit code {}, got {}",
+                         expected_status, proc_res.status),
+                proc_res));
         }
+        if self.props.run_fail_result && proc_res.main_error().is_none() {
+            return Err(self.fatal_proc_rec(
+                FailureKind::Run,
+                "failure produced the wrong error: expected `main` to return an error, \
+                 but stderr has no `Error: ` line",
+                proc_res));
+        }
+        Ok(())
+    }
+
+    /// The failure of a test program which should have exited successfully,
+    /// naming the error if it came from `main` returning `Err`.
+    fn run_failed(&self, proc_res: &ProcRes) -> Box<TestFailure> {
+        let message = match proc_res.main_error() {
+            Some(error) => format!("test run failed! (`main` returned `{}`)", error),
+            None => "test run failed!".to_owned(),
+        };
+        self.fatal_proc_rec(FailureKind::Run, &message, proc_res)
     }
 
-    fn run_rpass_test(&self) {
-        let proc_res = self.compile_test();
+    fn run_rpass_test(&self) -> TestResult {
+        let proc_res = self.compile_test()?;
 
         if !proc_res.status.success() {
-            self.fatal_proc_rec("compilation failed!", &proc_res);
+            return Err(self.fatal_proc_rec(FailureKind::Compile, "compilation failed!", &proc_res));
         }
 
         // FIXME(#41968): Move this check to tidy?
@@ -232,14 +560,19 @@ impl<'test> TestCx<'test> {
         assert!(expected_errors.is_empty(),
                 "run-pass tests with expected warnings should be moved to ui/");
 
-        let proc_res = self.exec_compiled_test();
+        if self.is_check_only() || !self.should_run() {
+            return Ok(());
+        }
+
+        let proc_res = self.exec_compiled_test()?;
 
         if !proc_res.status.success() {
-            self.fatal_proc_rec("test run failed!", &proc_res);
+            return Err(self.run_failed(&proc_res));
         }
+        Ok(())
     }
 
-    fn run_valgrind_test(&self) {
+    fn run_valgrind_test(&self) -> TestResult {
         assert!(self.revision.is_none(), "revisions not relevant here");
 
         if self.config.valgrind_path.is_none() {
@@ -247,29 +580,34 @@ impl<'test> TestCx<'test> {
             return self.run_rpass_test();
         }
 
-        let mut proc_res = self.compile_test();
+        let mut proc_res = self.compile_test()?;
 
         if !proc_res.status.success() {
-            self.fatal_proc_rec("compilation failed!", &proc_res);
+            return Err(self.fatal_proc_rec(FailureKind::Compile, "compilation failed!", &proc_res));
+        }
+
+        if self.is_check_only() || !self.should_run() {
+            return Ok(());
         }
 
         let mut new_config = self.config.clone();
         new_config.runtool = new_config.valgrind_path.clone();
         let new_cx = TestCx { config: &new_config, ..*self };
-        proc_res = new_cx.exec_compiled_test();
+        proc_res = new_cx.exec_compiled_test()?;
 
         if !proc_res.status.success() {
-            self.fatal_proc_rec("test run failed!", &proc_res);
+            return Err(self.run_failed(&proc_res));
         }
+        Ok(())
     }
 
     #[cfg(feature = "stable")]
-    fn run_pretty_test(&self) {
-        self.fatal("pretty-printing tests can only be used with nightly Rust".into());
+    fn run_pretty_test(&self) -> TestResult {
+        Err(self.fatal("pretty-printing tests can only be used with nightly Rust"))
     }
 
     #[cfg(not(feature = "stable"))]
-    fn run_pretty_test(&self) {
+    fn run_pretty_test(&self) -> TestResult {
         if self.props.pp_exact.is_some() {
             logv(self.config, "testing for exact pretty-printing".to_owned());
         } else {
@@ -289,9 +627,11 @@ impl<'test> TestCx<'test> {
             let proc_res = self.print_source(srcs[round].to_owned(), &self.props.pretty_mode);
 
             if !proc_res.status.success() {
-                self.fatal_proc_rec(&format!("pretty-printing failed in round {} revision {:?}",
-                                             round, self.revision),
-                                    &proc_res);
+                return Err(self.fatal_proc_rec(
+                    FailureKind::Compile,
+                    &format!("pretty-printing failed in round {} revision {:?}",
+                             round, self.revision),
+                    &proc_res));
             }
 
             let ProcRes{ stdout, .. } = proc_res;
@@ -317,32 +657,38 @@ impl<'test> TestCx<'test> {
             expected = expected.replace(&cr, "").to_owned();
         }
 
-        self.compare_source(&expected, &actual);
+        self.compare_source(&expected, &actual)?;
 
         // If we're only making sure that the output matches then just stop here
-        if self.props.pretty_compare_only { return; }
+        if self.props.pretty_compare_only { return Ok(()); }
 
         // Finally, let's make sure it actually appears to remain valid code
-        let proc_res = self.typecheck_source(actual);
+        let proc_res = self.typecheck_source(actual)?;
         if !proc_res.status.success() {
-            self.fatal_proc_rec("pretty-printed source does not typecheck", &proc_res);
+            return Err(self.fatal_proc_rec(FailureKind::Compile,
+                                           "pretty-printed source does not typecheck",
+                                           &proc_res));
         }
 
-        if !self.props.pretty_expanded { return }
+        if !self.props.pretty_expanded { return Ok(()) }
 
         // additionally, run `--pretty expanded` and try to build it.
         let proc_res = self.print_source(srcs[round].clone(), "expanded");
         if !proc_res.status.success() {
-            self.fatal_proc_rec("pretty-printing (expanded) failed", &proc_res);
+            return Err(self.fatal_proc_rec(FailureKind::Compile,
+                                           "pretty-printing (expanded) failed",
+                                           &proc_res));
         }
 
         let ProcRes{ stdout: expanded_src, .. } = proc_res;
-        let proc_res = self.typecheck_source(expanded_src);
+        let proc_res = self.typecheck_source(expanded_src)?;
         if !proc_res.status.success() {
-            self.fatal_proc_rec(
+            return Err(self.fatal_proc_rec(
+                FailureKind::Compile,
                 "pretty-printed source (expanded) does not typecheck",
-                &proc_res);
+                &proc_res));
         }
+        Ok(())
     }
 
     fn print_source(&self, src: String, pretty_type: &str) -> ProcRes {
@@ -365,9 +711,10 @@ impl<'test> TestCx<'test> {
 
     fn compare_source(&self,
                       expected: &str,
-                      actual: &str) {
-        if expected != actual {
-            self.error("pretty-printed source does not match expected source");
+                      actual: &str) -> TestResult {
+        if expected != actual && !self.config.dry_run {
+            let err = "pretty-printed source does not match expected source";
+            self.error(err);
             println!("\n\
 expected:\n\
 ------------------------------------------\n\
@@ -379,11 +726,12 @@ actual:\n\
 ------------------------------------------\n\
 \n",
                      expected, actual);
-            panic!();
+            return Err(self.failure(FailureKind::Comparison, err));
         }
+        Ok(())
     }
 
-    fn typecheck_source(&self, src: String) -> ProcRes {
+    fn typecheck_source(&self, src: String) -> TestResult<ProcRes> {
         let mut rustc = Command::new(&self.config.rustc_path);
 
         let out_dir = self.output_base_name().with_extension("pretty-out");
@@ -415,7 +763,7 @@ actual:\n\
         self.compose_and_run_compiler(rustc, Some(src))
     }
 
-    fn run_debuginfo_gdb_test(&self) {
+    fn run_debuginfo_gdb_test(&self) -> TestResult {
         assert!(self.revision.is_none(), "revisions not relevant here");
 
         let config = Config {
@@ -429,10 +777,10 @@ actual:\n\
             ..*self
         };
 
-        test_cx.run_debuginfo_gdb_test_no_opt();
+        test_cx.run_debuginfo_gdb_test_no_opt()
     }
 
-    fn run_debuginfo_gdb_test_no_opt(&self) {
+    fn run_debuginfo_gdb_test_no_opt(&self) -> TestResult {
         let prefixes = if self.config.gdb_native_rust {
             // GDB with Rust
             static PREFIXES: &'static [&'static str] = &["gdb", "gdbr"];
@@ -449,13 +797,13 @@ actual:\n\
             commands,
             check_lines,
             breakpoint_lines
-        } = self.parse_debugger_commands(prefixes);
+        } = self.parse_debugger_commands(prefixes)?;
         let mut cmds = commands.join("\n");
 
         // compile test file (it should have 'compile-flags:-g' in the header)
-        let compiler_run_result = self.compile_test();
+        let compiler_run_result = self.compile_test()?;
         if !compiler_run_result.status.success() {
-            self.fatal_proc_rec("compilation failed!", &compiler_run_result);
+            return Err(self.fatal_proc_rec(FailureKind::Compile, "compilation failed!", &compiler_run_result));
         }
 
         let exe_file = self.make_exe_name();
@@ -470,7 +818,7 @@ actual:\n\
 
                 let tool_path = match self.config.android_cross_path.to_str() {
                     Some(x) => x.to_owned(),
-                    None => self.fatal("cannot find android cross path")
+                    None => return Err(self.fatal("cannot find android cross path")),
                 };
 
                 // write debugger script
@@ -493,7 +841,8 @@ actual:\n\
                 script_str.push_str("\nquit\n");
 
                 debug!("script_str = {}", script_str);
-                self.dump_output_file(&script_str, "debugger.script");
+                let debugger_script =
+                    self.dump_output_file(&script_str, "debugger.script", "debugger script");
 
                 let adb_path = &self.config.adb_path;
 
@@ -540,7 +889,6 @@ actual:\n\
                 }
                 drop(stdout);
 
-                let debugger_script = self.make_out_name("debugger.script");
                 // FIXME (#9639): This needs to handle non-utf8 paths
                 let debugger_opts =
                     vec!["-quiet".to_owned(),
@@ -641,9 +989,8 @@ actual:\n\
                 script_str.push_str("\nquit\n");
 
                 debug!("script_str = {}", script_str);
-                self.dump_output_file(&script_str, "debugger.script");
-
-                let debugger_script = self.make_out_name("debugger.script");
+                let debugger_script =
+                    self.dump_output_file(&script_str, "debugger.script", "debugger script");
 
                 // FIXME (#9639): This needs to handle non-utf8 paths
                 let debugger_opts =
@@ -665,17 +1012,19 @@ actual:\n\
         }
 
         if !debugger_run_result.status.success() {
-            self.fatal_proc_rec("gdb failed to execute", &debugger_run_result);
+            return Err(self.fatal_proc_rec(FailureKind::Run,
+                                           "gdb failed to execute",
+                                           &debugger_run_result));
         }
 
-        self.check_debugger_output(&debugger_run_result, &check_lines);
+        self.check_debugger_output(&debugger_run_result, &check_lines)
     }
 
-    fn run_debuginfo_lldb_test(&self) {
+    fn run_debuginfo_lldb_test(&self) -> TestResult {
         assert!(self.revision.is_none(), "revisions not relevant here");
 
         if self.config.lldb_python_dir.is_none() {
-            self.fatal("Can't run LLDB test because LLDB's python path is not set.");
+            return Err(self.fatal("Can't run LLDB test because LLDB's python path is not set."));
         }
 
         let config = Config {
@@ -690,14 +1039,16 @@ actual:\n\
             ..*self
         };
 
-        test_cx.run_debuginfo_lldb_test_no_opt();
+        test_cx.run_debuginfo_lldb_test_no_opt()
     }
 
-    fn run_debuginfo_lldb_test_no_opt(&self) {
+    fn run_debuginfo_lldb_test_no_opt(&self) -> TestResult {
         // compile test file (it should have 'compile-flags:-g' in the header)
-        let compile_result = self.compile_test();
+        let compile_result = self.compile_test()?;
         if !compile_result.status.success() {
-            self.fatal_proc_rec("compilation failed!", &compile_result);
+            return Err(self.fatal_proc_rec(FailureKind::Compile,
+                                           "compilation failed!",
+                                           &compile_result));
         }
 
         let exe_file = self.make_exe_name();
@@ -719,7 +1070,7 @@ actual:\n\
             check_lines,
             breakpoint_lines,
             ..
-        } = self.parse_debugger_commands(&["lldb"]);
+        } = self.parse_debugger_commands(&["lldb"])?;
 
         // Write debugger script:
         // We don't want to hang when calling `quit` while the process is still running
@@ -764,26 +1115,28 @@ actual:\n\
 
         // Write the script into a file
         debug!("script_str = {}", script_str);
-        self.dump_output_file(&script_str, "debugger.script");
-        let debugger_script = self.make_out_name("debugger.script");
+        let debugger_script =
+            self.dump_output_file(&script_str, "debugger.script", "debugger script");
 
         // Let LLDB execute the script via lldb_batchmode.py
         let debugger_run_result = self.run_lldb(&exe_file,
                                                 &debugger_script,
-                                                &rust_src_root);
+                                                &rust_src_root)?;
 
         if !debugger_run_result.status.success() {
-            self.fatal_proc_rec("Error while running LLDB", &debugger_run_result);
+            return Err(self.fatal_proc_rec(FailureKind::Run,
+                                           "Error while running LLDB",
+                                           &debugger_run_result));
         }
 
-        self.check_debugger_output(&debugger_run_result, &check_lines);
+        self.check_debugger_output(&debugger_run_result, &check_lines)
     }
 
     fn run_lldb(&self,
                 test_executable: &Path,
                 debugger_script: &Path,
                 rust_src_root: &Path)
-                -> ProcRes {
+                -> TestResult<ProcRes> {
         // Prepare the lldb_batchmode which executes the debugger script
         let lldb_script_path = rust_src_root.join("src/etc/lldb_batchmode.py");
         self.cmd2procres(Command::new(&self.config.lldb_python)
@@ -794,7 +1147,7 @@ actual:\n\
                               self.config.lldb_python_dir.as_ref().unwrap()))
     }
 
-    fn cmd2procres(&self, cmd: &mut Command) -> ProcRes {
+    fn cmd2procres(&self, cmd: &mut Command) -> TestResult<ProcRes> {
         let (status, out, err) = match cmd.output() {
             Ok(Output { status, stdout, stderr }) => {
                 (status,
@@ -802,21 +1155,22 @@ actual:\n\
                  String::from_utf8(stderr).unwrap())
             },
             Err(e) => {
-                self.fatal(&format!("Failed to setup Python process for \
-                                      LLDB script: {}", e))
+                return Err(self.fatal(&format!("Failed to setup Python process for \
+                                                 LLDB script: {}", e)))
             }
         };
 
         self.dump_output(&out, &err);
-        ProcRes {
+        Ok(ProcRes {
             status,
             stdout: out,
             stderr: err,
             cmdline: format!("{:?}", cmd)
-        }
+        })
     }
 
-    fn parse_debugger_commands(&self, debugger_prefixes: &[&str]) -> DebuggerCommands {
+    fn parse_debugger_commands(&self, debugger_prefixes: &[&str])
+                               -> TestResult<DebuggerCommands> {
         let directives = debugger_prefixes.iter().map(|prefix| (
             format!("{}-command", prefix),
             format!("{}-check", prefix),
@@ -849,17 +1203,18 @@ actual:\n\
                     }
                 }
                 Err(e) => {
-                    self.fatal(&format!("Error while parsing debugger commands: {}", e))
+                    return Err(self.fatal(&format!("Error while parsing debugger commands: {}",
+                                                   e)))
                 }
             }
             counter += 1;
         }
 
-        DebuggerCommands {
+        Ok(DebuggerCommands {
             commands,
             check_lines,
             breakpoint_lines,
-        }
+        })
     }
 
     fn cleanup_debug_info_options(&self, options: &Option<String>) -> Option<String> {
@@ -878,10 +1233,11 @@ actual:\n\
                                           .filter(|x| !options_to_remove.contains(x))
                                           .collect::<Vec<String>>();
 
-        Some(new_options.join(" "))
+        Some(new_options.iter().map(|arg| util::quote_arg(arg)).collect::<Vec<_>>().join(" "))
     }
 
-    fn check_debugger_output(&self, debugger_run_result: &ProcRes, check_lines: &[String]) {
+    fn check_debugger_output(&self, debugger_run_result: &ProcRes, check_lines: &[String])
+                             -> TestResult {
         let num_check_lines = check_lines.len();
 
         let mut check_line_index = 0;
@@ -895,9 +1251,10 @@ actual:\n\
             }
         }
         if check_line_index != num_check_lines && num_check_lines > 0 {
-            self.fatal_proc_rec(&format!("line not found in debugger output: {}",
-                                         check_lines[check_line_index]),
-                                debugger_run_result);
+            return Err(self.fatal_proc_rec(FailureKind::Comparison,
+                                           &format!("line not found in debugger output: {}",
+                                                    check_lines[check_line_index]),
+                                           debugger_run_result));
         }
 
         fn check_single_line(line: &str, check_line: &str) -> bool {
@@ -939,146 +1296,242 @@ actual:\n\
 
             true
         }
+
+        Ok(())
     }
 
     fn check_error_patterns(&self,
                             output_to_check: &str,
-                            proc_res: &ProcRes) {
-        if self.props.error_patterns.is_empty() {
+                            proc_res: &ProcRes) -> TestResult {
+        if !self.props.has_error_patterns() {
             if self.props.must_compile_successfully {
-                return
+                return Ok(())
             } else {
-                self.fatal(&format!("no error pattern specified in {:?}",
-                                    self.testpaths.file.display()));
-            }
-        }
-        let mut next_err_idx = 0;
-        let mut next_err_pat = self.props.error_patterns[next_err_idx].trim();
-        let mut done = false;
-        for line in output_to_check.lines() {
-            if line.contains(next_err_pat) {
-                debug!("found error pattern {}", next_err_pat);
-                next_err_idx += 1;
-                if next_err_idx == self.props.error_patterns.len() {
-                    debug!("found all error patterns");
-                    done = true;
-                    break;
-                }
-                next_err_pat = self.props.error_patterns[next_err_idx].trim();
+                return Err(self.fatal(&format!("no error pattern specified in {:?}",
+                                               self.testpaths.file.display())));
             }
         }
-        if done { return; }
+        // Literal patterns and regexes are each found in order, independent
+        // of each other.
+        let literal = &self.props.error_patterns;
+        let found = count_found_in_order(output_to_check, literal, |line, pattern| {
+            line.contains(pattern.trim())
+        });
+        let regexes = &self.props.error_pattern_regexes;
+        let found_regexes = count_found_in_order(output_to_check, regexes, |line, regex| {
+            regex.is_match(line)
+        });
+        let missing_patterns = literal[found..].iter()
+            .map(|pattern| format!("error pattern '{}'", pattern))
+            .chain(regexes[found_regexes..].iter()
+                .map(|regex| format!("error pattern regex '{}'", regex.as_str())))
+            .collect::<Vec<_>>();
+        if missing_patterns.is_empty() {
+            debug!("found all error patterns");
+            return Ok(());
+        }
 
-        let missing_patterns = &self.props.error_patterns[next_err_idx..];
         if missing_patterns.len() == 1 {
-            self.fatal_proc_rec(
-                &format!("error pattern '{}' not found!", missing_patterns[0]),
-                proc_res);
+            Err(self.fatal_proc_rec(
+                FailureKind::Comparison,
+                &format!("{} not found!", missing_patterns[0]),
+                proc_res))
         } else {
-            for pattern in missing_patterns {
-                self.error(&format!("error pattern '{}' not found!", *pattern));
+            for pattern in &missing_patterns {
+                self.error(&format!("{} not found!", pattern));
             }
-            self.fatal_proc_rec("multiple error patterns not found", proc_res);
+            Err(self.fatal_proc_rec(FailureKind::Comparison,
+                                    "multiple error patterns not found",
+                                    proc_res))
         }
     }
 
-    fn check_no_compiler_crash(&self, proc_res: &ProcRes) {
-        for line in proc_res.stderr.lines() {
-            if line.contains("error: internal compiler error") {
-                self.fatal_proc_rec("compiler encountered internal error", proc_res);
-            }
+    fn run_smoke_check(&self) -> TestResult {
+        let expected_errors = errors::load_errors(&self.testpaths.file, self.revision);
+
+        if !self.props.aux_builds.is_empty() {
+            create_dir_all(self.aux_output_dir_name()).unwrap();
+        }
+        let out_dir = self.output_base_name().parent().unwrap().to_path_buf();
+        let mut rustc = self.make_compile_args(&self.testpaths.file,
+                                               TargetLocation::ThisDirectory(out_dir));
+        rustc.arg("-L").arg(self.aux_output_dir_name());
+        rustc.arg("--emit=metadata");
+        let incremental_dir = self.config.build_base.join("smoke-incremental");
+        rustc.args(["-Z", &format!("incremental={}", incremental_dir.display())]);
+        let proc_res = self.compose_and_run_compiler(rustc, None)?;
+
+        self.check_no_compiler_crash(&proc_res)?;
+        let expects_errors = expected_errors.iter().any(|e| e.kind == Some(ErrorKind::Error));
+        let must_fail = !self.props.must_compile_successfully &&
+            (self.config.mode == CompileFail || expects_errors);
+        if self.props.must_compile_successfully && !proc_res.status.success() {
+            return Err(self.fatal_proc_rec(
+                FailureKind::Compile,
+                "smoke check: compilation failed although it shouldn't!",
+                &proc_res));
         }
+        if must_fail && proc_res.status.success() {
+            return Err(self.fatal_proc_rec(FailureKind::Compile,
+                                           "smoke check: test compiled successfully!",
+                                           &proc_res));
+        }
+        Ok(())
+    }
+
+    /// Check that the compiler didn't crash, or that it did for `should-ice`
+    /// tests.
+    fn check_no_compiler_crash(&self, proc_res: &ProcRes) -> TestResult {
+        let crashed = proc_res.stderr.lines()
+            .any(|line| line.contains("error: internal compiler error"));
+        if self.props.should_ice && !crashed {
+            return Err(self.fatal_proc_rec(FailureKind::Compile,
+                                           "the internal compiler error expected by \
+                                            `should-ice` no longer reproduces, remove the \
+                                            directive (or the test)",
+                                           proc_res));
+        }
+        if !self.props.should_ice && crashed {
+            return Err(self.fatal_proc_rec(FailureKind::Compile,
+                                           "compiler encountered internal error",
+                                           proc_res));
+        }
+        Ok(())
     }
 
     fn check_forbid_output(&self,
                            output_to_check: &str,
-                           proc_res: &ProcRes) {
+                           proc_res: &ProcRes) -> TestResult {
         for pat in &self.props.forbid_output {
             if output_to_check.contains(pat) {
-                self.fatal_proc_rec("forbidden pattern found in compiler output", proc_res);
+                return Err(self.fatal_proc_rec(FailureKind::Comparison,
+                                               "forbidden pattern found in compiler output",
+                                               proc_res));
+            }
+        }
+        for regex in &self.props.forbid_output_regexes {
+            let found = output_to_check.lines().enumerate().find(|&(_, line)| {
+                regex.is_match(line)
+            });
+            if let Some((line_num, line)) = found {
+                return Err(self.fatal_proc_rec(
+                    FailureKind::Comparison,
+                    &format!("forbidden pattern regex '{}' found in compiler output, \
+                              on line {}: {}",
+                             regex.as_str(), line_num + 1, line),
+                    proc_res));
             }
         }
+        Ok(())
     }
 
     fn check_expected_errors(&self,
                              expected_errors: Vec<errors::Error>,
-                             proc_res: &ProcRes) {
+                             proc_res: &ProcRes) -> TestResult {
         if proc_res.status.success() &&
             expected_errors.iter().any(|x| x.kind == Some(ErrorKind::Error)) {
-            self.fatal_proc_rec("process did not return an error status", proc_res);
+            return Err(self.fatal_proc_rec(FailureKind::Compile,
+                                           "process did not return an error status",
+                                           proc_res));
         }
 
         let file_name =
             format!("{}", self.testpaths.file.display())
             .replace(r"\", "/"); // on windows, translate all '\' path separators to '/'
 
-        // If the testcase being checked contains at least one expected "help"
-        // message, then we'll ensure that all "help" messages are expected.
-        // Otherwise, all "help" messages reported by the compiler will be ignored.
-        // This logic also applies to "note" messages.
-        let expect_help = expected_errors.iter().any(|ee| ee.kind == Some(ErrorKind::Help));
-        let expect_note = expected_errors.iter().any(|ee| ee.kind == Some(ErrorKind::Note));
+        let location = |line_num: usize| if line_num == 0 {
+            file_name.clone()
+        } else {
+            format!("{}:{}", file_name, line_num)
+        };
+        let kind_str = |kind: &Option<ErrorKind>| {
+            kind.as_ref().map_or(String::from("message"), |k| k.to_string())
+        };
+        // The kind and error code, like `error[E0308]`
+        let kind_and_code = |error: &Error| match error.code {
+            Some(ref code) => format!("{}[{}]", kind_str(&error.kind), code),
+            None => kind_str(&error.kind),
+        };
 
         // Parse the JSON output from the compiler and extract out the messages.
-        let actual_errors = json::parse_output(&file_name, &proc_res.stderr, proc_res);
-        let mut unexpected = Vec::new();
-        let mut found = vec![false; expected_errors.len()];
-        for actual_error in &actual_errors {
-            let opt_index =
-                expected_errors
-                .iter()
-                .enumerate()
-                .position(|(index, expected_error)| {
-                    !found[index] &&
-                        actual_error.line_num == expected_error.line_num &&
-                        (expected_error.kind.is_none() ||
-                         actual_error.kind == expected_error.kind) &&
-                        actual_error.msg.contains(&expected_error.msg)
-                });
-
-            match opt_index {
-                Some(index) => {
-                    // found a match, everybody is happy
-                    assert!(!found[index]);
-                    found[index] = true;
-                }
-
-                None => {
-                    if self.is_unexpected_compiler_message(actual_error, expect_help, expect_note) {
-                        self.error(
-                            &format!("{}:{}: unexpected {}: '{}'",
-                                     file_name,
-                                     actual_error.line_num,
-                                     actual_error.kind.as_ref()
-                                     .map_or(String::from("message"),
-                                             |k| k.to_string()),
-                                     actual_error.msg));
-                        unexpected.push(actual_error);
-                    }
-                }
+        let actual_errors = match json::parse_output(&file_name, &proc_res.stderr) {
+            Ok(errors) => errors,
+            Err(e) => {
+                return Err(proc_res.failure(FailureKind::Compile,
+                                            &format!("{}\noutput: {}", e, proc_res.stderr)));
             }
+        };
+        let actual_errors = if self.props.dedup_diagnostics {
+            let (actual_errors, removed) = errors::dedup_errors(actual_errors);
+            logv(self.config, format!("collapsed {} duplicate diagnostics", removed));
+            actual_errors
+        } else {
+            actual_errors
+        };
+        let MatchResult {
+            unexpected, not_found, wrong_span_count, wrong_column, wrong_count, ..
+        } = errors::match_errors(&expected_errors, &actual_errors);
+        for actual_error in &unexpected {
+            self.error(&format!("{}: unexpected {}: '{}'",
+                                location(actual_error.line_num),
+                                kind_and_code(actual_error),
+                                actual_error.msg));
+        }
+        for expected_error in &not_found {
+            self.error(&format!("{}: expected {} not found: {}",
+                                location(expected_error.line_num),
+                                kind_and_code(expected_error),
+                                expected_error.msg));
+        }
+        for &(expected_error, actual_error) in &wrong_span_count {
+            let lines = actual_error.span_lines.iter()
+                .map(|line| line.to_string())
+                .collect::<Vec<_>>();
+            self.error(&format!("{}: expected {} with {} spans, found {} (on lines {}): {}",
+                                location(expected_error.line_num),
+                                kind_str(&expected_error.kind),
+                                expected_error.span_count.unwrap(),
+                                lines.len(),
+                                lines.join(", "),
+                                expected_error.msg));
+        }
+        for &(expected_error, actual_error) in &wrong_column {
+            let found = actual_error.column.map_or("no column".to_owned(), |column| {
+                format!("{}:{}", actual_error.line_num, column)
+            });
+            self.error(&format!("{}: {} found at {}, expected {}:{}: {}",
+                                location(expected_error.line_num),
+                                kind_str(&expected_error.kind),
+                                found,
+                                expected_error.line_num,
+                                expected_error.column.unwrap(),
+                                expected_error.msg));
         }
 
-        let mut not_found = Vec::new();
-        // anything not yet found is a problem
-        for (index, expected_error) in expected_errors.iter().enumerate() {
-            if !found[index] {
-                self.error(
-                    &format!("{}:{}: expected {} not found: {}",
-                             file_name,
-                             expected_error.line_num,
-                             expected_error.kind.as_ref()
-                             .map_or("message".into(),
-                                     |k| k.to_string()),
-                             expected_error.msg));
-                not_found.push(expected_error);
-            }
+        for &(expected_error, found) in &wrong_count {
+            self.error(&format!("{}: expected {} {} times, found {}: {}",
+                                location(expected_error.line_num),
+                                kind_and_code(expected_error),
+                                expected_error.count,
+                                found,
+                                expected_error.msg));
         }
 
-        if !unexpected.is_empty() || !not_found.is_empty() {
-            self.error(
-                &format!("{} unexpected errors found, {} expected errors not found",
-                         unexpected.len(), not_found.len()));
+        if !unexpected.is_empty() || !not_found.is_empty() || !wrong_span_count.is_empty() ||
+           !wrong_column.is_empty() || !wrong_count.is_empty() {
+            let mut err = format!("{} unexpected errors found, {} expected errors not found",
+                                  unexpected.len(), not_found.len());
+            if !wrong_span_count.is_empty() {
+                err.push_str(&format!(", {} errors with the wrong number of spans",
+                                      wrong_span_count.len()));
+            }
+            if !wrong_column.is_empty() {
+                err.push_str(&format!(", {} errors at the wrong column", wrong_column.len()));
+            }
+            if !wrong_count.is_empty() {
+                err.push_str(&format!(", {} errors found too few times", wrong_count.len()));
+            }
+            self.error(&err);
             println!("status: {}\ncommand: {}",
                    proc_res.status, proc_res.cmdline);
             if !unexpected.is_empty() {
@@ -1087,30 +1540,79 @@ actual:\n\
             if !not_found.is_empty() {
                 println!("not found errors (from test file): {:#?}\n", not_found);
             }
-            panic!();
+            self.explain_mismatches(&file_name, &actual_errors, &unexpected, &not_found);
+            let mut failure = self.failure(FailureKind::Comparison, &err);
+            failure.proc_res = Some(Box::new(proc_res.clone()));
+            return Err(failure);
         }
+        Ok(())
     }
 
-    /// Returns true if we should report an error about `actual_error`,
-    /// which did not match any of the expected error. We always require
-    /// errors/warnings to be explicitly listed, but only require
-    /// helps/notes if there are explicit helps/notes given.
-    fn is_unexpected_compiler_message(&self,
-                                      actual_error: &Error,
-                                      expect_help: bool,
-                                      expect_note: bool)
-                                      -> bool {
-        match actual_error.kind {
-            Some(ErrorKind::Help) => expect_help,
-            Some(ErrorKind::Note) => expect_note,
-            Some(ErrorKind::Error) |
-            Some(ErrorKind::Warning) => true,
-            Some(ErrorKind::Suggestion) |
-            None => false
+    /// Helps with updating annotations after `check_expected_errors` failed:
+    /// shows the actual diagnostics closest to each expected error that was
+    /// not found, and the annotations which almost matched an unexpected
+    /// error, along with the part that did not match.
+    fn explain_mismatches(&self,
+                          file_name: &str,
+                          actual_errors: &[Error],
+                          unexpected: &[&Error],
+                          not_found: &[&Error]) {
+        fn kind_str(kind: &Option<ErrorKind>) -> String {
+            kind.as_ref().map_or("message".into(), |k| k.to_string())
+        }
+
+        for expected_error in not_found {
+            let mut candidates: Vec<(f64, &Error)> =
+                actual_errors.iter()
+                             .map(|actual| (message_similarity(&expected_error.msg, &actual.msg),
+                                            actual))
+                             .filter(|&(score, _)| score > 0.0)
+                             .collect();
+            if candidates.is_empty() {
+                continue;
+            }
+            candidates.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
+
+            println!("closest diagnostics to expected {} on line {} ('{}'):",
+                     kind_str(&expected_error.kind), expected_error.line_num, expected_error.msg);
+            for &(_, actual) in candidates.iter().take(3) {
+                println!("    {}:{}: {}: '{}'",
+                         file_name, actual.line_num, kind_str(&actual.kind), actual.msg);
+            }
+            println!();
+        }
+
+        for actual_error in unexpected {
+            let same_line = not_found.iter().filter(|e| e.line_num == actual_error.line_num);
+            for expected_error in same_line {
+                let kind_matches = expected_error.kind.is_none() ||
+                                   actual_error.kind == expected_error.kind;
+                let msg_matches = actual_error.msg.contains(&expected_error.msg);
+                if kind_matches == msg_matches {
+                    continue;
+                }
+                let mismatch = if kind_matches {
+                    "the message is not contained in the actual message".to_owned()
+                } else {
+                    format!("the kind differs: expected {}, found {}",
+                            kind_str(&expected_error.kind), kind_str(&actual_error.kind))
+                };
+                println!("{}:{}: annotation '{}' almost matches unexpected {} '{}': {}",
+                         file_name, actual_error.line_num, expected_error.msg,
+                         kind_str(&actual_error.kind), actual_error.msg, mismatch);
+            }
         }
     }
 
-    fn compile_test(&self) -> ProcRes {
+    fn compile_test(&self) -> TestResult<ProcRes> {
+        self.compile_test_with_args(&[])
+    }
+
+    fn compile_test_with_args(&self, extra_args: &[&str]) -> TestResult<ProcRes> {
+        if self.is_check_only() {
+            return self.check_test();
+        }
+
         let mut rustc = self.make_compile_args(
             &self.testpaths.file, TargetLocation::ThisFile(self.make_exe_name()));
 
@@ -1123,18 +1625,69 @@ actual:\n\
                 // want to actually assert warnings about all this code. Instead
                 // let's just ignore unused code warnings by defaults and tests
                 // can turn it back on if needed.
-                rustc.args(&["-A", "unused"]);
+                if !self.props.sets_unused_lint_level() {
+                    rustc.args(["-A", "unused"]);
+                } else if self.config.verbose {
+                    println!("not passing `-A unused`, the test sets the level of unused lints");
+                }
             }
             _ => {}
         }
 
+        rustc.args(extra_args);
+
+        // Record which files the test depends on, unless that would change
+        // what the compiler emits.
+        if self.config.skip_up_to_date &&
+            !extra_args.iter().cloned().chain(self.props.compile_flags.iter().map(|s| &**s))
+                .any(|arg| arg.starts_with("--emit")) {
+            rustc.arg(format!("--emit=link,dep-info={}", self.dep_info_path().display()));
+        }
+
         self.compose_and_run_compiler(rustc, None)
     }
 
-    fn document(&self, out_dir: &Path) -> ProcRes {
+    /// Where the compiler writes dep-info for `Config::skip_up_to_date`.
+    fn dep_info_path(&self) -> PathBuf {
+        let mut path = self.output_base_name().into_os_string();
+        if let Some(revision) = self.revision {
+            path.push(format!(".{}", revision));
+        }
+        path.push(".d");
+        PathBuf::from(path)
+    }
+
+    /// Whether `force_check_only` applies to this test, i.e. it is only
+    /// type checked and not built or run.
+    fn is_check_only(&self) -> bool {
+        self.config.force_check_only && matches!(self.config.mode, RunPass | RunPassValgrind)
+    }
+
+    /// Compile the test for metadata only, falling back to `-Zno-trans` on
+    /// compilers which can't emit metadata.
+    fn check_test(&self) -> TestResult<ProcRes> {
+        let check = |check_arg: &str| {
+            // No binary is produced, so only tell rustc where to put the
+            // metadata.
+            let out_dir = self.output_base_name().parent().unwrap().to_path_buf();
+            let mut rustc = self.make_compile_args(
+                &self.testpaths.file, TargetLocation::ThisDirectory(out_dir));
+            rustc.arg("-L").arg(self.aux_output_dir_name());
+            rustc.arg(check_arg);
+            self.compose_and_run_compiler(rustc, None)
+        };
+
+        let proc_res = check("--emit=metadata")?;
+        if !proc_res.status.success() && proc_res.stderr.contains("unknown emission type") {
+            return check("-Zno-trans");
+        }
+        Ok(proc_res)
+    }
+
+    fn document(&self, out_dir: &Path) -> TestResult<ProcRes> {
         if self.props.build_aux_docs {
             for rel_ab in &self.props.aux_builds {
-                let aux_testpaths = self.compute_aux_test_paths(rel_ab);
+                let aux_testpaths = self.compute_aux_test_paths(rel_ab)?;
                 let aux_props = self.props.from_aux_file(&aux_testpaths.file,
                                                          self.revision,
                                                          self.config);
@@ -1144,9 +1697,9 @@ actual:\n\
                     testpaths: &aux_testpaths,
                     revision: self.revision
                 };
-                let auxres = aux_cx.document(out_dir);
+                let auxres = aux_cx.document(out_dir)?;
                 if !auxres.status.success() {
-                    return auxres;
+                    return Ok(auxres);
                 }
             }
         }
@@ -1167,8 +1720,8 @@ actual:\n\
         self.compose_and_run_compiler(rustdoc, None)
     }
 
-    fn exec_compiled_test(&self) -> ProcRes {
-        let env = &self.props.exec_env;
+    fn exec_compiled_test(&self) -> TestResult<ProcRes> {
+        let env = &self.exec_env();
 
         match &*self.config.target {
             // This is pretty similar to below, we're transforming:
@@ -1186,7 +1739,7 @@ actual:\n\
             // the process) and then report back the same result.
             _ if self.config.remote_test_client.is_some() => {
                 let aux_dir = self.aux_output_dir_name();
-                let ProcArgs { mut prog, args } = self.make_run_args();
+                let ProcArgs { mut prog, args } = self.make_run_args()?;
                 if let Ok(entries) = aux_dir.read_dir() {
                     for entry in entries {
                         let entry = entry.unwrap();
@@ -1199,84 +1752,277 @@ actual:\n\
                 }
                 let mut test_client = Command::new(
                     self.config.remote_test_client.as_ref().unwrap());
+                for var in &self.props.unset_exec_env {
+                    test_client.env_remove(var);
+                }
                 test_client
                     .args(&["run", &prog])
                     .args(args)
                     .envs(env.clone());
-                self.compose_and_run(test_client,
-                                     self.config.run_lib_path.to_str().unwrap(),
-                                     Some(aux_dir.to_str().unwrap()),
-                                     None)
+                let proc_res = self.compose_and_run(test_client,
+                                                    self.config.run_lib_path.to_str().unwrap(),
+                                                    Some(aux_dir.to_str().unwrap()),
+                                                    None);
+                // If the client itself failed we don't know anything about
+                // the test program, so don't let e.g. a run-fail test pass.
+                if let Some(err) = remote_test_client_error(&proc_res) {
+                    let mut failure = self.fatal_proc_rec(
+                        FailureKind::Infrastructure,
+                        &format!("remote-test-client failed, the test was not run: {}", err.line),
+                        &proc_res);
+                    failure.retryable = err.retryable;
+                    return Err(failure);
+                }
+                Ok(proc_res)
             }
+            target if target.contains("android") => self.exec_compiled_test_adb(),
             _ => {
                 let aux_dir = self.aux_output_dir_name();
-                let ProcArgs { prog, args } = self.make_run_args();
+                let ProcArgs { prog, args } = self.make_run_args()?;
                 let mut program = Command::new(&prog);
+                for var in &self.props.unset_exec_env {
+                    program.env_remove(var);
+                }
                 program.args(args)
                     .current_dir(&self.output_base_name().parent().unwrap())
                     .envs(env.clone());
-                self.compose_and_run(program,
-                                     self.config.run_lib_path.to_str().unwrap(),
-                                     Some(aux_dir.to_str().unwrap()),
-                                     None)
+                Ok(self.compose_and_run_isolated(program,
+                                                 self.config.run_lib_path.to_str().unwrap(),
+                                                 &[aux_dir.to_str().unwrap()],
+                                                 None,
+                                                 self.config.isolate_process_group))
+            }
+        }
+    }
+
+    /// The environment for running the test program: `exec-env` plus
+    /// defaults for the sanitizer runtimes, if the test uses a sanitizer.
+    fn exec_env(&self) -> Vec<(String, String)> {
+        if !self.uses_sanitizer() {
+            return self.props.exec_env.clone();
+        }
+        let symbolizer = match self.config.llvm_symbolizer {
+            Some(ref path) => format!(":external_symbolizer_path={}", path.display()),
+            None => String::new(),
+        };
+        let defaults = [
+            ("ASAN_OPTIONS", format!("detect_leaks=1:symbolize=1{}", symbolizer)),
+            ("LSAN_OPTIONS", format!("symbolize=1{}", symbolizer)),
+        ];
+        // Settings from the environment or the test take precedence.
+        let env = defaults.iter()
+            .filter(|&&(key, _)| env::var_os(key).is_none())
+            .map(|&(key, ref value)| (key.to_owned(), value.clone()))
+            .chain(self.props.exec_env.iter().cloned())
+            .collect();
+        merge_env(self.config, env)
+    }
+
+    fn uses_sanitizer(&self) -> bool {
+        let target_rustcflags = self.config.target_rustcflags.as_ref().map_or("", |s| &**s);
+        self.props.compile_flags.iter().map(|s| &**s)
+            .chain(Some(target_rustcflags))
+            .any(|flags| flags.contains("sanitizer="))
+    }
+
+    /// Run the test program on an Android device: push it and the aux
+    /// libraries to `adb_test_dir`, run it there and collect its output and
+    /// exit status.
+    fn exec_compiled_test_adb(&self) -> TestResult<ProcRes> {
+        const STATUS_MARKER: &str = "compiletest-exit-status:";
+
+        let test_dir = &self.config.adb_test_dir;
+        let exe_file = self.make_exe_name();
+        let exe_name = exe_file.file_name().unwrap().to_str().unwrap().to_owned();
+
+        let mut files = vec![exe_file.clone()];
+        if let Ok(entries) = self.aux_output_dir_name().read_dir() {
+            for entry in entries {
+                let path = entry.unwrap().path();
+                if path.is_file() {
+                    files.push(path);
+                }
+            }
+        }
+        for file in &files {
+            let proc_res = self.adb(&["push", file.to_str().unwrap(), test_dir])?;
+            if !proc_res.status.success() {
+                return Err(self.fatal_proc_rec(
+                    FailureKind::Infrastructure,
+                    &format!("failed to push `{}` to the device", file.display()),
+                    &proc_res));
             }
         }
+
+        // `adb shell` doesn't report the exit status of the command (on older
+        // devices) and mixes stderr into stdout, so the status is printed
+        // after the output and stderr goes to a file which is read back.
+        let mut shell_cmd = format!("cd {};", test_dir);
+        for (key, value) in &self.exec_env() {
+            shell_cmd.push_str(&format!(" export {}='{}';", key, value.replace("'", "'\\''")));
+        }
+        let stderr_file = format!("{}/{}.stderr", test_dir, exe_name);
+        shell_cmd.push_str(&format!(" LD_LIBRARY_PATH={} ./{}", test_dir, exe_name));
+        for arg in &self.props.run_flags {
+            shell_cmd.push_str(&format!(" '{}'", arg.replace("'", "'\\''")));
+        }
+        shell_cmd.push_str(&format!(" 2>{}; echo {}$?", stderr_file, STATUS_MARKER));
+
+        let run_res = self.adb(&["shell", &shell_cmd])?;
+        let run_stdout = run_res.stdout.replace("\r\n", "\n");
+        let status_line = run_stdout.rfind(STATUS_MARKER).map(|i| (i, &run_stdout[i..]));
+        let parsed = status_line.and_then(|(i, line)| {
+            line[STATUS_MARKER.len()..].trim().parse::<i32>().ok()
+                .map(|code| (run_stdout[..i].to_owned(), code))
+        });
+        let (stdout, code) = match parsed {
+            Some(parsed) => parsed,
+            None => {
+                return Err(self.fatal_proc_rec(FailureKind::Infrastructure,
+                                               "failed to run the test on the device",
+                                               &run_res));
+            }
+        };
+        let stderr = self.adb(&["shell", "cat", &stderr_file])?.stdout.replace("\r\n", "\n");
+
+        Ok(ProcRes {
+            status: exit_status_from_code(code),
+            stdout,
+            stderr,
+            cmdline: run_res.cmdline,
+        })
+    }
+
+    fn adb(&self, args: &[&str]) -> TestResult<ProcRes> {
+        let mut adb = Command::new(&self.config.adb_path);
+        adb.args(args);
+        let cmdline = self.make_cmdline(&adb, "");
+        logv(self.config, format!("executing {}", cmdline));
+        let Output { status, stdout, stderr } = adb.output().map_err(|e| {
+            self.fatal(&format!("failed to exec `{}`: {}", self.config.adb_path, e))
+        })?;
+        Ok(ProcRes {
+            status,
+            stdout: String::from_utf8_lossy(&stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&stderr).into_owned(),
+            cmdline,
+        })
     }
 
     /// For each `aux-build: foo/bar` annotation, we check to find the
     /// file in a `aux` directory relative to the test itself.
-    fn compute_aux_test_paths(&self, rel_ab: &str) -> TestPaths {
-        let test_ab = self.testpaths.file
-                                    .parent()
-                                    .expect("test file path has no parent")
-                                    .join("auxiliary")
-                                    .join(rel_ab);
-        if !test_ab.exists() {
-            self.fatal(&format!("aux-build `{}` source not found", test_ab.display()))
+    fn compute_aux_test_paths(&self, rel_ab: &str) -> TestResult<TestPaths> {
+        let test_ab = match self.config.aux_file(&self.testpaths.file, rel_ab) {
+            Some(test_ab) => test_ab,
+            None => {
+                let local = self.testpaths.file
+                                          .parent()
+                                          .expect("test file path has no parent")
+                                          .join("auxiliary")
+                                          .join(rel_ab);
+                let mut msg = format!("aux-build `{}` source not found", local.display());
+                if let Some(ref aux_base) = self.config.aux_base {
+                    msg.push_str(&format!(" (nor in `{}`)", aux_base.display()));
+                }
+                return Err(self.fatal(&msg));
+            }
+        };
+
+        // Leave out the `..` of paths like `../shared/helper.rs`, so that
+        // the outputs of the aux build stay inside the build directory.
+        let rel_dir = Path::new(rel_ab).parent().expect("aux-build path has no parent");
+        let mut relative_dir = self.testpaths.relative_dir.join("auxiliary");
+        for component in rel_dir.components() {
+            if let Component::Normal(name) = component {
+                relative_dir.push(name);
+            }
         }
 
-        TestPaths {
+        Ok(TestPaths {
             file: test_ab,
             base: self.testpaths.base.clone(),
-            relative_dir: self.testpaths.relative_dir
-                                        .join("auxiliary")
-                                        .join(rel_ab)
-                                        .parent()
-                                        .expect("aux-build path has no parent")
-                                        .to_path_buf()
-        }
+            relative_dir,
+        })
     }
 
-    fn compose_and_run_compiler(&self, mut rustc: Command, input: Option<String>) -> ProcRes {
-        if !self.props.aux_builds.is_empty() {
+    fn compose_and_run_compiler(&self, mut rustc: Command, input: Option<String>)
+                                -> TestResult<ProcRes> {
+        if !self.props.aux_builds.is_empty() || !self.props.aux_crates.is_empty() {
             create_dir_all(&self.aux_output_dir_name()).unwrap();
         }
 
         let aux_dir = self.aux_output_dir_name();
 
         for rel_ab in &self.props.aux_builds {
-            let aux_testpaths = self.compute_aux_test_paths(rel_ab);
-            let aux_props = self.props.from_aux_file(&aux_testpaths.file,
-                                                     self.revision,
-                                                     self.config);
-            let aux_output = {
-                let f = self.make_lib_name(&self.testpaths.file);
-                let parent = f.parent().unwrap();
-                TargetLocation::ThisDirectory(parent.to_path_buf())
-            };
-            let aux_cx = TestCx {
-                config: self.config,
-                props: &aux_props,
-                testpaths: &aux_testpaths,
-                revision: self.revision
-            };
-            let mut aux_rustc = aux_cx.make_compile_args(&aux_testpaths.file, aux_output);
+            // Proc macros have to be built for the host and passed with
+            // `--extern`, just like `// proc-macro` crates.
+            let aux_file = self.compute_aux_test_paths(rel_ab)?.file;
+            if is_proc_macro_source(&read_to_string(&aux_file)) {
+                let (crate_name, artifact) = self.build_proc_macro(rel_ab)?;
+                rustc.arg("--extern").arg(format!("{}={}", crate_name, artifact.display()));
+            } else {
+                self.build_auxiliary(rel_ab, &aux_dir, false)?;
+            }
+        }
+        for (name, rel_ac) in &self.props.aux_crates {
+            let artifact = self.build_auxiliary(rel_ac, &aux_dir, true)?.unwrap();
+            rustc.arg("--extern").arg(format!("{}={}", name, artifact.display()));
+        }
+
+        for rel_pm in &self.props.proc_macros {
+            let (crate_name, artifact) = self.build_proc_macro(rel_pm)?;
+            rustc.arg("--extern").arg(format!("{}={}", crate_name, artifact.display()));
+        }
+
+        for var in &self.props.unset_rustc_env {
+            rustc.env_remove(var);
+        }
+        rustc.envs(self.props.rustc_env.clone());
+        let mut dylib_dirs = vec![aux_dir.to_str().unwrap()];
+        // The compiler has to find what the codegen backend links to.
+        let backend_dir = self.codegen_backend().and_then(|backend| backend.parent());
+        if let Some(dir) = backend_dir {
+            dylib_dirs.insert(0, dir.to_str().unwrap());
+        }
+        Ok(self.compose_and_run_isolated(rustc,
+                                         self.config.compile_lib_path.to_str().unwrap(),
+                                         &dylib_dirs,
+                                         input,
+                                         false))
+    }
+
+    /// Build the auxiliary crate `rel_ab` into `aux_dir`. If `report_artifact`
+    /// is true, returns the path of the library rustc produced.
+    fn build_auxiliary(&self, rel_ab: &str, aux_dir: &Path, report_artifact: bool)
+                       -> TestResult<Option<PathBuf>> {
+        let aux_testpaths = self.compute_aux_test_paths(rel_ab)?;
+        let aux_props = self.props.from_aux_file(&aux_testpaths.file,
+                                                 self.revision,
+                                                 self.config);
+        let aux_output = {
+            let f = self.make_lib_name(&self.testpaths.file);
+            f.parent().unwrap().to_path_buf()
+        };
+        // If the aux crate can't tell the revisions apart, build it
+        // without the revision's `--cfg` so that all revisions can share
+        // the same build.
+        let aux_source = read_to_string(&aux_testpaths.file);
+        let shared = self.revision.is_some() &&
+            !self.props.revisions.iter().any(|rev| mentions_word(&aux_source, rev));
+        let aux_cx = TestCx {
+            config: self.config,
+            props: &aux_props,
+            testpaths: &aux_testpaths,
+            revision: if shared { None } else { self.revision }
+        };
+        let make_aux_rustc = || {
+            let mut aux_rustc = aux_cx.make_compile_args(&aux_testpaths.file,
+                                                         TargetLocation::ThisDirectory(
+                                                             aux_output.clone()));
 
             let crate_type = if aux_props.no_prefer_dynamic {
                 None
-            } else if (self.config.target.contains("musl") && !aux_props.force_host) ||
-                      self.config.target.contains("wasm32") ||
-                      self.config.target.contains("emscripten") {
+            } else if !util::target_supports_dylibs(aux_cx.crate_target()) {
                 // We primarily compile all auxiliary libraries as dynamic libraries
                 // to avoid code size bloat and large binaries as much as possible
                 // for the test suite (otherwise including libstd statically in all
@@ -1284,8 +2030,8 @@ actual:\n\
                 //
                 // For targets like MUSL or Emscripten, however, there is no support for
                 // dynamic libraries so we just go back to building a normal library. Note,
-                // however, that for MUSL if the library is built with `force_host` then
-                // it's ok to be a dylib as the host should always support dylibs.
+                // however, that if the library is built with `force_host` then it's ok
+                // to be a dylib as the host should always support dylibs.
                 Some("lib")
             } else {
                 Some("dylib")
@@ -1296,31 +2042,195 @@ actual:\n\
             }
 
             aux_rustc.arg("-L").arg(&aux_dir);
+            for var in self.props.unset_rustc_env.iter().chain(&aux_props.unset_rustc_env) {
+                aux_rustc.env_remove(var);
+            }
+            aux_rustc
+        };
 
-            let auxres = aux_cx.compose_and_run(aux_rustc,
-                                                aux_cx.config.compile_lib_path.to_str().unwrap(),
-                                                Some(aux_dir.to_str().unwrap()),
-                                                None);
-            if !auxres.status.success() {
-                self.fatal_proc_rec(
-                    &format!("auxiliary build of {:?} failed to compile: ",
-                             aux_testpaths.file.display()),
-                    &auxres);
+        // The file rustc produces, which depends on the crate type and target
+        let artifact = if report_artifact {
+            let mut print_rustc = make_aux_rustc();
+            print_rustc.args(["--print", "file-names"]);
+            let print_res = aux_cx.compose_and_run(print_rustc,
+                                                   aux_cx.config.compile_lib_path.to_str().unwrap(),
+                                                   Some(aux_dir.to_str().unwrap()),
+                                                   None);
+            match print_res.stdout.lines().next() {
+                Some(file_name) if print_res.status.success() => {
+                    Some(aux_output.join(file_name.trim()))
+                }
+                _ => {
+                    return Err(self.fatal_proc_rec(
+                        FailureKind::Compile,
+                        &format!("failed to get the file name of auxiliary crate {:?}",
+                                 aux_testpaths.file.display()),
+                        &print_res));
+                }
+            }
+        } else {
+            None
+        };
+        let aux_rustc = make_aux_rustc();
+
+        let shared_key = if shared {
+            let mut hasher = DefaultHasher::new();
+            aux_source.hash(&mut hasher);
+            Some(format!("{:?} {:016x}", aux_rustc, hasher.finish()))
+        } else {
+            None
+        };
+        if let Some(ref key) = shared_key {
+            let built = SHARED_AUX_BUILDS.lock().unwrap_or_else(|e| e.into_inner());
+            let built = built.as_ref().and_then(|b| b.get(&self.testpaths.file));
+            if built.map_or(false, |b| b.contains(key)) {
+                logv(self.config, format!("reusing aux build of {} from an earlier revision",
+                                          aux_testpaths.file.display()));
+                return Ok(artifact);
             }
         }
 
-        rustc.envs(self.props.rustc_env.clone());
-        self.compose_and_run(rustc,
-                             self.config.compile_lib_path.to_str().unwrap(),
-                             Some(aux_dir.to_str().unwrap()),
-                             input)
+        let auxres = aux_cx.compose_and_run(aux_rustc,
+                                            aux_cx.config.compile_lib_path.to_str().unwrap(),
+                                            Some(aux_dir.to_str().unwrap()),
+                                            None);
+        self.dump_aux_output(&aux_testpaths.file, &auxres);
+        if !auxres.status.success() {
+            return Err(self.fatal_proc_rec(
+                FailureKind::Compile,
+                &format!("auxiliary build of {:?} failed to compile: ",
+                         aux_testpaths.file.display()),
+                &auxres));
+        }
+        if let Some(key) = shared_key {
+            let mut built = SHARED_AUX_BUILDS.lock().unwrap_or_else(|e| e.into_inner());
+            built.get_or_insert_with(HashMap::new)
+                 .entry(self.testpaths.file.clone())
+                 .or_default()
+                 .insert(key);
+        }
+        Ok(artifact)
+    }
+
+    /// The custom codegen backend to compile this crate with, if any.
+    fn codegen_backend(&self) -> Option<&Path> {
+        match self.config.codegen_backend {
+            Some(ref backend) if self.props.custom_codegen_backend => Some(backend),
+            _ => None,
+        }
+    }
+
+    /// Build a `// proc-macro: foo.rs` crate (or an `// aux-build` which is a
+    /// proc macro) for the host, returning its crate name and the path of the
+    /// built library.
+    ///
+    /// Proc macros don't depend on the target, so each one is only built once
+    /// per source and host compile command, no matter how many tests (or
+    /// targets) use it.
+    fn build_proc_macro(&self, rel_pm: &str) -> TestResult<(String, PathBuf)> {
+        // hash of the source, build base and compile command -> built proc
+        // macro library, once it was built
+        type Built = Arc<Mutex<Option<PathBuf>>>;
+        static PROC_MACRO_CACHE: Mutex<Option<HashMap<u64, Built>>> = Mutex::new(None);
+
+        let pm_testpaths = self.compute_aux_test_paths(rel_pm)?;
+        let crate_name = pm_testpaths.file.file_stem().unwrap()
+                                     .to_str().unwrap().replace('-', "_");
+
+        let mut source = Vec::new();
+        File::open(&pm_testpaths.file).and_then(|mut f| f.read_to_end(&mut source))
+            .map_err(|e| self.fatal(&format!("failed to read `{}`: {}",
+                                             pm_testpaths.file.display(), e)))?;
+
+        let mut pm_props = self.props.from_aux_file(&pm_testpaths.file,
+                                                    self.revision,
+                                                    self.config);
+        pm_props.force_host = true;
+        pm_props.no_prefer_dynamic = true;
+        // Custom codegen backends can't build proc macros.
+        pm_props.custom_codegen_backend = false;
+        let pm_cx = TestCx {
+            config: self.config,
+            props: &pm_props,
+            testpaths: &pm_testpaths,
+            revision: self.revision
+        };
+        let make_pm_rustc = |out_dir: &Path| {
+            let mut pm_rustc = pm_cx.make_compile_args(
+                &pm_testpaths.file, TargetLocation::ThisDirectory(out_dir.to_path_buf()));
+            pm_rustc.args(["--crate-type", "proc-macro"]);
+            pm_rustc.args(["--crate-name", &crate_name]);
+            pm_rustc
+        };
+
+        // The command covers the host, the flags and the revision's `--cfg`.
+        let mut hasher = DefaultHasher::new();
+        source.hash(&mut hasher);
+        self.config.build_base.hash(&mut hasher);
+        format!("{:?}", make_pm_rustc(Path::new(""))).hash(&mut hasher);
+        let key = hasher.finish();
+
+        // Tests needing the same proc macro wait for each other instead of
+        // building it again, others don't.
+        let entry = {
+            let mut cache = PROC_MACRO_CACHE.lock().unwrap_or_else(|e| e.into_inner());
+            cache.get_or_insert_with(HashMap::new).entry(key).or_default().clone()
+        };
+        let mut built = entry.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(ref artifact) = *built {
+            if artifact.exists() {
+                logv(self.config, format!("reusing proc macro `{}`", artifact.display()));
+                return Ok((crate_name, artifact.clone()));
+            }
+        }
+
+        let out_dir = self.config.build_base
+            .join("proc-macros")
+            .join(&self.config.host)
+            .join(format!("{}-{:016x}", crate_name, key));
+        create_dir_all(&out_dir).unwrap();
+
+        let pmres = pm_cx.compose_and_run(make_pm_rustc(&out_dir),
+                                          self.config.compile_lib_path.to_str().unwrap(),
+                                          None,
+                                          None);
+        self.dump_aux_output(&pm_testpaths.file, &pmres);
+        if !pmres.status.success() {
+            return Err(self.fatal_proc_rec(
+                FailureKind::Compile,
+                &format!("proc macro {:?} failed to compile: ", pm_testpaths.file.display()),
+                &pmres));
+        }
+
+        let artifact = out_dir.join(format!("{}{}{}",
+                                            env::consts::DLL_PREFIX,
+                                            crate_name,
+                                            env::consts::DLL_SUFFIX));
+        if !self.config.dry_run {
+            *built = Some(artifact.clone());
+        }
+        Ok((crate_name, artifact))
     }
 
     fn compose_and_run(&self,
-                       mut command: Command,
+                       command: Command,
                        lib_path: &str,
                        aux_path: Option<&str>,
                        input: Option<String>) -> ProcRes {
+        let aux_paths: Vec<&str> = aux_path.into_iter().collect();
+        self.compose_and_run_isolated(command, lib_path, &aux_paths, input, false)
+    }
+
+    /// Like `compose_and_run`, but with any number of directories to add to
+    /// the dylib search path, and if `isolate` is true, the process gets a
+    /// process group of its own which is killed once it exits, taking any
+    /// processes it left running with it.
+    fn compose_and_run_isolated(&self,
+                                mut command: Command,
+                                lib_path: &str,
+                                aux_paths: &[&str],
+                                input: Option<String>,
+                                isolate: bool) -> ProcRes {
         let cmdline =
         {
             let cmdline = self.make_cmdline(&command, lib_path);
@@ -1337,7 +2247,7 @@ actual:\n\
         // search path for the child.
         let mut path = env::split_paths(&env::var_os(dylib_env_var()).unwrap_or(OsString::new()))
             .collect::<Vec<_>>();
-        if let Some(p) = aux_path {
+        for p in aux_paths.iter().rev() {
             path.insert(0, PathBuf::from(p))
         }
         path.insert(0, PathBuf::from(lib_path));
@@ -1346,12 +2256,19 @@ actual:\n\
         let newpath = env::join_paths(&path).unwrap();
         command.env(dylib_env_var(), newpath);
 
+        if self.config.dry_run {
+            return print_dry_run(&command, cmdline, input.as_deref());
+        }
+        if isolate {
+            procgroup::isolate(&mut command);
+        }
         let mut child = command.spawn().expect(&format!("failed to exec `{:?}`", &command));
+        let group = if isolate { Some(ProcessGroup::new(&child)) } else { None };
         if let Some(input) = input {
             child.stdin.as_mut().unwrap().write_all(input.as_bytes()).unwrap();
         }
 
-        let Output { status, stdout, stderr } = read2_abbreviated(child)
+        let Output { status, stdout, stderr } = read2_abbreviated(child, group.as_ref())
             .expect("failed to read output");
 
         let result = ProcRes {
@@ -1377,13 +2294,7 @@ actual:\n\
             .fold(false, |acc, x| acc || x.starts_with("--target"));
 
         if !custom_target {
-            let target = if self.props.force_host {
-                &*self.config.host
-            } else {
-                &*self.config.target
-            };
-
-            rustc.arg(&format!("--target={}", target));
+            rustc.arg(format!("--target={}", self.crate_target()));
         }
 
         if let Some(revision) = self.revision {
@@ -1403,8 +2314,16 @@ actual:\n\
                 // If we are extracting and matching errors in the new
                 // fashion, then you want JSON mode. Old-skool error
                 // patterns still match the raw compiler output.
-                if self.props.error_patterns.is_empty() {
-                    rustc.args(&["--error-format", "json"]);
+                if !self.props.has_error_patterns() {
+                    match self.props.error_format() {
+                        None => {
+                            rustc.args(["--error-format", "json"]);
+                        }
+                        Some(format) => if self.config.verbose {
+                            println!("not passing `--error-format json`, the test asks for `{}`",
+                                     format);
+                        },
+                    }
                 }
             }
             MirOpt => {
@@ -1429,6 +2348,7 @@ actual:\n\
             DebugInfoGdb |
             DebugInfoLldb |
             Codegen |
+            Assembly |
             Rustdoc |
             RunMake |
             Ui |
@@ -1438,9 +2358,7 @@ actual:\n\
         }
 
 
-        if self.config.target == "wasm32-unknown-unknown" {
-            // rustc.arg("-g"); // get any backtrace at all on errors
-        } else if !self.props.no_prefer_dynamic {
+        if self.prefers_dynamic() {
             rustc.args(&["-C", "prefer-dynamic"]);
         }
 
@@ -1462,10 +2380,51 @@ actual:\n\
             rustc.arg(format!("-Clinker={}", linker));
         }
 
-        rustc.args(&self.props.compile_flags);
+        if let Some(backend) = self.codegen_backend() {
+            rustc.arg(format!("-Zcodegen-backend={}", backend.display()));
+        }
 
-        rustc
-    }
+        if self.props.check_color_output &&
+            !self.props.compile_flags.iter().any(|f| f.starts_with("--color")) {
+            rustc.arg("--color=always");
+        }
+
+        let edition = self.props.edition.as_ref().or(self.config.edition.as_ref());
+        if let Some(edition) = edition {
+            if !self.props.compile_flags.iter().any(|f| f.starts_with("--edition")) {
+                rustc.arg(format!("--edition={}", edition));
+            }
+        }
+
+        rustc.args(&self.props.compile_flags);
+
+        rustc
+    }
+
+    /// The target this crate is compiled for.
+    fn crate_target(&self) -> &str {
+        if self.props.force_host {
+            &self.config.host
+        } else {
+            &self.config.target
+        }
+    }
+
+    /// Whether to compile with `-C prefer-dynamic`, which is the default for
+    /// targets supporting dylibs.
+    fn prefers_dynamic(&self) -> bool {
+        if self.props.no_prefer_dynamic {
+            false
+        } else if self.props.force_prefer_dynamic {
+            true
+        } else if !util::target_supports_dylibs(self.crate_target()) {
+            logv(self.config, format!("not using `-C prefer-dynamic`, {} has no dylibs",
+                                      self.crate_target()));
+            false
+        } else {
+            true
+        }
+    }
 
     fn make_lib_name(&self, auxfile: &Path) -> PathBuf {
         // what we return here is not particularly important, as it
@@ -1493,64 +2452,21 @@ actual:\n\
         f
     }
 
-    fn make_run_args(&self) -> ProcArgs {
-        // If we've got another tool to run under (valgrind),
-        // then split apart its command
-        let mut args = self.split_maybe_args(&self.config.runtool);
-
-        // If this is emscripten, then run tests under nodejs
-        if self.config.target.contains("emscripten") {
-            if let Some(ref p) = self.config.nodejs {
-                args.push(p.clone());
-            } else {
-                self.fatal("no NodeJS binary found (--nodejs)");
-            }
-        }
-
-        // If this is otherwise wasm , then run tests under nodejs with our
-        // shim
-        if self.config.target.contains("wasm32") {
-            if let Some(ref p) = self.config.nodejs {
-                args.push(p.clone());
-            } else {
-                self.fatal("no NodeJS binary found (--nodejs)");
-            }
-
-            let src = self.config.src_base
-                .parent().unwrap() // chop off `run-pass`
-                .parent().unwrap() // chop off `test`
-                .parent().unwrap(); // chop off `src`
-            args.push(src.join("src/etc/wasm32-shim.js").display().to_string());
-        }
-
+    fn make_run_args(&self) -> TestResult<ProcArgs> {
         let exe_file = self.make_exe_name();
-
         // FIXME (#9639): This needs to handle non-utf8 paths
-        args.push(exe_file.to_str().unwrap().to_owned());
-
-        // Add the arguments in the run_flags directive
-        args.extend(self.split_maybe_args(&self.props.run_flags));
-
+        let mut args = run_args(self.config, exe_file.to_str().unwrap(), &self.props.run_flags)
+            .map_err(|e| self.fatal(&e))?;
         let prog = args.remove(0);
-         ProcArgs {
+        Ok(ProcArgs {
             prog,
             args,
-        }
+        })
     }
 
     fn split_maybe_args(&self, argstr: &Option<String>) -> Vec<String> {
         match *argstr {
-            Some(ref s) => {
-                s
-                    .split(' ')
-                    .filter_map(|s| {
-                        if s.chars().all(|c| c.is_whitespace()) {
-                            None
-                        } else {
-                            Some(s.to_owned())
-                        }
-                    }).collect()
-            }
+            Some(ref s) => util::split_args(s),
             None => Vec::new()
         }
     }
@@ -1579,20 +2495,55 @@ actual:\n\
             String::new()
         };
 
-        self.dump_output_file(out, &format!("{}out", revision));
-        self.dump_output_file(err, &format!("{}err", revision));
+        self.dump_output_file(out, &format!("{}out", revision), "stdout of the last command");
+        self.dump_output_file(err, &format!("{}err", revision), "stderr of the last command");
         self.maybe_dump_to_stdout(out, err);
     }
 
+    /// Saves the output of an auxiliary build next to the output of the test
+    /// that requested it (e.g. `foo.aux-helper.err`), so that warnings from
+    /// aux builds which succeeded are not lost.
+    fn dump_aux_output(&self, aux_file: &Path, proc_res: &ProcRes) {
+        let revision = if let Some(r) = self.revision {
+            format!("{}.", r)
+        } else {
+            String::new()
+        };
+        let aux_name = aux_file.file_stem().unwrap().to_string_lossy();
+
+        self.dump_output_file(&proc_res.stdout,
+                              &format!("{}aux-{}.out", revision, aux_name),
+                              &format!("stdout of building {}", aux_file.display()));
+        self.dump_output_file(&proc_res.stderr,
+                              &format!("{}aux-{}.err", revision, aux_name),
+                              &format!("stderr of building {}", aux_file.display()));
+
+        if self.config.verbose {
+            let warnings = count_warnings(&proc_res.stderr);
+            println!("aux {}: {} warning{}",
+                     aux_file.file_name().unwrap().to_string_lossy(),
+                     warnings,
+                     if warnings == 1 { "" } else { "s" });
+        }
+    }
+
+    /// Saves `out` in the test's artifacts directory, returning its path.
     fn dump_output_file(&self,
                         out: &str,
-                        extension: &str) {
-        let outfile = self.make_out_name(extension);
-        File::create(&outfile).unwrap().write_all(out.as_bytes()).unwrap();
+                        extension: &str,
+                        producer: &str) -> PathBuf {
+        if self.config.dry_run {
+            return self.artifacts().path(extension);
+        }
+        self.artifacts().write(extension, out.as_bytes(), producer, self.revision)
     }
 
-    fn make_out_name(&self, extension: &str) -> PathBuf {
-        self.output_base_name().with_extension(extension)
+    /// The files this test left behind for inspection, see `artifacts.rs`.
+    fn artifacts(&self) -> Artifacts {
+        let f = self.output_base_name();
+        let mut fname = f.file_name().unwrap().to_os_string();
+        fname.push(format!("{}.artifacts", self.config.mode.disambiguator()));
+        Artifacts::new(f.with_file_name(&fname), self.config.max_artifacts_size)
     }
 
     fn aux_output_dir_name(&self) -> PathBuf {
@@ -1634,14 +2585,41 @@ actual:\n\
         }
     }
 
-    fn fatal(&self, err: &str) -> ! {
-        self.error(err); panic!();
+    /// A failure whose details were already printed.
+    fn failure(&self, kind: FailureKind, err: &str) -> Box<TestFailure> {
+        let revision = self.revision.map_or(String::new(), |r| format!("{}.", r));
+        let dumped = |extension: &str| {
+            Some(self.artifacts().path(&format!("{}{}", revision, extension)))
+                .filter(|path| !self.config.dry_run && path.is_file())
+        };
+        Box::new(TestFailure {
+            revision: self.revision.map(str::to_owned),
+            stdout_file: dumped("out"),
+            stderr_file: dumped("err"),
+            ..TestFailure::new(kind, err)
+        })
     }
 
-    fn fatal_proc_rec(&self, err: &str, proc_res: &ProcRes) -> ! {
+    fn fatal(&self, err: &str) -> Box<TestFailure> {
+        self.error(err);
+        self.failure(FailureKind::Infrastructure, err)
+    }
+
+    fn fatal_proc_rec(&self, kind: FailureKind, err: &str, proc_res: &ProcRes) -> Box<TestFailure> {
         self.try_print_open_handles();
         self.error(err);
-        proc_res.fatal(None);
+        self.print_artifacts_index();
+        proc_res.print();
+        let mut failure = self.failure(kind, err);
+        failure.proc_res = Some(Box::new(proc_res.clone()));
+        failure
+    }
+
+    fn print_artifacts_index(&self) {
+        let index = self.artifacts().index_path();
+        if index.exists() {
+            println!("files left behind by this test are listed in `{}`", index.display());
+        }
     }
 
     // This function is a poor man's attempt to debug rust-lang/rust#38620, if
@@ -1668,7 +2646,7 @@ actual:\n\
         cmd.arg("-nobanner");
         cmd.stdout(Stdio::piped());
         cmd.stderr(Stdio::piped());
-        let output = match cmd.spawn().and_then(read2_abbreviated) {
+        let output = match cmd.spawn().and_then(|child| read2_abbreviated(child, None)) {
             Ok(output) => output,
             Err(_) => return,
         };
@@ -1685,7 +2663,7 @@ actual:\n\
 
     // codegen tests (using FileCheck)
 
-    fn compile_test_and_save_ir(&self) -> ProcRes {
+    fn compile_test_and_save_ir(&self) -> TestResult<ProcRes> {
         let aux_dir = self.aux_output_dir_name();
 
         let output_file = TargetLocation::ThisDirectory(
@@ -1705,22 +2683,74 @@ actual:\n\
         self.compose_and_run(filecheck, "", None, None)
     }
 
-    fn run_codegen_test(&self) {
+    fn run_codegen_test(&self) -> TestResult {
         assert!(self.revision.is_none(), "revisions not relevant here");
 
         if self.config.llvm_filecheck.is_none() {
-            self.fatal("missing --llvm-filecheck");
+            return Err(self.fatal("missing --llvm-filecheck"));
         }
 
-        let mut proc_res = self.compile_test_and_save_ir();
+        let mut proc_res = self.compile_test_and_save_ir()?;
         if !proc_res.status.success() {
-            self.fatal_proc_rec("compilation failed!", &proc_res);
+            return Err(self.fatal_proc_rec(FailureKind::Compile, "compilation failed!", &proc_res));
         }
 
         proc_res = self.check_ir_with_filecheck();
         if !proc_res.status.success() {
-            self.fatal_proc_rec("verification with 'FileCheck' failed", &proc_res);
+            return Err(self.fatal_proc_rec(FailureKind::Comparison,
+                                           "verification with 'FileCheck' failed",
+                                           &proc_res));
         }
+        Ok(())
+    }
+
+    fn compile_test_and_save_assembly(&self) -> TestResult<(ProcRes, PathBuf)> {
+        let aux_dir = self.aux_output_dir_name();
+        let asm_file = self.output_base_name().with_extension("s");
+
+        let output_file = TargetLocation::ThisFile(asm_file.clone());
+        let mut rustc = self.make_compile_args(&self.testpaths.file, output_file);
+        rustc.arg("-L").arg(aux_dir);
+
+        match self.props.assembly_output.as_deref() {
+            None | Some("emit-asm") => {
+                rustc.arg("--emit=asm");
+            }
+            Some("ptx-linker") => {
+                // The PTX linker already writes assembly to the output file,
+                // no extra flags needed.
+            }
+            Some(other) => {
+                return Err(self.fatal(&format!("unknown `assembly-output` kind `{}`, \
+                                                expected `emit-asm` or `ptx-linker`", other)))
+            }
+        }
+
+        Ok((self.compose_and_run_compiler(rustc, None)?, asm_file))
+    }
+
+    fn run_assembly_test(&self) -> TestResult {
+        if self.config.llvm_filecheck.is_none() {
+            return Err(self.fatal("missing --llvm-filecheck"));
+        }
+
+        let (proc_res, asm_file) = self.compile_test_and_save_assembly()?;
+        if !proc_res.status.success() {
+            return Err(self.fatal_proc_rec(FailureKind::Compile, "compilation failed!", &proc_res));
+        }
+
+        let mut filecheck = Command::new(self.config.llvm_filecheck.as_ref().unwrap());
+        filecheck.arg("--input-file").arg(&asm_file)
+            .arg(&self.testpaths.file);
+        let proc_res = self.compose_and_run(filecheck, "", None, None);
+        if !proc_res.status.success() {
+            return Err(self.fatal_proc_rec(FailureKind::Comparison,
+                                           &format!("verification with 'FileCheck' failed, \
+                                                     assembly is in `{}`",
+                                                    asm_file.display()),
+                                           &proc_res));
+        }
+        Ok(())
     }
 
     fn charset() -> &'static str {
@@ -1734,31 +2764,50 @@ actual:\n\
         }
     }
 
-    fn run_rustdoc_test(&self) {
+    fn run_rustdoc_test(&self) -> TestResult {
         assert!(self.revision.is_none(), "revisions not relevant here");
 
         let out_dir = self.output_base_name();
         let _ = fs::remove_dir_all(&out_dir);
         create_dir_all(&out_dir).unwrap();
 
-        let proc_res = self.document(&out_dir);
+        let proc_res = self.document(&out_dir)?;
         if !proc_res.status.success() {
-            self.fatal_proc_rec("rustdoc failed!", &proc_res);
+            return Err(self.fatal_proc_rec(FailureKind::Compile, "rustdoc failed!", &proc_res));
         }
 
         if self.props.check_test_line_numbers_match {
-            self.check_rustdoc_test_option(proc_res);
+            self.check_rustdoc_test_option(proc_res)
         } else {
-            let root = self.config.find_rust_src_root().unwrap();
+            let docck_script = self.docck_script()?;
             let res = self.cmd2procres(
                 Command::new(&self.config.docck_python)
-                    .arg(root.join("src/etc/htmldocck.py"))
-                    .arg(out_dir)
+                    .arg(&docck_script)
+                    .arg(&out_dir)
                     .arg(&self.testpaths.file),
-            );
+            )?;
             if !res.status.success() {
-                self.fatal_proc_rec("htmldocck failed!", &res);
+                return Err(self.fatal_proc_rec(
+                    FailureKind::Comparison,
+                    &format!("{} failed! (documentation is in `{}`)",
+                             docck_script.display(), out_dir.display()),
+                    &res));
             }
+            Ok(())
+        }
+    }
+
+    /// The script checking the `// @has`/`// @matches` directives against the
+    /// generated documentation. Defaults to rustc's `htmldocck.py` when the
+    /// tests live inside a rust checkout.
+    fn docck_script(&self) -> TestResult<PathBuf> {
+        if let Some(ref script) = self.config.docck_script {
+            return Ok(script.clone());
+        }
+        match self.config.find_rust_src_root() {
+            Some(root) => Ok(root.join("src/etc/htmldocck.py")),
+            None => Err(self.fatal("no docck script configured (--docck-script) and \
+                                    no rust source root found to look for htmldocck.py")),
         }
     }
 
@@ -1802,7 +2851,7 @@ actual:\n\
                .collect()
     }
 
-    fn check_rustdoc_test_option(&self, res: ProcRes) {
+    fn check_rustdoc_test_option(&self, res: ProcRes) -> TestResult {
         let mut other_files = Vec::new();
         let mut files: HashMap<String, Vec<usize>> = HashMap::new();
         let cwd = env::current_dir().unwrap();
@@ -1824,72 +2873,74 @@ actual:\n\
         }
 
         let mut tested = 0;
-        for _ in res.stdout.split('\n')
-                           .filter(|s| s.starts_with("test "))
-                           .inspect(|s| {
-                               let tmp: Vec<&str> = s.split(" - ").collect();
-                               if tmp.len() == 2 {
-                                   let path = tmp[0].rsplit("test ").next().unwrap();
-                                   if let Some(ref mut v) = files.get_mut(
-                                                                &path.replace('\\', "/")) {
-                                       tested += 1;
-                                       let mut iter = tmp[1].split("(line ");
-                                       iter.next();
-                                       let line = iter.next()
-                                                      .unwrap_or(")")
-                                                      .split(')')
-                                                      .next()
-                                                      .unwrap_or("0")
-                                                      .parse()
-                                                      .unwrap_or(0);
-                                       if let Ok(pos) = v.binary_search(&line) {
-                                           v.remove(pos);
-                                       } else {
-                                           self.fatal_proc_rec(
-                                               &format!("Not found doc test: \"{}\" in \"{}\":{:?}",
-                                                        s, path, v),
-                                               &res);
-                                       }
-                                   }
-                               }
-                           }) {}
-        if tested == 0 {
-            self.fatal_proc_rec(&format!("No test has been found... {:?}", files), &res);
-        } else {
-            for (entry, v) in &files {
-                if !v.is_empty() {
-                    self.fatal_proc_rec(&format!("Not found test at line{} \"{}\":{:?}",
-                                                 if v.len() > 1 { "s" } else { "" }, entry, v),
-                                        &res);
+        for s in res.stdout.split('\n').filter(|s| s.starts_with("test ")) {
+            let tmp: Vec<&str> = s.split(" - ").collect();
+            if tmp.len() == 2 {
+                let path = tmp[0].rsplit("test ").next().unwrap();
+                if let Some(ref mut v) = files.get_mut(&path.replace('\\', "/")) {
+                    tested += 1;
+                    let mut iter = tmp[1].split("(line ");
+                    iter.next();
+                    let line = iter.next()
+                                   .unwrap_or(")")
+                                   .split(')')
+                                   .next()
+                                   .unwrap_or("0")
+                                   .parse()
+                                   .unwrap_or(0);
+                    if let Ok(pos) = v.binary_search(&line) {
+                        v.remove(pos);
+                    } else {
+                        return Err(self.fatal_proc_rec(
+                            FailureKind::Comparison,
+                            &format!("Not found doc test: \"{}\" in \"{}\":{:?}", s, path, v),
+                            &res));
+                    }
                 }
             }
         }
+        if tested == 0 {
+            return Err(self.fatal_proc_rec(FailureKind::Comparison,
+                                           &format!("No test has been found... {:?}", files),
+                                           &res));
+        }
+        for (entry, v) in &files {
+            if !v.is_empty() {
+                return Err(self.fatal_proc_rec(
+                    FailureKind::Comparison,
+                    &format!("Not found test at line{} \"{}\":{:?}",
+                             if v.len() > 1 { "s" } else { "" }, entry, v),
+                    &res));
+            }
+        }
+        Ok(())
     }
 
-    fn run_codegen_units_test(&self) {
+    fn run_codegen_units_test(&self) -> TestResult {
         assert!(self.revision.is_none(), "revisions not relevant here");
 
-        let proc_res = self.compile_test();
+        let proc_res = self.compile_codegen_units_test()?;
 
         if !proc_res.status.success() {
-            self.fatal_proc_rec("compilation failed!", &proc_res);
+            return Err(self.fatal_proc_rec(FailureKind::Compile, "compilation failed!", &proc_res));
         }
 
-        self.check_no_compiler_crash(&proc_res);
+        self.check_no_compiler_crash(&proc_res)?;
 
-        const PREFIX: &'static str = "TRANS_ITEM ";
+        // Newer compilers print `MONO_ITEM`, older ones `TRANS_ITEM`.
+        const PREFIXES: &[&str] = &["MONO_ITEM ", "TRANS_ITEM "];
         const CGU_MARKER: &'static str = "@@";
 
-        let actual: Vec<TransItem> = proc_res
+        let actual: Vec<MonoItem> = proc_res
             .stdout
             .lines()
-            .filter(|line| line.starts_with(PREFIX))
-            .map(str_to_trans_item)
+            .filter(|line| PREFIXES.iter().any(|p| line.starts_with(p)))
+            .map(str_to_mono_item)
             .collect();
 
-        let expected: Vec<TransItem> = errors::load_errors(&self.testpaths.file, None)
+        let expected: Vec<MonoItem> = errors::load_errors(&self.testpaths.file, None)
             .iter()
-            .map(|e| str_to_trans_item(&e.msg[..]))
+            .map(|e| str_to_mono_item(&e.msg[..]))
             .collect();
 
         let mut missing = Vec::new();
@@ -1958,25 +3009,35 @@ actual:\n\
 
         if !(missing.is_empty() && unexpected.is_empty() && wrong_cgus.is_empty())
         {
-            panic!();
+            println!("diff of expected and actual mono items:\n");
+            let expected_str = mono_items_to_str(&expected);
+            let actual_str = mono_items_to_str(&actual);
+            print_line_diff(&expected_str, &actual_str);
+            println!();
+
+            return Err(self.fatal_proc_rec(
+                FailureKind::Comparison,
+                &format!("{} missing, {} unexpected and {} misplaced mono items",
+                         missing.len(), unexpected.len(), wrong_cgus.len()),
+                &proc_res));
         }
 
         #[derive(Clone, Eq, PartialEq)]
-        struct TransItem {
+        struct MonoItem {
             name: String,
             codegen_units: HashSet<String>,
             string: String,
         }
 
-        // [TRANS_ITEM] name [@@ (cgu)+]
-        fn str_to_trans_item(s: &str) -> TransItem {
-            let s = if s.starts_with(PREFIX) {
-                (&s[PREFIX.len()..]).trim()
-            } else {
-                s.trim()
+        // [MONO_ITEM|TRANS_ITEM] name [@@ (cgu)+]
+        fn str_to_mono_item(s: &str) -> MonoItem {
+            let s = s.trim();
+            let s = match PREFIXES.iter().find(|p| s.starts_with(p.trim())) {
+                Some(prefix) => s[prefix.trim().len()..].trim(),
+                None => s,
             };
 
-            let full_string = format!("{}{}", PREFIX, s.trim().to_owned());
+            let full_string = format!("{}{}", PREFIXES[0], s.trim().to_owned());
 
             let parts: Vec<&str> = s.split(CGU_MARKER)
                                     .map(str::trim)
@@ -1998,7 +3059,7 @@ actual:\n\
                 HashSet::new()
             };
 
-            TransItem {
+            MonoItem {
                 name: name.to_owned(),
                 codegen_units: cgus,
                 string: full_string,
@@ -2018,15 +3079,71 @@ actual:\n\
 
             string
         }
+
+        // One line per item, sorted by name, so that the two sets can be diffed.
+        fn mono_items_to_str(items: &[MonoItem]) -> String {
+            let mut lines: Vec<String> = items.iter()
+                .map(|item| format!("{} {} {}",
+                                    item.name,
+                                    CGU_MARKER,
+                                    codegen_units_to_str(&item.codegen_units)).trim().to_owned())
+                .collect();
+            lines.sort();
+            lines.join("\n")
+        }
+
+        Ok(())
+    }
+
+    /// Compiles a codegen-units test asking rustc to print its mono items.
+    /// Tests may pick the collection strategy themselves through their
+    /// compile-flags; otherwise we request eager collection, falling back
+    /// to the `-Zprint-trans-items` spelling for older compilers.
+    fn compile_codegen_units_test(&self) -> TestResult<ProcRes> {
+        let explicit = self.props.compile_flags.iter().any(|f| {
+            f.contains("print-mono-items") || f.contains("print-trans-items")
+        });
+        if explicit {
+            return self.compile_test();
+        }
+
+        let proc_res = self.compile_test_with_args(&["-Zprint-mono-items=eager"])?;
+        if !proc_res.status.success() &&
+           proc_res.stderr.contains("unknown debugging option") {
+            return self.compile_test_with_args(&["-Zprint-trans-items=eager"]);
+        }
+        Ok(proc_res)
     }
 
-    fn init_incremental_test(&self) {
+    fn init_incremental_test(&self) -> TestResult {
         // (See `run_incremental_test` for an overview of how incremental tests work.)
 
-        // Before any of the revisions have executed, create the
-        // incremental workproduct directory.  Delete any old
-        // incremental work products that may be there from prior
-        // runs.
+        // Check the revision names up front, so that a typo doesn't show up
+        // only after the earlier revisions have been built.
+        if self.props.revisions.is_empty() {
+            return Err(self.fatal("incremental tests require a list of revisions, \
+                                   e.g. `// revisions: rpass1 cfail2 rpass3`"));
+        }
+        for revision in &self.props.revisions {
+            if incremental_revision_kind(revision).is_none() {
+                return Err(self.fatal(&format!("revision `{}` must begin with rpass, rfail, \
+                                                or cfail",
+                                               revision)));
+            }
+        }
+
+        self.init_incremental_dir();
+        Ok(())
+    }
+
+    /// Before any of the revisions have executed, create the incremental
+    /// workproduct directory. Delete any old incremental work products that
+    /// may be there from prior runs, so that every run of the test starts
+    /// from an empty cache.
+    fn init_incremental_dir(&self) {
+        if self.config.dry_run {
+            return;
+        }
         let incremental_dir = self.incremental_dir();
         if incremental_dir.exists() {
             // Canonicalizing the path will convert it to the //?/ format
@@ -2037,14 +3154,14 @@ actual:\n\
         fs::create_dir_all(&incremental_dir).unwrap();
 
         if self.config.verbose {
-            print!("init_incremental_test: incremental_dir={}", incremental_dir.display());
+            print!("init_incremental_dir: incremental_dir={}", incremental_dir.display());
         }
     }
 
-    fn run_incremental_test(&self) {
+    fn run_incremental_test(&self) -> TestResult {
         // Basic plan for a test incremental/foo/bar.rs:
         // - load list of revisions rpass1, cfail2, rpass3
-        //   - each should begin with `rpass`, `cfail`, or `cfail`
+        //   - each should begin with `rpass`, `rfail`, or `cfail`
         //   - if `rpass`, expect compile and execution to succeed
         //   - if `cfail`, expect compilation to fail
         //   - if `rfail`, expect execution to fail
@@ -2053,7 +3170,7 @@ actual:\n\
         //   - because name of revision starts with "rpass", expect success
         // - compile foo/bar.rs with -Z incremental=.../foo/bar.incremental and -C cfail2
         //   - because name of revision starts with "cfail", expect an error
-        //   - load expected errors as usual, but filter for those that end in `[rfail2]`
+        //   - load expected errors as usual, but filter for those that end in `[cfail2]`
         // - compile foo/bar.rs with -Z incremental=.../foo/bar.incremental and -C rpass3
         //   - because name of revision starts with "rpass", expect success
         // - execute build/foo/bar.exe and save output
@@ -2082,15 +3199,11 @@ actual:\n\
             print!("revision={:?} revision_props={:#?}", revision, revision_props);
         }
 
-        if revision.starts_with("rpass") {
-            revision_cx.run_rpass_test();
-        } else if revision.starts_with("rfail") {
-            revision_cx.run_rfail_test();
-        } else if revision.starts_with("cfail") {
-            revision_cx.run_cfail_test();
-        } else {
-            revision_cx.fatal(
-                "revision name must begin with rpass, rfail, or cfail");
+        match incremental_revision_kind(revision) {
+            Some(RunPass) => revision_cx.run_rpass_test(),
+            Some(RunFail) => revision_cx.run_rfail_test(),
+            Some(CompileFail) => revision_cx.run_cfail_test(),
+            _ => Err(revision_cx.fatal("revision name must begin with rpass, rfail, or cfail")),
         }
     }
 
@@ -2099,10 +3212,10 @@ actual:\n\
         self.output_base_name().with_extension("inc")
     }
 
-    fn run_rmake_test(&self) {
+    fn run_rmake_test(&self) -> TestResult {
         // FIXME(#11094): we should fix these tests
         if self.config.host != self.config.target {
-            return
+            return Ok(())
         }
 
         let cwd = env::current_dir().unwrap();
@@ -2111,22 +3224,49 @@ actual:\n\
                                            .parent().unwrap();
         let src_root = cwd.join(&src_root);
 
-        let tmpdir = cwd.join(self.output_base_name());
+        let tmpdir = cwd.join(self.rmake_tmpdir());
         if tmpdir.exists() {
-            self.aggressive_rm_rf(&tmpdir).unwrap();
+            self.remove_rmake_tmpdir(&tmpdir);
         }
         create_dir_all(&tmpdir).unwrap();
+        // Many Makefiles can't cope with backslashes in paths.
+        let tmpdir_env = if cfg!(windows) {
+            tmpdir.to_str().unwrap().replace('\\', "/")
+        } else {
+            tmpdir.to_str().unwrap().to_owned()
+        };
 
-        let host = &self.config.host;
-        let make = if host.contains("bitrig") || host.contains("dragonfly") ||
-            host.contains("freebsd") || host.contains("netbsd") ||
-            host.contains("openbsd") {
-            "gmake"
+        // A test is driven either by a Makefile or by a Rust program in
+        // `rmake.rs`, which is built for the host first.
+        let rmake_rs = self.testpaths.file.join("rmake.rs");
+        let (mut cmd, driver) = if rmake_rs.exists() {
+            let driver_path = tmpdir.join(format!("rmake{}", env::consts::EXE_SUFFIX));
+            let mut rustc = Command::new(cwd.join(&self.config.rustc_path));
+            rustc.arg(&rmake_rs)
+                 .arg("-o").arg(&driver_path)
+                 .arg(format!("--target={}", self.config.host));
+            let proc_res = self.compose_and_run(rustc,
+                                                self.config.compile_lib_path.to_str().unwrap(),
+                                                None,
+                                                None);
+            if !proc_res.status.success() {
+                return Err(self.fatal_proc_rec(FailureKind::Compile,
+                                               "failed to compile rmake.rs",
+                                               &proc_res));
+            }
+            (Command::new(driver_path), "rmake.rs")
         } else {
-            "make"
+            let host = &self.config.host;
+            let make = if host.contains("bitrig") || host.contains("dragonfly") ||
+                host.contains("freebsd") || host.contains("netbsd") ||
+                host.contains("openbsd") {
+                "gmake"
+            } else {
+                "make"
+            };
+            (Command::new(make), "make")
         };
 
-        let mut cmd = Command::new(make);
         cmd.current_dir(&self.testpaths.file)
            .stdout(Stdio::piped())
            .stderr(Stdio::piped())
@@ -2137,7 +3277,7 @@ actual:\n\
            .env("RUSTC", cwd.join(&self.config.rustc_path))
            .env("RUSTDOC",
                cwd.join(&self.config.rustdoc_path.as_ref().expect("--rustdoc-path passed")))
-           .env("TMPDIR", &tmpdir)
+           .env("TMPDIR", &tmpdir_env)
            .env("LD_LIB_PATH_ENVVAR", dylib_env_var())
            .env("HOST_RPATH_DIR", cwd.join(&self.config.compile_lib_path))
            .env("TARGET_RPATH_DIR", cwd.join(&self.config.run_lib_path))
@@ -2178,7 +3318,19 @@ actual:\n\
             }
         }
 
-        let output = cmd.spawn().and_then(read2_abbreviated).expect("failed to spawn `make`");
+        if self.config.dry_run {
+            let cmdline = format!("{:?}", cmd);
+            print_dry_run(&cmd, cmdline, None);
+            self.remove_rmake_tmpdir(&tmpdir);
+            return Ok(());
+        }
+        let output = cmd.spawn().and_then(|child| read2_abbreviated(child, None))
+                        .map_err(|e| self.fatal(&format!("failed to spawn `{}`: {}", driver, e)))?;
+        let artifacts = self.artifacts();
+        artifacts.write(&format!("{}.out", driver), &output.stdout,
+                        &format!("stdout of {}", driver), self.revision);
+        artifacts.write(&format!("{}.err", driver), &output.stderr,
+                        &format!("stderr of {}", driver), self.revision);
         if !output.status.success() {
             let res = ProcRes {
                 status: output.status,
@@ -2186,7 +3338,49 @@ actual:\n\
                 stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
                 cmdline: format!("{:?}", cmd),
             };
-            self.fatal_proc_rec("make failed", &res);
+            return Err(self.fatal_proc_rec(FailureKind::Run,
+                                           &format!("{} failed, its TMPDIR was `{}`",
+                                                    driver, tmpdir.display()),
+                                           &res));
+        }
+
+        // Keep the directory of a failed test around for inspection, but
+        // don't let the ones of passing tests pile up.
+        self.remove_rmake_tmpdir(&tmpdir);
+        Ok(())
+    }
+
+    /// The TMPDIR of a run-make test. It includes the revision and a counter
+    /// that is bumped for every run-make test run by this process, so that
+    /// no two runs ever share (and delete) each other's directory.
+    fn rmake_tmpdir(&self) -> PathBuf {
+        static RUN_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let run = RUN_COUNTER.fetch_add(1, Ordering::SeqCst);
+        let base = self.output_base_name();
+        let mut fname = base.file_name().unwrap().to_os_string();
+        if let Some(revision) = self.revision {
+            fname.push(format!(".{}", revision));
+        }
+        fname.push(format!(".tmp{}", run));
+        base.with_file_name(&fname)
+    }
+
+    /// Remove a run-make TMPDIR, retrying a few times if something (like a
+    /// stale process of an earlier run) keeps creating files in it. Failing
+    /// to remove it isn't an error, the test just leaves some garbage behind.
+    fn remove_rmake_tmpdir(&self, tmpdir: &Path) {
+        let mut result = Ok(());
+        for _ in 0..3 {
+            result = self.aggressive_rm_rf(tmpdir);
+            match result {
+                Ok(()) => return,
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => return,
+                Err(_) => {}
+            }
+        }
+        if let Err(e) = result {
+            println!("warning: failed to remove `{}`: {}", tmpdir.display(), e);
         }
     }
 
@@ -2194,8 +3388,8 @@ actual:\n\
         for e in path.read_dir()? {
             let entry = e?;
             let path = entry.path();
-            if entry.file_type()?.is_dir() {
-                self.aggressive_rm_rf(&path)?;
+            let removed = if entry.file_type()?.is_dir() {
+                self.aggressive_rm_rf(&path)
             } else {
                 // Remove readonly files as well on windows (by default we can't)
                 fs::remove_file(&path).or_else(|e| {
@@ -2207,68 +3401,193 @@ actual:\n\
                     } else {
                         Err(e)
                     }
-                })?;
+                })
+            };
+            // Somebody else removing the entry first is just as good.
+            match removed {
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+                removed => removed?,
             }
         }
         fs::remove_dir(path)
     }
 
-    fn run_ui_test(&self) {
-        let proc_res = self.compile_test();
-
-        let expected_stderr_path = self.expected_output_path("stderr");
-        let expected_stderr = self.load_expected_output(&expected_stderr_path);
-
-        let expected_stdout_path = self.expected_output_path("stdout");
-        let expected_stdout = self.load_expected_output(&expected_stdout_path);
+    fn run_ui_test(&self) -> TestResult {
+        let proc_res = if self.props.check_pass && !self.props.build_pass &&
+                          !self.props.run_pass {
+            self.check_test()?
+        } else {
+            self.compile_test()?
+        };
+        // A crash mustn't be blessed as the expected output.
+        if (self.props.should_ice || self.config.bless) && !self.config.dry_run {
+            self.check_no_compiler_crash(&proc_res)?;
+        }
+        if (self.props.check_pass || self.props.build_pass) && !proc_res.status.success() {
+            let pass = if self.props.check_pass { "check-pass" } else { "build-pass" };
+            return Err(self.fatal_proc_rec(FailureKind::Compile,
+                                           &format!("{} test failed to compile!", pass),
+                                           &proc_res));
+        }
 
         let normalized_stdout =
             self.normalize_output(&proc_res.stdout, &self.props.normalize_stdout);
-        let normalized_stderr =
-            self.normalize_output(&proc_res.stderr, &self.props.normalize_stderr);
-
         let mut errors = 0;
-        errors += self.compare_output("stdout", &normalized_stdout, &expected_stdout);
-        errors += self.compare_output("stderr", &normalized_stderr, &expected_stderr);
+        if self.props.dont_check_compiler_stdout {
+            self.warn_unused_expected_output("stdout");
+        } else {
+            errors += self.compare_output_with_alternatives("stdout", &normalized_stdout)?;
+        }
+        if self.props.dont_check_compiler_stderr {
+            self.warn_unused_expected_output("stderr");
+        } else if self.props.check_color_output {
+            // Compared byte for byte, only paths are normalized.
+            let colored_stderr = self.normalize_paths(&proc_res.stderr);
+            errors += self.compare_output_with_alternatives("colored.stderr", &colored_stderr)?;
+        } else {
+            let normalized_stderr =
+                self.normalize_output(&proc_res.stderr, &self.props.normalize_stderr);
+            errors += self.compare_output_with_alternatives("stderr", &normalized_stderr)?;
+        }
+
+        if self.props.run_rustfix && !self.config.dry_run {
+            errors += self.check_rustfix(&proc_res)?;
+        }
+
+        let long_type_files = long_type_files(&proc_res.stderr);
+        if self.props.check_long_type_file && !self.config.dry_run {
+            errors += self.check_long_type_files(&long_type_files, &proc_res)?;
+        }
 
         if errors > 0 {
-            println!("To update references, run this command from build directory:");
-            let relative_path_to_file =
-                self.testpaths.relative_dir
-                              .join(self.testpaths.file.file_name().unwrap());
-            println!("{}/update-references.sh '{}' '{}'",
-                     self.config.src_base.display(),
-                     self.config.build_base.display(),
-                     relative_path_to_file.display());
-            self.fatal_proc_rec(&format!("{} errors occurred comparing output.", errors),
-                                &proc_res);
+            println!("To update references, run the tests again with `BLESS=1` set in the \
+                      environment (or `bless` set in the configuration).");
+            return Err(self.fatal_proc_rec(
+                FailureKind::Comparison,
+                &format!("{} errors occurred comparing output.", errors),
+                &proc_res));
+        }
+        // Kept for inspection if the test failed.
+        for path in &long_type_files {
+            let _ = fs::remove_file(path);
         }
 
-        if self.props.run_pass {
-            let proc_res = self.exec_compiled_test();
+        if self.props.run_pass && self.should_run() {
+            let proc_res = self.exec_compiled_test()?;
+
+            if self.props.check_run_results {
+                let normalized_stdout =
+                    self.normalize_output(&proc_res.stdout, &self.props.normalize_stdout);
+                let normalized_stderr =
+                    self.normalize_output(&proc_res.stderr, &self.props.normalize_stderr);
+                let errors =
+                    self.compare_output_with_alternatives("run.stdout", &normalized_stdout)? +
+                    self.compare_output_with_alternatives("run.stderr", &normalized_stderr)?;
+                if errors > 0 {
+                    return Err(self.fatal_proc_rec(
+                        FailureKind::Comparison,
+                        &format!("{} errors occurred comparing run output.", errors),
+                        &proc_res));
+                }
+            }
+
+            if self.props.failure_status.is_some() {
+                self.check_correct_failure_status(&proc_res)?;
+            } else if !proc_res.status.success() {
+                return Err(self.run_failed(&proc_res));
+            }
+        }
+        Ok(())
+    }
 
-            if !proc_res.status.success() {
-                self.fatal_proc_rec("test run failed!", &proc_res);
+    /// Compare the full type names the compiler wrote to `files` with the
+    /// expected `.long-type.txt` file. Returns the number of differing
+    /// outputs, like `compare_output`.
+    fn check_long_type_files(&self, files: &[PathBuf], proc_res: &ProcRes)
+                             -> TestResult<usize> {
+        if files.is_empty() {
+            return Err(self.fatal_proc_rec(
+                FailureKind::Comparison,
+                "`check-long-type-file` given, but the compiler didn't write the full name of \
+                 any type to a file",
+                proc_res));
+        }
+        let mut contents = String::new();
+        for path in files {
+            match fs::read_to_string(path) {
+                Ok(file) => contents.push_str(&file),
+                Err(e) => {
+                    return Err(self.fatal(&format!("failed to read `{}`: {}",
+                                                   path.display(), e)));
+                }
             }
         }
+        let normalized = self.normalize_output(&contents, &[]);
+        self.compare_output_with_alternatives("long-type.txt", &normalized)
     }
 
-    fn run_mir_opt_test(&self) {
-        let proc_res = self.compile_test();
+    /// Apply the suggestions of the compiler to the test, compare the result
+    /// with the expected `.fixed` file and check that it compiles. Returns
+    /// the number of differing outputs, like `compare_output`.
+    fn check_rustfix(&self, proc_res: &ProcRes) -> TestResult<usize> {
+        let json_res;
+        let json_res = if self.props.error_format() == Some("json") {
+            proc_res
+        } else {
+            json_res = self.compile_test_with_args(&["--error-format", "json"])?;
+            &json_res
+        };
+
+        let source = read_to_string(&self.testpaths.file);
+        let fixed = json::apply_suggestions(self.testpaths.file.to_str().unwrap(),
+                                            &source,
+                                            &json_res.stderr,
+                                            json_res)?;
+        let expected_fixed_path = self.expected_output_path("fixed");
+        let expected_fixed = self.load_expected_output(&expected_fixed_path)?;
+        let errors = if self.config.bless {
+            self.bless_output("fixed", &fixed, &expected_fixed, &expected_fixed_path)?
+        } else {
+            self.compare_output("fixed", &fixed, &expected_fixed)?
+        };
 
-        if !proc_res.status.success() {
-            self.fatal_proc_rec("compilation failed!", &proc_res);
+        let fixed_file = self.output_base_name().with_extension("fixed.rs");
+        if let Err(e) = File::create(&fixed_file).and_then(|mut f| f.write_all(fixed.as_bytes())) {
+            return Err(self.fatal(&format!("failed to write `{}`: {}", fixed_file.display(), e)));
+        }
+        let out_dir = self.output_base_name().with_extension("fixed");
+        let _ = fs::create_dir_all(&out_dir);
+        let mut rustc = self.make_compile_args(&fixed_file,
+                                               TargetLocation::ThisDirectory(out_dir));
+        rustc.arg("-L").arg(self.aux_output_dir_name());
+        rustc.arg("--emit=metadata");
+        let fixed_res = self.compose_and_run_compiler(rustc, None)?;
+        if !fixed_res.status.success() {
+            return Err(self.fatal_proc_rec(FailureKind::Compile,
+                                           "failed to compile the fixed source",
+                                           &fixed_res));
         }
+        Ok(errors)
+    }
 
-        let proc_res = self.exec_compiled_test();
+    fn run_mir_opt_test(&self) -> TestResult {
+        let proc_res = self.compile_test()?;
 
         if !proc_res.status.success() {
-            self.fatal_proc_rec("test run failed!", &proc_res);
+            return Err(self.fatal_proc_rec(FailureKind::Compile, "compilation failed!", &proc_res));
+        }
+
+        if self.should_run() {
+            let proc_res = self.exec_compiled_test()?;
+
+            if !proc_res.status.success() {
+                return Err(self.run_failed(&proc_res));
+            }
         }
-        self.check_mir_dump();
+        self.check_mir_dump()
     }
 
-    fn check_mir_dump(&self) {
+    fn check_mir_dump(&self) -> TestResult {
         let mut test_file_contents = String::new();
         fs::File::open(self.testpaths.file.clone()).unwrap()
                                                    .read_to_string(&mut test_file_contents)
@@ -2286,9 +3605,9 @@ actual:\n\
                 } else if l.starts_with("// END") {
                     let (_, t) = l.split_at("// END ".len());
                     if Some(t) != curr_test {
-                        panic!("mismatched START END test name");
+                        return Err(self.fatal("mismatched START END test name"));
                     }
-                    self.compare_mir_test_output(curr_test.unwrap(), &curr_test_contents);
+                    self.compare_mir_test_output(curr_test.unwrap(), &curr_test_contents)?;
                     curr_test = None;
                     curr_test_contents.clear();
                     curr_test_contents.push(ExpectedLine::Elision);
@@ -2302,31 +3621,35 @@ actual:\n\
                 }
             }
         }
+        Ok(())
     }
 
-    fn check_mir_test_timestamp(&self, test_name: &str, output_file: &Path) {
+    fn check_mir_test_timestamp(&self, test_name: &str, output_file: &Path) -> TestResult {
         let t = |file| FileTime::from_last_modification_time(&fs::metadata(file).unwrap());
         let source_file = &self.testpaths.file;
         let output_time = t(output_file);
         let source_time = t(source_file);
         if source_time > output_time {
             debug!("source file time: {:?} output file time: {:?}", source_time, output_time);
-            panic!("test source file `{}` is newer than potentially stale output file `{}`.",
-                   source_file.display(), test_name);
+            return Err(self.fatal(&format!("test source file `{}` is newer than potentially \
+                                            stale output file `{}`.",
+                                           source_file.display(), test_name)));
         }
+        Ok(())
     }
 
-    fn compare_mir_test_output(&self, test_name: &str, expected_content: &[ExpectedLine<&str>]) {
+    fn compare_mir_test_output(&self, test_name: &str, expected_content: &[ExpectedLine<&str>])
+                               -> TestResult {
         let mut output_file = PathBuf::new();
         output_file.push(self.get_mir_dump_dir());
         output_file.push(test_name);
         debug!("comparing the contests of: {:?}", output_file);
         debug!("with: {:?}", expected_content);
         if !output_file.exists() {
-            panic!("Output file `{}` from test does not exist",
-                   output_file.into_os_string().to_string_lossy());
+            return Err(self.fatal(&format!("Output file `{}` from test does not exist",
+                                           output_file.into_os_string().to_string_lossy())));
         }
-        self.check_mir_test_timestamp(test_name, &output_file);
+        self.check_mir_test_timestamp(test_name, &output_file)?;
 
         let mut dumped_file = fs::File::open(output_file.clone()).unwrap();
         let mut dumped_string = String::new();
@@ -2350,26 +3673,25 @@ actual:\n\
 
         let error = |expected_line, extra_msg| {
             let normalize_all = dumped_string.lines()
-                                             .map(nocomment_mir_line)
+                                             .map(|l| nocomment_mir_line(l).trim())
                                              .filter(|l| !l.is_empty())
                                              .collect::<Vec<_>>()
                                              .join("\n");
-            let f = |l: &ExpectedLine<_>| match l {
-                &ExpectedLine::Elision => "... (elided)".into(),
-                &ExpectedLine::Text(t) => t
-            };
             let expected_content = expected_content.iter()
-                                                   .map(|l| f(l))
+                                                   .map(|l| match *l {
+                                                       ExpectedLine::Elision => "... (elided)",
+                                                       ExpectedLine::Text(t) => t.trim(),
+                                                   })
                                                    .collect::<Vec<_>>()
                                                    .join("\n");
-            panic!("Did not find expected line, error: {}\n\
-                   Actual Line: {:?}\n\
-                   Expected:\n{}\n\
-                   Actual:\n{}",
-                   extra_msg,
-                   expected_line,
-                   expected_content,
-                   normalize_all);
+            println!("diff of expected MIR and `{}`:\n", output_file.display());
+            print_line_diff(&expected_content, &normalize_all);
+            println!();
+            self.fatal(&format!("Did not find expected line in `{}`, error: {}\n\
+                                 Actual Line: {:?}",
+                                output_file.display(),
+                                extra_msg,
+                                expected_line))
         };
 
         // We expect each non-empty line to appear consecutively, non-consecutive lines
@@ -2385,9 +3707,11 @@ actual:\n\
 
                     if !compare(expected_line, dumped_line) {
                         error!("{:?}", start_block_line);
-                        error(expected_line,
-                              format!("Mismatch in lines\nCurrnt block: {}\nExpected Line: {:?}",
-                                      start_block_line.unwrap_or("None"), dumped_line));
+                        return Err(error(expected_line,
+                                         format!("Mismatch in lines\nCurrnt block: {}\n\
+                                                  Expected Line: {:?}",
+                                                 start_block_line.unwrap_or("None"),
+                                                 dumped_line)));
                     }
                 },
                 Some(&ExpectedLine::Elision) => {
@@ -2407,13 +3731,15 @@ actual:\n\
                             }
                         }
                         if !found {
-                            error(expected_line, "ran out of mir dump to match against".into());
+                            return Err(error(expected_line,
+                                             "ran out of mir dump to match against".into()));
                         }
                     }
                 },
                 None => {},
             }
         }
+        Ok(())
     }
 
     fn get_mir_dump_dir(&self) -> PathBuf {
@@ -2424,11 +3750,26 @@ actual:\n\
         mir_dump_dir
     }
 
-    fn normalize_output(&self, output: &str, custom_rules: &[(String, String)]) -> String {
+    /// Warn about an expected output file for an output which isn't checked,
+    /// since it would silently get out of date.
+    fn warn_unused_expected_output(&self, kind: &str) {
+        let path = self.expected_output_path(kind);
+        if path.exists() {
+            println!("warning: `{}` is unused, since the {} of the compiler isn't checked \
+                      (`dont-check-compiler-{}`), remove it",
+                     path.display(), kind, kind);
+        }
+    }
+
+    /// Replace the directory of the test by `$DIR` in `output`.
+    fn normalize_paths(&self, output: &str) -> String {
         let parent_dir = self.testpaths.file.parent().unwrap();
-        let cflags = self.props.compile_flags.join(" ");
-        let json = cflags.contains("--error-format json") ||
-                   cflags.contains("--error-format pretty-json");
+        output.replace(&parent_dir.display().to_string(), "$DIR")
+    }
+
+    fn normalize_output(&self, output: &str, custom_rules: &[Normalization]) -> String {
+        let parent_dir = self.testpaths.file.parent().unwrap();
+        let json = matches!(self.props.error_format(), Some("json") | Some("pretty-json"));
         let parent_dir_str = if json {
             parent_dir.display().to_string().replace("\\", "\\\\")
         } else {
@@ -2449,8 +3790,15 @@ actual:\n\
               .replace("\\", "/") // normalize for paths on windows
               .replace("\r\n", "\n") // normalize for linebreaks on windows
               .replace("\t", "\\t"); // makes tabs visible
+        if self.uses_sanitizer() {
+            normalized = normalize_sanitizer_report(&normalized);
+        }
+        // The files full type names are written to have a random name and
+        // are in the build directory.
+        normalized = long_type_file_regex().replace_all(&normalized, "$$LONG_TYPE_FILE")
+            .into_owned();
         for rule in custom_rules {
-            normalized = normalized.replace(&rule.0, &rule.1);
+            normalized = rule.apply(&normalized);
         }
         normalized
     }
@@ -2463,50 +3811,271 @@ actual:\n\
         self.testpaths.file.with_extension(extension)
     }
 
-    fn load_expected_output(&self, path: &Path) -> String {
+    fn load_expected_output(&self, path: &Path) -> TestResult<String> {
         if !path.exists() {
-            return String::new();
+            return Ok(String::new());
         }
 
         let mut result = String::new();
         match File::open(path).and_then(|mut f| f.read_to_string(&mut result)) {
-            Ok(_) => result,
+            Ok(_) => Ok(result),
             Err(e) => {
-                self.fatal(&format!("failed to load expected output from `{}`: {}",
-                                    path.display(), e))
+                Err(self.fatal(&format!("failed to load expected output from `{}`: {}",
+                                        path.display(), e)))
+            }
+        }
+    }
+
+    /// Alternative expected outputs, e.g. `foo.alt1.stderr` for `foo.stderr`,
+    /// which are accepted as well during a transition between compilers.
+    fn alternative_output_paths(&self, kind: &str) -> Vec<PathBuf> {
+        let primary = self.expected_output_path(kind);
+        let suffix = format!(".{}", kind);
+        let primary_name = primary.file_name().unwrap().to_str().unwrap();
+        let prefix = format!("{}.alt", &primary_name[..primary_name.len() - suffix.len()]);
+
+        let mut paths = Vec::new();
+        if let Ok(entries) = primary.parent().unwrap().read_dir() {
+            for entry in entries {
+                let path = entry.unwrap().path();
+                let is_alternative = {
+                    let name = path.file_name().unwrap().to_string_lossy();
+                    name.starts_with(&prefix) && name.ends_with(&suffix) &&
+                        name.len() > prefix.len() + suffix.len() &&
+                        name[prefix.len()..name.len() - suffix.len()]
+                            .chars().all(|c| c.is_ascii_digit())
+                };
+                if is_alternative {
+                    paths.push(path);
+                }
+            }
+        }
+        paths.sort();
+        paths
+    }
+
+    /// Like `compare_output`, but the output may also match one of the
+    /// alternatives. If nothing matches, the closest one is diffed.
+    fn compare_output_with_alternatives(&self, kind: &str, actual: &str) -> TestResult<usize> {
+        if self.config.dry_run {
+            return Ok(0);
+        }
+        let primary_path = self.expected_output_path(kind);
+        let primary = self.load_expected_output(&primary_path)?;
+        if self.outputs_match(kind, &primary, actual) {
+            return Ok(0);
+        }
+
+        let alternatives = self.alternative_output_paths(kind);
+        if alternatives.is_empty() {
+            if self.config.bless {
+                return self.bless_output(kind, actual, &primary, &primary_path);
+            }
+            return self.compare_output(kind, actual, &primary);
+        }
+        if self.config.forbid_alternatives {
+            for path in &alternatives {
+                self.error(&format!("alternative expected output `{}` is forbidden",
+                                    path.display()));
+            }
+            return Ok(alternatives.len());
+        }
+
+        let mut closest = (count_differing_lines(&primary, actual), primary_path.clone(),
+                           primary.clone());
+        for path in alternatives {
+            let expected = self.load_expected_output(&path)?;
+            if self.outputs_match(kind, &expected, actual) {
+                if self.config.verbose {
+                    println!("{} matched alternative `{}`", kind, path.display());
+                }
+                return Ok(0);
+            }
+            let differing = count_differing_lines(&expected, actual);
+            if differing < closest.0 {
+                closest = (differing, path, expected);
+            }
+        }
+
+        if self.config.bless {
+            return self.bless_output(kind, actual, &primary, &primary_path);
+        }
+        println!("no expected {} matched, showing the closest one, `{}`",
+                 kind, closest.1.display());
+        self.compare_output(kind, actual, &closest.2)
+    }
+
+    /// Instead of comparing an output with the expected one, make it the
+    /// expected output at `path` (see `Config::bless`). An empty output
+    /// removes the file. Returns 0, like `compare_output` for a match.
+    fn bless_output(&self, kind: &str, actual: &str, expected: &str, path: &Path)
+                    -> TestResult<usize> {
+        if self.outputs_match(kind, expected, actual) {
+            return Ok(0);
+        }
+        let actual = if self.ignores_whitespace(kind) {
+            canonical_whitespace(actual)
+        } else {
+            actual.to_owned()
+        };
+        let action = if actual.is_empty() {
+            ::BlessAction::Deleted
+        } else if path.exists() {
+            ::BlessAction::Modified
+        } else {
+            ::BlessAction::Created
+        };
+        let result = if action == ::BlessAction::Deleted {
+            fs::remove_file(path)
+        } else {
+            fs::write(path, &actual)
+        };
+        if let Err(e) = result {
+            return Err(self.fatal(&format!("failed to bless {} `{}`: {}",
+                                           kind, path.display(), e)));
+        }
+        // The compiler version goes along with the expected output.
+        if action == ::BlessAction::Deleted {
+            let _ = fs::remove_file(snapshot_meta_path(path));
+        } else if self.config.track_snapshot_versions {
+            if let Some(version) = self.config.rustc_version() {
+                write_snapshot_meta(path, &version);
             }
         }
+        self.config.blessed.lock().unwrap_or_else(|e| e.into_inner())
+            .push((path.to_owned(), action));
+        Ok(0)
     }
 
-    fn compare_output(&self, kind: &str, actual: &str, expected: &str) -> usize {
-        if actual == expected {
-            return 0;
+    /// Whether an output of `kind` matches the expected one, see
+    /// `ignores_whitespace`.
+    fn outputs_match(&self, kind: &str, expected: &str, actual: &str) -> bool {
+        if self.ignores_whitespace(kind) {
+            canonical_whitespace(expected) == canonical_whitespace(actual)
+        } else {
+            expected == actual
         }
+    }
+
+    /// Whether trailing whitespace and final newlines don't matter in outputs
+    /// of `kind`. That's the case for the plain stdout and stderr unless
+    /// `Config::strict_whitespace` is set. Colored outputs are compared byte
+    /// for byte, their escape sequences may well end a line.
+    fn ignores_whitespace(&self, kind: &str) -> bool {
+        !self.config.strict_whitespace &&
+            matches!(kind, "stdout" | "stderr" | "run.stdout" | "run.stderr")
+    }
+
+    fn compare_output(&self, kind: &str, actual: &str, expected: &str) -> TestResult<usize> {
+        if self.outputs_match(kind, expected, actual) {
+            return Ok(0);
+        }
+        // The actual output is saved in the canonical form as well.
+        let (actual, expected) = if self.ignores_whitespace(kind) {
+            (canonical_whitespace(actual), canonical_whitespace(expected))
+        } else {
+            (actual.to_owned(), expected.to_owned())
+        };
+        let (actual, expected) = (&*actual, &*expected);
 
         println!("normalized {}:\n{}\n", kind, actual);
         println!("expected {}:\n{}\n", kind, expected);
         println!("diff of {}:\n", kind);
 
-        for diff in diff::lines(expected, actual) {
-            match diff {
-                diff::Result::Left(l)    => println!("-{}", l),
-                diff::Result::Both(l, _) => println!(" {}", l),
-                diff::Result::Right(r)   => println!("+{}", r),
-            }
+        // Only whitespace can't be all that differs when it is ignored.
+        if !self.ignores_whitespace(kind) &&
+           canonical_whitespace(expected) == canonical_whitespace(actual) {
+            println!("(only whitespace differs, shown as `{}`, `{}` and `{}`)",
+                     SPACE_MARKER, TAB_MARKER, CR_MARKER);
+            print_line_diff(&show_whitespace(expected), &show_whitespace(actual));
+        } else if has_control_chars(expected) || has_control_chars(actual) {
+            // Make escape sequences (e.g. of colored output) readable.
+            print_line_diff(&escape_control_chars(expected), &escape_control_chars(actual));
+        } else {
+            print_line_diff(expected, actual);
         }
 
         let output_file = self.output_base_name().with_extension(kind);
         match File::create(&output_file).and_then(|mut f| f.write_all(actual.as_bytes())) {
             Ok(()) => { }
             Err(e) => {
-                self.fatal(&format!("failed to write {} to `{}`: {}",
-                                    kind, output_file.display(), e))
+                return Err(self.fatal(&format!("failed to write {} to `{}`: {}",
+                                               kind, output_file.display(), e)))
             }
         }
 
+        self.artifacts().record(&output_file, &format!("actual {}", kind), self.revision);
+
         println!("\nThe actual {0} differed from the expected {0}.", kind);
         println!("Actual {} saved to {}", kind, output_file.display());
-        1
+        self.print_artifacts_index();
+
+        if self.config.track_snapshot_versions {
+            self.check_snapshot_version(kind, &output_file);
+        }
+        Ok(1)
+    }
+
+    /// Write the version of the compiler which produced `output_file` to its
+    /// `.meta` file (so that it gets updated along with the expected output),
+    /// and note if the expected output was produced by a different version.
+    fn check_snapshot_version(&self, kind: &str, output_file: &Path) {
+        let current = match self.config.rustc_version() {
+            Some(version) => version,
+            None => return,
+        };
+
+        if write_snapshot_meta(output_file, &current) {
+            self.artifacts().record(&snapshot_meta_path(output_file),
+                                    &format!("compiler version of the actual {}", kind),
+                                    self.revision);
+        }
+
+        let expected_meta_path = snapshot_meta_path(&self.expected_output_path(kind));
+        let mut expected_meta = String::new();
+        if File::open(&expected_meta_path)
+            .and_then(|mut f| f.read_to_string(&mut expected_meta)).is_err() {
+            return;
+        }
+        match serde_json::from_str::<SnapshotMeta>(&expected_meta) {
+            Ok(ref expected) if expected.rustc_version != current => {
+                println!("note: the expected {} was produced by `{}`, \
+                          but the current compiler is `{}`",
+                         kind, expected.rustc_version, current);
+            }
+            Ok(_) => {}
+            Err(e) => {
+                println!("warning: failed to parse `{}`: {}", expected_meta_path.display(), e);
+            }
+        }
+    }
+}
+
+/// Sidecar file recording which compiler produced an output snapshot.
+#[derive(Serialize, Deserialize)]
+struct SnapshotMeta {
+    rustc_version: String,
+}
+
+fn snapshot_meta_path(snapshot: &Path) -> PathBuf {
+    let mut fname = snapshot.file_name().unwrap().to_os_string();
+    fname.push(".meta");
+    snapshot.with_file_name(fname)
+}
+
+/// Record that `snapshot` was produced by `rustc_version`, returning whether
+/// that worked.
+fn write_snapshot_meta(snapshot: &Path, rustc_version: &str) -> bool {
+    let meta = SnapshotMeta { rustc_version: rustc_version.to_owned() };
+    let meta_json = serde_json::to_string(&meta).unwrap();
+    match File::create(snapshot_meta_path(snapshot))
+        .and_then(|mut f| f.write_all(meta_json.as_bytes())) {
+        Ok(()) => true,
+        Err(e) => {
+            println!("warning: failed to write snapshot metadata for `{}`: {}",
+                     snapshot.display(), e);
+            false
+        }
     }
 }
 
@@ -2515,6 +4084,54 @@ struct ProcArgs {
     args: Vec<String>,
 }
 
+/// The command line running the test program `exe` with `run_flags` on
+/// `config.target`: the `runtool`, if any, then the runner the target needs
+/// (nodejs with `nodejs_args` and the `wasm_shim`, or the `wasm_runner`),
+/// then the program and its flags.
+pub fn run_args(config: &Config, exe: &str, run_flags: &[String]) -> Result<Vec<String>, String> {
+    let split_maybe_args = |argstr: &Option<String>| {
+        argstr.as_ref().map_or(Vec::new(), |s| util::split_args(s))
+    };
+
+    // If we've got another tool to run under (valgrind),
+    // then split apart its command
+    let mut args = split_maybe_args(&config.runtool);
+
+    // If this is emscripten, then run tests under nodejs
+    if config.target.contains("emscripten") {
+        if let Some(ref p) = config.nodejs {
+            args.push(p.clone());
+            args.extend(config.nodejs_args.iter().cloned());
+        } else {
+            return Err("no NodeJS binary found (--nodejs)".to_owned());
+        }
+    }
+
+    // If this is otherwise wasm, then run tests under the configured
+    // wasm runner, or under nodejs with the shim (if any)
+    if config.target.contains("wasm32") && !config.target.contains("emscripten") {
+        if config.wasm_runner.is_some() {
+            args.extend(split_maybe_args(&config.wasm_runner));
+        } else if let Some(ref p) = config.nodejs {
+            args.push(p.clone());
+            args.extend(config.nodejs_args.iter().cloned());
+            if let Some(ref shim) = config.wasm_shim {
+                args.push(shim.display().to_string());
+            }
+        } else {
+            return Err("no wasm runner (--wasm-runner) or NodeJS binary (--nodejs) found"
+                       .to_owned());
+        }
+    }
+
+    args.push(exe.to_owned());
+
+    // Add the arguments in the run_flags directive
+    args.extend(run_flags.iter().cloned());
+    Ok(args)
+}
+
+#[derive(Clone, Debug)]
 pub struct ProcRes {
     status: ExitStatus,
     stdout: String,
@@ -2527,6 +4144,30 @@ impl ProcRes {
         if let Some(e) = err {
             println!("\nerror: {}", e);
         }
+        self.print();
+        panic!();
+    }
+
+    /// A failure caused by this process, which is printed first.
+    pub fn failure(&self, kind: FailureKind, err: &str) -> Box<TestFailure> {
+        println!("\nerror: {}", err);
+        self.print();
+        Box::new(TestFailure {
+            proc_res: Some(Box::new(self.clone())),
+            ..TestFailure::new(kind, err)
+        })
+    }
+
+    /// The error printed when `main` returned `Err`: the first line of
+    /// stderr starting with `Error: `, if the program exited with code 1.
+    fn main_error(&self) -> Option<&str> {
+        if self.status.code() != Some(1) {
+            return None;
+        }
+        self.stderr.lines().find(|line| line.starts_with("Error: "))
+    }
+
+    fn print(&self) {
         print!("\
             status: {}\n\
             command: {}\n\
@@ -2541,7 +4182,6 @@ impl ProcRes {
             \n",
                self.status, self.cmdline, self.stdout,
                self.stderr);
-        panic!();
     }
 }
 
@@ -2569,6 +4209,228 @@ where
     }
 }
 
+/// Prints a line based diff of `expected` and `actual`, prefixing removed
+/// lines with `-` and added lines with `+`.
+fn print_line_diff(expected: &str, actual: &str) {
+    for diff in diff::lines(expected, actual) {
+        match diff {
+            diff::Result::Left(l)    => println!("-{}", l),
+            diff::Result::Both(l, _) => println!(" {}", l),
+            diff::Result::Right(r)   => println!("+{}", r),
+        }
+    }
+}
+
+/// `s` without trailing whitespace on its lines, and ending in exactly one
+/// newline unless it is empty, see `Config::strict_whitespace`. CRLF line
+/// endings become LF.
+fn canonical_whitespace(s: &str) -> String {
+    let lines: Vec<&str> = s.lines().map(|line| line.trim_end()).collect();
+    let joined = lines.join("\n");
+    let body = joined.trim_end_matches('\n');
+    if body.is_empty() {
+        String::new()
+    } else {
+        format!("{}\n", body)
+    }
+}
+
+const SPACE_MARKER: char = '\u{2420}';
+const TAB_MARKER: char = '\u{2409}';
+const CR_MARKER: char = '\u{240d}';
+
+/// Make the trailing whitespace of each line and a missing final newline
+/// visible, for diffs of outputs which only differ in them.
+fn show_whitespace(s: &str) -> String {
+    let mut shown = String::new();
+    for line in s.split('\n') {
+        let content = line.trim_end();
+        shown.push_str(content);
+        for c in line[content.len()..].chars() {
+            shown.push(match c {
+                ' ' => SPACE_MARKER,
+                '\t' => TAB_MARKER,
+                '\r' => CR_MARKER,
+                c => c,
+            });
+        }
+        shown.push('\n');
+    }
+    // `split` yields an empty last line for the final newline.
+    shown.pop();
+    if s.ends_with('\n') {
+        shown
+    } else {
+        format!("{}\n\\ no newline at end of output\n", shown)
+    }
+}
+
+fn has_control_chars(s: &str) -> bool {
+    s.chars().any(|c| c.is_control() && c != '\n' && c != '\t')
+}
+
+/// Replace control characters other than newlines and tabs by escapes like
+/// `\x1b`.
+fn escape_control_chars(s: &str) -> String {
+    s.chars().map(|c| {
+        if c.is_control() && c != '\n' && c != '\t' {
+            format!("\\x{:02x}", c as u32)
+        } else {
+            c.to_string()
+        }
+    }).collect()
+}
+
+/// The number of lines missing from or added to `expected` in `actual`.
+fn count_differing_lines(expected: &str, actual: &str) -> usize {
+    diff::lines(expected, actual).iter()
+        .filter(|line| !matches!(**line, diff::Result::Both(..)))
+        .count()
+}
+
+fn read_to_string(path: &Path) -> String {
+    let mut contents = String::new();
+    File::open(path).and_then(|mut f| f.read_to_string(&mut contents))
+        .unwrap_or_else(|e| panic!("failed to read `{}`: {}", path.display(), e));
+    contents
+}
+
+/// Whether the crate attributes in `source` make it a proc macro crate.
+fn is_proc_macro_source(source: &str) -> bool {
+    source.lines().map(str::trim).any(|line| {
+        line.starts_with("#![crate_type") && line.contains("\"proc-macro\"")
+    })
+}
+
+/// Whether `word` appears in `text` as a whole identifier.
+fn mentions_word(text: &str, word: &str) -> bool {
+    text.split(|c: char| !(c.is_alphanumeric() || c == '_')).any(|w| w == word)
+}
+
+#[cfg(unix)]
+fn exit_status_from_code(code: i32) -> ExitStatus {
+    use std::os::unix::process::ExitStatusExt;
+    ExitStatus::from_raw((code & 0xff) << 8)
+}
+
+#[cfg(windows)]
+fn exit_status_from_code(code: i32) -> ExitStatus {
+    use std::os::windows::process::ExitStatusExt;
+    ExitStatus::from_raw(code as u32)
+}
+
+/// A failure of `remote-test-client` itself, see `remote_test_client_error`.
+struct ClientError {
+    /// The line the client reported the problem with.
+    line: String,
+    /// Whether the client failed talking to the device, rather than e.g.
+    /// being invoked wrongly.
+    retryable: bool,
+}
+
+/// Recognizes failures of `remote-test-client` itself (as opposed to the
+/// remote program failing).
+///
+/// The client forwards the program's output and exit status when it managed
+/// to run it. Otherwise it exits unsuccessfully after reporting the problem
+/// on a line of its own starting with `remote-test-client: error:`. Only
+/// such lines are looked at, so that the program's output can't be mistaken
+/// for them.
+fn remote_test_client_error(proc_res: &ProcRes) -> Option<ClientError> {
+    const PREFIX: &str = "remote-test-client: error:";
+    // Problems reaching the device, as opposed to problems of the client.
+    const TRANSPORT_ERRORS: &[&str] = &[
+        "failed to connect",
+        "connection refused",
+        "connection reset",
+        "failed to upload",
+    ];
+
+    if proc_res.status.success() {
+        return None;
+    }
+    let line = proc_res.stderr.lines().find(|line| line.starts_with(PREFIX))?;
+    let problem = line[PREFIX.len()..].to_lowercase();
+    Some(ClientError {
+        line: line.trim().to_owned(),
+        retryable: TRANSPORT_ERRORS.iter().any(|e| problem.contains(e)),
+    })
+}
+
+/// The mode an incremental test revision is checked in, derived from its
+/// name: `rpass1` must build and run, `rfail2` must build and fail at runtime,
+/// `cfail3` must fail to compile with the errors annotated for it.
+fn incremental_revision_kind(revision: &str) -> Option<Mode> {
+    if revision.starts_with("rpass") {
+        Some(RunPass)
+    } else if revision.starts_with("rfail") {
+        Some(RunFail)
+    } else if revision.starts_with("cfail") {
+        Some(CompileFail)
+    } else {
+        None
+    }
+}
+
+/// Counts the warnings in the (human readable or JSON) stderr of a compiler
+/// run, not counting the "N warnings emitted" summary.
+fn count_warnings(stderr: &str) -> usize {
+    stderr.lines()
+          .filter(|line| {
+              let is_warning = line.starts_with("warning") ||
+                  (line.starts_with('{') && line.contains("\"level\":\"warning\""));
+              is_warning && !line.contains("warnings emitted") && !line.contains("warning emitted")
+          })
+          .count()
+}
+
+/// Matches the path of a file the compiler wrote the full name of a type to,
+/// `foo.long-type-1234.txt` in its output directory.
+fn long_type_file_regex() -> Regex {
+    Regex::new(r#"[^'"\s]*\.long-type-\d+\.txt"#).unwrap()
+}
+
+/// The files the compiler wrote full type names to, in the order `output`
+/// mentions them first.
+fn long_type_files(output: &str) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for found in long_type_file_regex().find_iter(output) {
+        // Backslashes are escaped in JSON output.
+        let path = PathBuf::from(found.as_str().replace("\\\\", "\\"));
+        if !files.contains(&path) {
+            files.push(path);
+        }
+    }
+    files
+}
+
+/// Replace the parts of sanitizer reports which change from run to run: the
+/// process id in `==1234==` prefixes and addresses.
+fn normalize_sanitizer_report(output: &str) -> String {
+    let mut normalized = String::with_capacity(output.len());
+    for line in output.split_inclusive('\n') {
+        let mut rest = line;
+        if rest.starts_with("==") {
+            let pid_len = rest[2..].chars().take_while(|c| c.is_ascii_digit()).count();
+            if pid_len > 0 && rest[2 + pid_len..].starts_with("==") {
+                normalized.push_str("==PID==");
+                rest = &rest[4 + pid_len..];
+            }
+        }
+        while let Some(i) = rest.find("0x") {
+            normalized.push_str(&rest[..i + 2]);
+            rest = &rest[i + 2..];
+            let hex_len = rest.chars().take_while(|c| c.is_ascii_hexdigit()).count();
+            if hex_len > 0 {
+                normalized.push_str("$ADDR");
+                rest = &rest[hex_len..];
+            }
+        }
+        normalized.push_str(rest);
+    }
+    normalized
+}
+
 fn normalize_mir_line(line: &str) -> String {
     nocomment_mir_line(line).replace(char::is_whitespace, "")
 }
@@ -2582,7 +4444,10 @@ fn nocomment_mir_line(line: &str) -> &str {
     }
 }
 
-fn read2_abbreviated(mut child: Child) -> io::Result<Output> {
+/// Reads the output of `child` until it exits and closes its pipes. If it runs
+/// in `group`, everything it left in there is killed as soon as it exited, so
+/// that nothing keeps the pipes open.
+fn read2_abbreviated(mut child: Child, group: Option<&ProcessGroup>) -> io::Result<Output> {
     use std::mem::replace;
     use read2::read2;
 
@@ -2646,9 +4511,18 @@ fn read2_abbreviated(mut child: Child) -> io::Result<Output> {
     let mut stderr = ProcOutput::Full(Vec::new());
 
     drop(child.stdin.take());
-    read2(child.stdout.take().unwrap(), child.stderr.take().unwrap(), &mut |is_stdout, data, _| {
+    let (out_pipe, err_pipe) = (child.stdout.take().unwrap(), child.stderr.take().unwrap());
+    read2(out_pipe, err_pipe, &mut |is_stdout, data, _| {
         if is_stdout { &mut stdout } else { &mut stderr }.extend(data);
         data.clear();
+    }, &mut || {
+        let exited = child.try_wait().map(|status| status.is_some()).unwrap_or(false);
+        if exited {
+            if let Some(group) = group {
+                group.kill();
+            }
+        }
+        exited
     })?;
     let status = child.wait()?;
 
@@ -0,0 +1,245 @@
+// Copyright 2012-2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Skipping tests whose inputs didn't change since they last passed, for
+//! `Config::skip_up_to_date`.
+//!
+//! While such a test runs, rustc writes dep-info for each compilation of it.
+//! Once it passed, the files listed there (plus the test's expected outputs
+//! and auxiliary crates) are recorded next to its stamp, together with a
+//! fingerprint of the configuration. The test is up to date as long as the
+//! fingerprint matches and none of these files is newer than the stamp. A
+//! missing or unreadable record always means running the test again.
+//!
+//! Selecting tests by their changed inputs (`Config::changed_since` and
+//! `tests_touching_paths`) can't rely on dep-info, since tests which never ran
+//! have none. It goes by the test's headers instead, see `test_inputs`.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use filetime::FileTime;
+
+use common::{Config, TestPaths};
+use header::TestProps;
+
+/// The reason given for ignoring an up to date test.
+pub const UP_TO_DATE: &str = "up to date (none of its dependencies changed since it passed)";
+
+fn deps_path(config: &Config, testpaths: &TestPaths) -> PathBuf {
+    let mut path = ::stamp(config, testpaths).into_os_string();
+    path.push(".deps");
+    PathBuf::from(path)
+}
+
+/// Forget the dependencies of a test, so that it isn't considered up to date
+/// unless it passes again.
+pub fn forget(config: &Config, testpaths: &TestPaths) {
+    let _ = fs::remove_file(deps_path(config, testpaths));
+}
+
+/// Record the dependencies of a test which passed, from the dep-info files
+/// written while compiling it. Nothing is recorded if any of them is missing
+/// or can't be parsed.
+pub fn record(config: &Config, testpaths: &TestPaths, dep_infos: &[PathBuf]) {
+    let mut deps = Vec::new();
+    for dep_info in dep_infos {
+        match fs::read_to_string(dep_info).ok().and_then(|s| parse_dep_info(&s)) {
+            Some(paths) => deps.extend(paths),
+            None => return,
+        }
+    }
+    deps.extend(other_inputs(config, testpaths));
+
+    let mut contents = format!("{:x}\n", fingerprint(config));
+    for dep in deps {
+        match dep.canonicalize().ok().and_then(|p| p.to_str().map(str::to_owned)) {
+            Some(dep) => {
+                contents.push_str(&dep);
+                contents.push('\n');
+            }
+            None => return,
+        }
+    }
+    let _ = fs::write(deps_path(config, testpaths), contents);
+}
+
+/// Whether the test passed before and none of its dependencies changed since.
+pub fn is_up_to_date(config: &Config, testpaths: &TestPaths) -> bool {
+    let stamp_time = match mtime(&::stamp(config, testpaths)) {
+        Some(time) => time,
+        None => return false,
+    };
+    let contents = match fs::read_to_string(deps_path(config, testpaths)) {
+        Ok(contents) => contents,
+        Err(_) => return false,
+    };
+    let mut lines = contents.lines();
+    if lines.next() != Some(&*format!("{:x}", fingerprint(config))) {
+        return false;
+    }
+    // A compiler built locally may still print the same version after it was
+    // rebuilt.
+    if mtime(&config.rustc_path).map_or(false, |time| time > stamp_time) {
+        return false;
+    }
+    lines.all(|dep| mtime(Path::new(dep)).map_or(false, |time| time <= stamp_time))
+}
+
+/// The files listed in a dep-info file. rustc adds an empty rule for each of
+/// them, which (unlike the rule for the output) can be parsed without caring
+/// about colons in Windows paths.
+fn parse_dep_info(contents: &str) -> Option<Vec<PathBuf>> {
+    let deps: Vec<PathBuf> = contents.lines()
+        .map(str::trim)
+        .filter(|line| !line.starts_with('#') && line.ends_with(':'))
+        .map(|line| PathBuf::from(line[..line.len() - 1].replace("\\ ", " ")))
+        .collect();
+    if deps.is_empty() {
+        None
+    } else {
+        Some(deps)
+    }
+}
+
+/// Inputs of a test which don't show up in dep-info: its expected outputs
+/// and the auxiliary crates next to it or named by its headers.
+fn other_inputs(config: &Config, testpaths: &TestPaths) -> Vec<PathBuf> {
+    let mut inputs = expected_outputs(testpaths);
+    collect_files(&testpaths.file.parent().unwrap().join("auxiliary"), &mut inputs);
+    for path in aux_sources(config, testpaths) {
+        if !inputs.contains(&path) {
+            inputs.push(path);
+        }
+    }
+    inputs
+}
+
+/// The expected outputs of a test (`foo.stderr`, `foo.rev.stdout`, ...).
+fn expected_outputs(testpaths: &TestPaths) -> Vec<PathBuf> {
+    let mut outputs = Vec::new();
+    let dir = testpaths.file.parent().unwrap();
+    let prefix = format!("{}.", testpaths.file.file_stem().unwrap().to_string_lossy());
+    if let Ok(entries) = fs::read_dir(dir) {
+        for entry in entries.filter_map(Result::ok) {
+            if entry.file_name().to_string_lossy().starts_with(&prefix) {
+                outputs.push(entry.path());
+            }
+        }
+    }
+    outputs
+}
+
+/// The files a test is made of: the test itself (every file of a run-make
+/// test), its expected outputs and the sources of the auxiliary crates its
+/// headers name, in any revision.
+pub fn test_inputs(config: &Config, testpaths: &TestPaths) -> Vec<PathBuf> {
+    let mut inputs = Vec::new();
+    if testpaths.file.is_dir() {
+        collect_files(&testpaths.file, &mut inputs);
+        return inputs;
+    }
+    inputs.push(testpaths.file.clone());
+    inputs.extend(expected_outputs(testpaths));
+    inputs.extend(aux_sources(config, testpaths));
+    inputs
+}
+
+/// The sources of the auxiliary crates the headers of a test name, in any
+/// revision.
+fn aux_sources(config: &Config, testpaths: &TestPaths) -> Vec<PathBuf> {
+    let mut sources = Vec::new();
+    let aux_dir = testpaths.file.parent().unwrap().join("auxiliary");
+    let base_props = TestProps::from_file(&testpaths.file, None, config);
+    let mut all_props = base_props.revisions.iter()
+        .map(|rev| TestProps::from_file(&testpaths.file, Some(rev), config))
+        .collect::<Vec<_>>();
+    all_props.push(base_props);
+    for props in &all_props {
+        let aux_sources = props.aux_builds.iter()
+            .chain(&props.proc_macros)
+            .chain(props.aux_crates.iter().map(|(_, path)| path));
+        for rel in aux_sources {
+            let path = config.aux_file(&testpaths.file, rel).unwrap_or_else(|| aux_dir.join(rel));
+            if !sources.contains(&path) {
+                sources.push(path);
+            }
+        }
+    }
+    sources
+}
+
+/// Whether any of the `test_inputs` was modified after `time`.
+pub fn changed_since(config: &Config, testpaths: &TestPaths, time: SystemTime) -> bool {
+    test_inputs(config, testpaths).iter()
+        .filter_map(|path| fs::metadata(path).and_then(|m| m.modified()).ok())
+        .any(|modified| modified > time)
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    if let Ok(entries) = fs::read_dir(dir) {
+        for entry in entries.filter_map(Result::ok) {
+            let path = entry.path();
+            if path.is_dir() {
+                collect_files(&path, files);
+            } else {
+                files.push(path);
+            }
+        }
+    }
+}
+
+/// The parts of the configuration which change how tests are compiled.
+fn fingerprint(config: &Config) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    config.mode.to_string().hash(&mut hasher);
+    config.rustc_path.hash(&mut hasher);
+    rustc_verbose_version(config).hash(&mut hasher);
+    config.target.hash(&mut hasher);
+    config.host.hash(&mut hasher);
+    config.stage_id.hash(&mut hasher);
+    config.target_rustcflags.hash(&mut hasher);
+    config.host_rustcflags.hash(&mut hasher);
+    config.edition.hash(&mut hasher);
+    config.force_check_only.hash(&mut hasher);
+    config.run.hash(&mut hasher);
+    config.codegen_backend.hash(&mut hasher);
+    config.strict_whitespace.hash(&mut hasher);
+    config.aux_base.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Results of `rustc_verbose_version`, by compiler.
+static RUSTC_VERBOSE_VERSIONS: Mutex<Option<HashMap<PathBuf, Option<String>>>> =
+    Mutex::new(None);
+
+/// What `rustc -vV` prints. Unlike `rustc_path`, this changes when a rustup
+/// proxy runs another toolchain. This is only run once per compiler.
+fn rustc_verbose_version(config: &Config) -> Option<String> {
+    let mut versions = RUSTC_VERBOSE_VERSIONS.lock().unwrap();
+    let versions = versions.get_or_insert_with(HashMap::new);
+    versions.entry(config.rustc_path.clone()).or_insert_with(|| {
+        let output = Command::new(&config.rustc_path).arg("-vV").output().ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        Some(String::from_utf8_lossy(&output.stdout).into_owned())
+    }).clone()
+}
+
+fn mtime(path: &Path) -> Option<FileTime> {
+    fs::metadata(path).ok().map(|m| FileTime::from_last_modification_time(&m))
+}
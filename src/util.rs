@@ -9,6 +9,7 @@
 // except according to those terms.
 
 use std::env;
+use std::mem;
 use common::Config;
 
 /// Conversion table from triple OS name to Rust SYSNAME
@@ -51,6 +52,26 @@ const ARCH_TABLE: &'static [(&'static str, &'static str)] = &[
     ("wasm32", "wasm32"),
 ];
 
+/// Architectures (the first component of a triple) which are big-endian, all
+/// others are little-endian
+const BIG_ENDIAN_TABLE: &[&str] = &[
+    "aarch64_be",
+    "armeb",
+    "armebv7r",
+    "m68k",
+    "mips",
+    "mips64",
+    "mipsisa32r6",
+    "mipsisa64r6",
+    "powerpc",
+    "powerpc64",
+    "s390x",
+    "sparc",
+    "sparc64",
+    "sparcv9",
+    "thumbeb",
+];
+
 pub fn matches_os(triple: &str, name: &str) -> bool {
     // For the wasm32 bare target we ignore anything also ignored on emscripten
     // and then we also recognize `wasm32-bare` as the os for the target
@@ -77,6 +98,12 @@ pub fn get_env(triple: &str) -> Option<&str> {
     triple.split('-').nth(3)
 }
 
+/// Whether crates can be linked dynamically for the target `triple`. Targets
+/// like MUSL, wasm32 and Emscripten only support static linking.
+pub fn target_supports_dylibs(triple: &str) -> bool {
+    !(triple.contains("musl") || triple.contains("wasm32") || triple.contains("emscripten"))
+}
+
 pub fn get_pointer_width(triple: &str) -> &'static str {
     if (triple.contains("64") && !triple.ends_with("gnux32")) || triple.starts_with("s390x") {
         "64bit"
@@ -85,6 +112,17 @@ pub fn get_pointer_width(triple: &str) -> &'static str {
     }
 }
 
+/// The byte order of the target `triple`, as matched by `ignore-endian-big`
+/// and the like.
+pub fn get_endian(triple: &str) -> &'static str {
+    let arch = triple.split('-').next().unwrap();
+    if BIG_ENDIAN_TABLE.contains(&arch) {
+        "endian-big"
+    } else {
+        "endian-little"
+    }
+}
+
 pub fn make_new_path(path: &str) -> String {
     assert!(cfg!(windows));
     // Windows just uses PATH as the library search path, so we have to
@@ -108,3 +146,114 @@ pub fn logv(config: &Config, s: String) {
         println!("{}", s);
     }
 }
+
+/// Remove duplicate variables from `env`, a list of settings in increasing
+/// order of precedence: a later setting of a variable overrides an earlier
+/// one, but keeps its position.
+pub fn merge_env(config: &Config, env: Vec<(String, String)>) -> Vec<(String, String)> {
+    let mut merged: Vec<(String, String)> = Vec::with_capacity(env.len());
+    for (key, value) in env {
+        match merged.iter().position(|(k, _)| *k == key) {
+            Some(i) => {
+                logv(config, format!("`{}={}` overrides `{}={}`",
+                                     key, value, merged[i].0, merged[i].1));
+                merged[i].1 = value;
+            }
+            None => merged.push((key, value)),
+        }
+    }
+    merged
+}
+
+/// Split command line arguments like a shell would: at whitespace, except
+/// within single or double quotes, which are removed (`""` is an empty
+/// argument). A backslash escapes a following quote or whitespace, and
+/// within double quotes a following double quote. Any other backslash is
+/// kept, so that Windows paths don't need escaping.
+pub fn split_args(s: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut arg = String::new();
+    // Whether `arg` was started, even if it's still empty.
+    let mut in_arg = false;
+    let mut quote = None;
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some('\''), '\'') | (Some('"'), '"') => quote = None,
+            (Some('\''), _) => arg.push(c),
+            (Some(_), '\\') if chars.peek() == Some(&'"') => arg.extend(chars.next()),
+            (Some(_), _) => arg.push(c),
+            (None, '\\') if chars.peek().map_or(false, |&next| {
+                next == '"' || next == '\'' || next.is_whitespace()
+            }) => {
+                arg.extend(chars.next());
+                in_arg = true;
+            }
+            (None, '"') | (None, '\'') => {
+                quote = Some(c);
+                in_arg = true;
+            }
+            (None, _) if c.is_whitespace() => {
+                if in_arg {
+                    args.push(mem::take(&mut arg));
+                    in_arg = false;
+                }
+            }
+            (None, _) => {
+                arg.push(c);
+                in_arg = true;
+            }
+        }
+    }
+    if in_arg {
+        args.push(arg);
+    }
+    args
+}
+
+/// Quote `arg` if needed, so that `split_args` gives it back unchanged.
+pub fn quote_arg(arg: &str) -> String {
+    if !arg.is_empty() && !arg.contains(|c: char| c.is_whitespace() || c == '"' || c == '\'') {
+        return arg.to_owned();
+    }
+    if arg.contains('\'') {
+        format!("\"{}\"", arg.replace("\"", "\\\""))
+    } else {
+        format!("'{}'", arg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::Config;
+    use super::{get_endian, merge_env};
+
+    fn env(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter().map(|&(k, v)| (k.to_owned(), v.to_owned())).collect()
+    }
+
+    #[test]
+    fn merged_env() {
+        let config = Config::default();
+        // Later settings win, but stay where the variable was first set.
+        assert_eq!(merge_env(&config, env(&[("A", "1"), ("B", "2"), ("A", "3"), ("C", "4"),
+                                            ("B", "5")])),
+                   env(&[("A", "3"), ("B", "5"), ("C", "4")]));
+        assert_eq!(merge_env(&config, vec![]), env(&[]));
+    }
+
+    #[test]
+    fn endian() {
+        for &triple in &["powerpc64-unknown-linux-gnu", "s390x-unknown-linux-gnu",
+                         "sparc64-unknown-netbsd", "mips-unknown-linux-gnu",
+                         "mips64-unknown-linux-gnuabi64", "powerpc-unknown-linux-gnu"] {
+            assert_eq!(get_endian(triple), "endian-big", "{}", triple);
+        }
+        for &triple in &["x86_64-unknown-linux-gnu", "i686-pc-windows-msvc",
+                         "aarch64-apple-darwin", "powerpc64le-unknown-linux-gnu",
+                         "mipsel-unknown-linux-gnu", "mips64el-unknown-linux-gnuabi64",
+                         "wasm32-unknown-unknown", "armv7-unknown-linux-gnueabihf"] {
+            assert_eq!(get_endian(triple), "endian-little", "{}", triple);
+        }
+    }
+}
@@ -0,0 +1,7 @@
+// failure-status: 1
+
+fn main() {
+    let _x: u32 = ""; //~ ERROR@19 mismatched types
+    let _y: u32 = "";
+    //~^^^^^^ ERROR@5:19 mismatched types
+}
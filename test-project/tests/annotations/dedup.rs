@@ -0,0 +1,11 @@
+// dedup-diagnostics
+
+fn generic<T>(value: T) -> T {
+    let unused = 0; //~ WARN unused variable
+    value
+}
+
+fn main() {
+    generic(0u8);
+    generic("two instances");
+}
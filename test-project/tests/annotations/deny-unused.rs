@@ -0,0 +1,8 @@
+// compile-flags: -D unused-variables --error-format json
+// failure-status: 1
+
+// The test's own lint level and error format are used as they are.
+
+fn main() {
+    let x = 1; //~ ERROR unused variable: `x`
+}
@@ -0,0 +1,12 @@
+// failure-status: 1
+
+fn main() {
+    //~v ERROR mismatched types
+    let _x: u32 = "";
+    //~vvv ERROR mismatched types
+    //~| ERROR mismatched types
+
+    let _y: (u32, u32) = ("", "");
+    let _z: u32 = "";
+    //~^ ERROR mismatched types
+}
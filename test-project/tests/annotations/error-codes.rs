@@ -0,0 +1,6 @@
+// failure-status: 1
+
+fn main() {
+    let _x: u32 = ""; //~ ERROR[E0308]
+    let _y: u32 = MISSING; //~ ERROR E0425: cannot find value
+}
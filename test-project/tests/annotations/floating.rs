@@ -0,0 +1,17 @@
+// failure-status: 1
+
+// The error in `g` is only expected by the annotation at the end of the file.
+
+fn f() -> u32 {
+    MISSING //~ ERROR cannot find value `MISSING`
+}
+
+fn g() -> u32 {
+    MISSING
+}
+
+fn main() {
+    f() + g();
+}
+
+//~? ERROR cannot find value `MISSING`
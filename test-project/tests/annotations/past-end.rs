@@ -0,0 +1,3 @@
+fn main() {}
+
+//~vv ERROR nothing down here
@@ -0,0 +1,12 @@
+// revisions: a b
+// failure-status: 1
+
+#[cfg(a)]
+const VALUE: &str = "a";
+
+fn main() {
+    let _x: u32 = VALUE; //[a]~ ERROR mismatched types
+                         //[b]~^ ERROR cannot find value `VALUE`
+    let _y: u32 = (); //~ ERROR mismatched types
+    let _z: () = 1; //[a,b]~ ERROR mismatched types
+}
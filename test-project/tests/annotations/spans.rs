@@ -0,0 +1,7 @@
+fn main() {
+    let mut x = 0;
+    let a = &mut x;
+    let b = &mut x; //~ ERROR[spans=3] cannot borrow `x` as mutable more than once
+    *a += 1;
+    *b += 1;
+}
@@ -0,0 +1,9 @@
+// failure-status: 1
+
+fn main() {
+    let _ = "not an annotation //~ ERROR nope";
+    let _ = r#"neither "//~ ERROR" nor this"#;
+    let _ = "an escaped \" quote //~ ERROR nope";
+    let _ = (b"bytes //~ ERROR nope", br"raw bytes //~ ERROR nope", '"');
+    let _: u32 = "\\"; //~ ERROR mismatched types
+}
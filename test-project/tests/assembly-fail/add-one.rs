@@ -0,0 +1,8 @@
+// only-x86_64
+// compile-flags: -C opt-level=3 --crate-type lib
+
+// CHECK: add_two:
+#[no_mangle]
+pub fn add_one(x: u32) -> u32 {
+    x + 1
+}
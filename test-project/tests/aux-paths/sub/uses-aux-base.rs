@@ -0,0 +1,7 @@
+// aux-build:base_helper.rs
+
+extern crate base_helper;
+
+fn main() {
+    assert_eq!(base_helper::answer(), 43);
+}
@@ -0,0 +1,4 @@
+#[cfg(a)]
+pub const REVISION: &str = "a";
+#[cfg(b)]
+pub const REVISION: &str = "b";
@@ -0,0 +1,14 @@
+// revisions: a b
+// aux-build:shared.rs
+// aux-build:per-revision.rs
+
+extern crate shared;
+extern crate per_revision;
+
+fn main() {
+    assert_eq!(shared::answer(), 42);
+    #[cfg(a)]
+    assert_eq!(per_revision::REVISION, "a");
+    #[cfg(b)]
+    assert_eq!(per_revision::REVISION, "b");
+}
@@ -0,0 +1,10 @@
+// revisions: a b
+// Copied to a temporary directory and blessed by the `bless` test.
+
+#[cfg(a)]
+fn main() {
+    let _x: u32 = "a";
+}
+
+#[cfg(b)]
+fn main() {}
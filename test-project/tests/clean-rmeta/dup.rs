@@ -0,0 +1,4 @@
+// Built twice by the `clean_rmeta` test, once only as rmeta.
+pub fn answer() -> u32 {
+    42
+}
@@ -0,0 +1,6 @@
+// check-pass
+extern crate dup;
+
+fn main() {
+    assert_eq!(dup::answer(), 42);
+}
@@ -0,0 +1,13 @@
+// compile-flags: -Zprint-mono-items=yes --crate-type lib
+// rustc-env:RUSTC_BOOTSTRAP=1
+
+//~ MONO_ITEM fn generic::<u8>
+//~ MONO_ITEM fn generic::<u32>
+pub fn generic<T>(t: T) -> T {
+    t
+}
+
+//~ MONO_ITEM fn user
+pub fn user() -> (u8, u32) {
+    (generic(1), generic(2))
+}
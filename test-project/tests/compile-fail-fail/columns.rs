@@ -0,0 +1,5 @@
+// failure-status: 1
+
+fn main() {
+    let _x: u32 = ""; //~ ERROR@15 mismatched types
+}
@@ -0,0 +1,5 @@
+// failure-status: 1
+
+fn main() {
+    let _: (u32, u32) = ("", 0); //~ ERROR mismatched types (x2)
+}
@@ -0,0 +1,10 @@
+// failure-status: 1
+
+// Only one of the two annotations at the end of the file is matched.
+
+fn main() {
+    let _x: u32 = "";
+}
+
+//~? ERROR mismatched types
+//~? ERROR mismatched types
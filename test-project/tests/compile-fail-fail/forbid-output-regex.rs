@@ -0,0 +1,9 @@
+// failure-status: 1
+// error-pattern: mismatched types
+// forbid-output-regex: error\[E\d+\]
+
+// The error has a code, which is forbidden.
+
+fn main() {
+    let _x: u32 = "a";
+}
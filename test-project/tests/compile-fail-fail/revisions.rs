@@ -0,0 +1,11 @@
+// revisions: a b
+// failure-status: 1
+
+// Revision `b` expects the error of revision `a`.
+
+#[cfg(a)]
+const VALUE: &str = "a";
+
+fn main() {
+    let _x: u32 = VALUE; //[a,b]~ ERROR mismatched types
+}
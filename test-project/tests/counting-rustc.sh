@@ -0,0 +1,4 @@
+#!/bin/sh
+# Runs rustc, logging its arguments to `invocations` next to this script.
+echo "$@" >> "$(dirname "$0")/invocations"
+exec rustc "$@"
@@ -0,0 +1,7 @@
+// aux-build:helper.rs
+
+extern crate helper;
+
+fn main() {
+    assert_eq!(helper::answer(), 42);
+}
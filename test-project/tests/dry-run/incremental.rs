@@ -0,0 +1,4 @@
+// revisions: a b
+// incremental
+
+fn main() {}
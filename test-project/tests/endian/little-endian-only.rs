@@ -0,0 +1,7 @@
+// ignore-endian-big
+
+// The expected bytes are little-endian.
+
+fn main() {
+    assert_eq!(0x0102u16.to_ne_bytes(), [2, 1]);
+}
@@ -0,0 +1,3 @@
+// exclusive
+
+fn main() {}
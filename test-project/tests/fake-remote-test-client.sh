@@ -0,0 +1,16 @@
+#!/bin/sh
+# A stand-in for remote-test-client which runs the program right here, invoked
+# as `fake-remote-test-client.sh run <program>[:<support library>...] <args>...`.
+# `FAKE_REMOTE_TEST_CLIENT` makes it fail instead: `refused` as if the device
+# was gone, `broken` as if the client itself was broken.
+case "$FAKE_REMOTE_TEST_CLIENT" in
+refused)
+    echo "remote-test-client: error: failed to connect to 127.0.0.1:12345: Connection refused" >&2
+    exit 1 ;;
+broken)
+    echo "remote-test-client: error: unsupported command" >&2
+    exit 1 ;;
+esac
+program=${2%%:*}
+shift 2
+exec "$program" "$@"
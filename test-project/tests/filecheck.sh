@@ -0,0 +1,9 @@
+#!/bin/sh
+# A stand-in for FileCheck, invoked as `filecheck.sh --input-file <input> <test>`:
+# each `// CHECK: <text>` line of the test has to appear in the input.
+grep '// CHECK: ' "$3" | sed 's@.*// CHECK: @@' | while read -r pattern; do
+    if ! grep -qF -- "$pattern" "$2"; then
+        echo "error: CHECK: expected string not found in input: $pattern" >&2
+        exit 1
+    fi
+done
@@ -0,0 +1,5 @@
+// no-run
+
+fn main() {
+    panic!("the program isn't run");
+}
@@ -0,0 +1,4 @@
+// only-linux
+// ignore-x86_64
+
+fn main() {}
@@ -0,0 +1,4 @@
+// only-linux
+// only-x86_64
+
+fn main() {}
@@ -0,0 +1,3 @@
+// only-linux
+
+fn main() {}
@@ -0,0 +1,3 @@
+// force-prefer-dynamic
+
+fn main() {}
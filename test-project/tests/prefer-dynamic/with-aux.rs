@@ -0,0 +1,7 @@
+// aux-build:lib.rs
+
+extern crate lib;
+
+fn main() {
+    lib::f();
+}
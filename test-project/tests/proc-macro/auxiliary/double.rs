@@ -0,0 +1,8 @@
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+
+#[proc_macro]
+pub fn double(input: TokenStream) -> TokenStream {
+    format!("2 * ({})", input).parse().unwrap()
+}
@@ -0,0 +1,8 @@
+// proc-macro: double.rs
+
+#[macro_use]
+extern crate double;
+
+fn main() {
+    assert_eq!(double!(20 + 1), 42);
+}
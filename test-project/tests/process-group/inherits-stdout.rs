@@ -0,0 +1,10 @@
+use std::fs;
+use std::process::Command;
+
+// Leaves a process behind which keeps our stdout and stderr open, and tells
+// the test which one.
+fn main() {
+    let sleeper = Command::new("sleep").arg("60").spawn().unwrap();
+    let pid_file = std::env::temp_dir().join("compiletest-process-group-inherited.pid");
+    fs::write(pid_file, sleeper.id().to_string()).unwrap();
+}
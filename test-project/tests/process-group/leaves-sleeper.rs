@@ -0,0 +1,15 @@
+use std::fs;
+use std::process::{Command, Stdio};
+
+// Leaves a process behind, and tells the test which one. It doesn't get our
+// stdout and stderr, so that it doesn't hold up compiletest when it isn't
+// killed.
+fn main() {
+    let sleeper = Command::new("sleep").arg("60")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let pid_file = std::env::temp_dir().join("compiletest-process-group.pid");
+    fs::write(pid_file, sleeper.id().to_string()).unwrap();
+}
@@ -0,0 +1,9 @@
+// error-pattern-regex: index \d+ is out of bounds
+
+// The panic message says it differently, so the test has to fail.
+
+fn main() {
+    let v: Vec<u8> = Vec::new();
+    let i = std::env::args().count() + 9;
+    v[i];
+}
@@ -0,0 +1,8 @@
+// run-fail-result
+// error-pattern: could not frobnicate
+
+// A panic isn't an error returned from `main`.
+
+fn main() -> Result<(), String> {
+    panic!("could not frobnicate");
+}
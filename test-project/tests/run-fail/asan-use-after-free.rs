@@ -0,0 +1,22 @@
+// Copyright 2018 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// needs-sanitizer-address
+// only-linux
+// only-x86_64
+// compile-flags: -Z sanitizer=address -C opt-level=0
+// error-pattern: AddressSanitizer: heap-use-after-free
+
+fn main() {
+    let b = Box::new(42u32);
+    let p = &*b as *const u32;
+    drop(b);
+    println!("{}", unsafe { *p });
+}
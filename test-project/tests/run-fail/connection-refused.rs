@@ -0,0 +1,5 @@
+// error-pattern: Connection refused
+
+fn main() {
+    panic!("failed to connect: Connection refused");
+}
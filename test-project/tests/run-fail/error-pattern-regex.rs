@@ -0,0 +1,8 @@
+// error-pattern-regex: index out of bounds: the len is \d+ but the index is \d+
+// error-pattern: main
+
+fn main() {
+    let v: Vec<u8> = Vec::new();
+    let i = std::env::args().count() + 9;
+    v[i];
+}
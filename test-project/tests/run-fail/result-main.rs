@@ -0,0 +1,7 @@
+// run-fail-result
+
+// Returning an error from `main` exits with 1 and prints `Error: ...`.
+
+fn main() -> Result<(), String> {
+    Err(format!("could not frobnicate {} widgets", 3))
+}
@@ -0,0 +1,15 @@
+//@ revisions: a b
+//@[a] run-flags: --only-a
+//@ rustc-env:SYNTAX=at
+// exec-env:LEGACY=1
+
+// Directives in `//@` comments, and a legacy one.
+
+use std::env;
+
+fn main() {
+    assert_eq!(env!("SYNTAX"), "at");
+    let args: Vec<String> = env::args().skip(1).collect();
+    assert_eq!(args.len(), if cfg!(a) { 1 } else { 0 });
+    assert_eq!(env::var("LEGACY").ok(), Some("1".to_owned()));
+}
@@ -0,0 +1,5 @@
+// ignore-release
+
+fn main() {
+    assert!(cfg!(debug_assertions));
+}
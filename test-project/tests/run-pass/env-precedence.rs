@@ -0,0 +1,23 @@
+// revisions: a b
+//[a] exec-env:OVERRIDDEN=a
+// exec-env:SHARED=first
+// exec-env:OVERRIDDEN=all
+// exec-env:SHARED=second
+//[b] rustc-env:COMPILE_TIME=b
+// rustc-env:COMPILE_TIME=all
+
+use std::env;
+
+fn main() {
+    assert_eq!(env::var("SHARED").unwrap(), "second");
+    #[cfg(a)]
+    {
+        assert_eq!(env::var("OVERRIDDEN").unwrap(), "a");
+        assert_eq!(env!("COMPILE_TIME"), "all");
+    }
+    #[cfg(b)]
+    {
+        assert_eq!(env::var("OVERRIDDEN").unwrap(), "all");
+        assert_eq!(env!("COMPILE_TIME"), "b");
+    }
+}
@@ -0,0 +1,11 @@
+// compile-flags: -L {{sysroot}}/lib/rustlib/{{target}}/lib
+// rustc-env:TARGET_LIB={{sysroot}}/lib/rustlib/{{target}}/lib
+// exec-env:HOST={{host}}
+
+use std::env;
+use std::path::Path;
+
+fn main() {
+    assert!(Path::new(env!("TARGET_LIB")).is_dir());
+    assert!(!env::var("HOST").unwrap().contains("{{"));
+}
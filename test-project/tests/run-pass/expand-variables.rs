@@ -0,0 +1,15 @@
+// rustc-env:SRC_BASE={{src-base}}
+// exec-env:DATA_DIR={{src-base}}/data dir
+// exec-env: EQUATION=a=b c
+// compile-flags: -L {{build-base}}
+// run-flags: {{src-base}}/data --flag
+
+use std::env;
+
+fn main() {
+    let src_base = env!("SRC_BASE");
+    assert_eq!(env::var("DATA_DIR").unwrap(), format!("{}/data dir", src_base));
+    assert_eq!(env::var("EQUATION").unwrap(), "a=b c");
+    let args: Vec<String> = env::args().skip(1).collect();
+    assert_eq!(args, [format!("{}/data", src_base), "--flag".to_owned()]);
+}
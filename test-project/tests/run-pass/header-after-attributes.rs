@@ -0,0 +1,32 @@
+#![allow(dead_code)]
+#![cfg_attr(all(),
+            allow(unused_variables))]
+//! Directives may follow inner attributes and doc comments.
+
+// run-flags: --after-attributes
+// exec-env: AFTER_ATTRIBUTES=1
+
+/* Block comments don't need leading stars,
+   /* and may be nested. */
+// run-flags: --in-comment
+*/
+
+extern crate core;
+#[allow(unused_imports)]
+use std::{
+    env,
+    io,
+};
+
+// exec-env: AFTER_USE=1
+
+macro_rules! nothing { () => {} }
+
+// run-flags: --after-item
+
+fn main() {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    assert_eq!(args, ["--after-attributes"]);
+    assert_eq!(env::var("AFTER_ATTRIBUTES").unwrap(), "1");
+    assert_eq!(env::var("AFTER_USE").unwrap(), "1");
+}
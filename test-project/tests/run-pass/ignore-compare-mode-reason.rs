@@ -0,0 +1,3 @@
+// ignore-compare-mode-nll (the borrow checker explains this differently)
+
+fn main() {}
@@ -0,0 +1,3 @@
+// ignore-compare-mode-nll
+
+fn main() {}
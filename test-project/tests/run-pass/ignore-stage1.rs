@@ -0,0 +1,4 @@
+// ignore-stage1
+// Only ignored when `stage_id` names stage 1.
+
+fn main() {}
@@ -0,0 +1,4 @@
+// min-llvm-version: 9.0
+// max-llvm-version: 10.0
+
+fn main() {}
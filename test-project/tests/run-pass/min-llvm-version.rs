@@ -0,0 +1,3 @@
+// min-llvm-version 10.0
+
+fn main() {}
@@ -0,0 +1,4 @@
+// needs-profiler-support
+// Ignored, with the reason, unless the profiler runtime is available.
+
+fn main() {}
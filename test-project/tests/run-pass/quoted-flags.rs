@@ -0,0 +1,10 @@
+// compile-flags: --cfg 'feature="some value"'
+// run-flags: "two words" '' 'say "hi"'
+
+use std::env;
+
+#[cfg(feature = "some value")]
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    assert_eq!(args, ["two words", "", "say \"hi\""]);
+}
@@ -0,0 +1,10 @@
+// `main` returning `Ok` is a successful run.
+
+fn parse(s: &str) -> Result<u32, std::num::ParseIntError> {
+    s.parse()
+}
+
+fn main() -> Result<(), std::num::ParseIntError> {
+    assert_eq!(parse("42")?, 42);
+    Ok(())
+}
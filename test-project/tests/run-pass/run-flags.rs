@@ -0,0 +1,17 @@
+// revisions: a b
+//[a] run-flags: --only-a
+// run-flags: --first
+// run-flags: --second third
+
+// Every `run-flags` line counts, revision specific ones come last.
+
+use std::env;
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if cfg!(a) {
+        assert_eq!(args, ["--first", "--second", "third", "--only-a"]);
+    } else {
+        assert_eq!(args, ["--first", "--second", "third"]);
+    }
+}
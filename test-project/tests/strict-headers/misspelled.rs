@@ -0,0 +1,3 @@
+// compile-flag: -O
+
+fn main() {}
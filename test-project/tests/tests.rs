@@ -19,7 +19,1383 @@ fn run_mode(mode: &'static str) {
 fn compile_test() {
     run_mode("compile-fail");
     run_mode("run-pass");
+    run_mode("ui");
 
     #[cfg(not(feature = "stable"))]
     run_mode("pretty");
 }
+
+#[test]
+fn run_tests_result() {
+    let mut config = compiletest::Config::default().tempdir();
+    config.mode = "ui".parse().unwrap();
+    config.src_base = PathBuf::from("tests/ui-fail");
+    config.quiet = true;
+    config.link_deps();
+    let summary = compiletest::run_tests_result(&config).unwrap();
+    assert_eq!((summary.passed, summary.failed, summary.ignored), (1, 4, 0));
+    assert!(summary.failures.contains(&"[ui] ui-fail/run-pass-exit-code.rs".to_owned()));
+
+    // A second run in the same process counts on its own.
+    config.mode = "run-pass".parse().unwrap();
+    config.src_base = PathBuf::from("tests/run-pass");
+    config.filter = Some("compare-mode.rs".to_owned());
+    config.compare_mode = Some("nll".to_owned());
+    let summary = compiletest::run_tests_result(&config).unwrap();
+    assert_eq!(summary, compiletest::TestSummary { ignored: 1, ..Default::default() });
+
+    config.src_base = PathBuf::from("tests/missing");
+    let error = compiletest::run_tests_result(&config).unwrap_err();
+    assert!(error.to_string().starts_with("invalid compiletest configuration"));
+}
+
+#[test]
+fn run_tests_collect() {
+    use compiletest::TestStatus;
+
+    let mut config = compiletest::Config::default().tempdir();
+    config.mode = "ui".parse().unwrap();
+    config.src_base = PathBuf::from("tests/ui-fail");
+    config.quiet = true;
+    config.link_deps();
+    let outcomes = compiletest::run_tests_collect(&config).unwrap();
+    assert_eq!(outcomes.len(), 5);
+    let known_bug = &outcomes[1];
+    assert_eq!(known_bug.name, "[ui] ui-fail/known-bug.rs");
+    assert_eq!(known_bug.status, TestStatus::Passed);
+    assert_eq!(known_bug.message, None);
+    let exit_code = &outcomes[3];
+    assert_eq!(exit_code.paths.file, PathBuf::from("tests/ui-fail/run-pass-exit-code.rs"));
+    assert_eq!(exit_code.status, TestStatus::Failed);
+    assert_eq!(exit_code.message.as_ref().map(String::as_str), Some("error: test run failed!"));
+    assert!(exit_code.duration > std::time::Duration::from_secs(0));
+    for file in &[&exit_code.stdout_file, &exit_code.stderr_file] {
+        assert!(file.as_ref().map_or(false, |file| file.starts_with(&config.build_base)));
+    }
+
+    config.mode = "run-pass".parse().unwrap();
+    config.src_base = PathBuf::from("tests/run-pass");
+    config.filter = Some("compare-mode.rs".to_owned());
+    config.compare_mode = Some("nll".to_owned());
+    let outcomes = compiletest::run_tests_collect(&config).unwrap();
+    assert_eq!(outcomes.len(), 1);
+    assert_eq!(outcomes[0].status, TestStatus::Ignored);
+    assert_eq!(outcomes[0].paths.file, PathBuf::from("tests/run-pass/ignore-compare-mode.rs"));
+}
+
+#[test]
+fn bless() {
+    use std::fs;
+
+    let src = std::env::temp_dir().join("compiletest-bless");
+    let _ = fs::remove_dir_all(&src);
+    fs::create_dir_all(&src).unwrap();
+    fs::copy("tests/bless/revisions.rs", src.join("revisions.rs")).unwrap();
+    fs::write(src.join("revisions.b.stderr"), "stale\n").unwrap();
+    fs::write(src.join("revisions.b.stderr.meta"), "{\"rustc_version\":\"rustc 1.0.0\"}")
+        .unwrap();
+    let src_base = src.to_str().unwrap();
+
+    let failure = run_file("ui", src_base, "revisions.rs", |_| {}).unwrap_err();
+    assert_eq!(failure.message, "1 errors occurred comparing output.");
+    let blessed = compiletest::common::Blessed::default();
+    run_file("ui", src_base, "revisions.rs", |config| {
+        config.bless = true;
+        config.track_snapshot_versions = true;
+        config.blessed = blessed.clone();
+    }).expect("blessing updates the expected output");
+    let mut blessed = blessed.lock().unwrap().clone();
+    blessed.sort();
+    assert_eq!(blessed, [(src.join("revisions.a.stderr"), compiletest::BlessAction::Created),
+                         (src.join("revisions.b.stderr"), compiletest::BlessAction::Deleted)]);
+    let stderr = fs::read_to_string(src.join("revisions.a.stderr")).unwrap();
+    assert!(stderr.contains("error[E0308]: mismatched types"));
+    assert!(!src.join("revisions.b.stderr").exists());
+    // The compiler version goes along with the expected output.
+    let version = compiletest::Config::default().rustc_version().unwrap();
+    let meta = fs::read_to_string(src.join("revisions.a.stderr.meta")).unwrap();
+    assert!(meta.contains(&version), "{}", meta);
+    assert!(!src.join("revisions.b.stderr.meta").exists());
+    run_file("ui", src_base, "revisions.rs", |_| {}).expect("the blessed output matches");
+
+    // Whole runs list what they blessed at the end.
+    fs::write(src.join("revisions.a.stderr"), "stale\n").unwrap();
+    let mut config = compiletest::Config::default().tempdir();
+    config.mode = "ui".parse().unwrap();
+    config.src_base = src.clone();
+    config.bless = true;
+    let summary = compiletest::run_tests_result(&config).unwrap();
+    assert_eq!((summary.passed, summary.failed), (1, 0));
+    assert!(fs::read_to_string(src.join("revisions.a.stderr")).unwrap().contains("E0308"));
+}
+
+#[test]
+fn ui_run_pass_exit_code() {
+    let failure = run_ui_fail("run-pass-exit-code.rs", |_| {}).unwrap_err();
+    assert_eq!(failure.message, "test run failed!");
+}
+
+#[test]
+fn ui_result_main_err() {
+    let failure = run_ui_fail("result-main-err.rs", |_| {}).unwrap_err();
+    assert_eq!(failure.message,
+               "test run failed! (`main` returned `Error: \"the answer wasn't 42\"`)");
+}
+
+#[test]
+fn ui_check_run_results() {
+    run_file("ui", "tests/ui", "check-run-results.rs", |_| {})
+        .expect("the program prints what's expected");
+    let failure = run_ui_fail("check-run-results.rs", |_| {}).unwrap_err();
+    assert_eq!(failure.message, "1 errors occurred comparing run output.");
+}
+
+#[test]
+fn ui_should_ice_fixed() {
+    let failure = run_ui_fail("should-ice-fixed.rs", |_| {}).unwrap_err();
+    assert!(failure.message.contains("no longer reproduces"));
+}
+
+#[test]
+fn ui_known_bug() {
+    run_ui_fail("known-bug.rs", |_| {}).expect("changed output of a known bug only warns");
+    let failure = run_ui_fail("known-bug.rs", |config| config.fail_on_known_bug_pass = true)
+        .unwrap_err();
+    assert_eq!(failure.known_bug, Some("#1234".to_owned()));
+    assert!(failure.to_string().ends_with("(known bug #1234)"));
+}
+
+#[test]
+fn error_pattern_regex() {
+    run_file("run-fail", "tests/run-fail", "error-pattern-regex.rs", |_| {})
+        .expect("the regexes match the panic message");
+    let failure = run_file("run-fail", "tests/run-fail-fail", "error-pattern-regex.rs", |_| {})
+        .unwrap_err();
+    assert_eq!(failure.message, r"error pattern regex 'index \d+ is out of bounds' not found!");
+}
+
+#[test]
+fn run_fail_result() {
+    run_file("run-fail", "tests/run-fail", "result-main.rs", |_| {})
+        .expect("`main` returned an error");
+    let failure = run_file("run-fail", "tests/run-fail-fail", "result-main-panic.rs", |_| {})
+        .unwrap_err();
+    assert_eq!(failure.message,
+               "failure produced the wrong error: expected exit code 1, got exit status: 101");
+}
+
+#[test]
+fn dry_run() {
+    let mut config = compiletest::Config::default().tempdir();
+    config.mode = "run-pass".parse().expect("Invalid mode");
+    config.src_base = PathBuf::from("tests/dry-run");
+    config.dry_run = true;
+    let testpaths = compiletest::common::TestPaths {
+        file: config.src_base.join("aux-build.rs"),
+        base: config.src_base.clone(),
+        relative_dir: PathBuf::new(),
+    };
+    compiletest::runtest::try_run((*config).clone(), &testpaths)
+        .expect("nothing is checked in a dry run");
+    assert_no_files(&config.build_base);
+}
+
+#[test]
+fn incremental() {
+    use compiletest::header::TestProps;
+
+    let mut config = compiletest::Config::default().tempdir();
+    config.mode = "run-pass".parse().expect("Invalid mode");
+    config.src_base = PathBuf::from("tests/dry-run");
+    config.dry_run = true;
+    let file = config.src_base.join("incremental.rs");
+    for &revision in &[None, Some("a"), Some("b")] {
+        let props = TestProps::from_file(&file, revision, &config);
+        assert!(props.incremental);
+        assert_eq!(props.incremental_dir, None);
+    }
+    let testpaths = compiletest::common::TestPaths {
+        file: file,
+        base: config.src_base.clone(),
+        relative_dir: PathBuf::new(),
+    };
+    compiletest::runtest::try_run((*config).clone(), &testpaths)
+        .expect("nothing is checked in a dry run");
+    // The cache isn't emptied in a dry run.
+    assert!(!config.build_base.join("incremental.inc").exists());
+    assert_no_files(&config.build_base);
+}
+
+#[test]
+#[cfg(not(feature = "stable"))]
+fn dry_run_pretty() {
+    // The source is passed to the compiler on stdin.
+    let mut config = compiletest::Config::default().tempdir();
+    config.mode = "pretty".parse().expect("Invalid mode");
+    config.src_base = PathBuf::from("tests/pretty");
+    config.dry_run = true;
+    let testpaths = compiletest::common::TestPaths {
+        file: config.src_base.join("macro.rs"),
+        base: config.src_base.clone(),
+        relative_dir: PathBuf::new(),
+    };
+    compiletest::runtest::try_run((*config).clone(), &testpaths)
+        .expect("nothing is checked in a dry run");
+    assert_no_files(&config.build_base);
+}
+
+fn assert_no_files(dir: &std::path::Path) {
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        assert!(path.is_dir(), "`{}` was written in a dry run", path.display());
+        assert_no_files(&path);
+    }
+}
+
+#[test]
+fn forbid_output_regex() {
+    let failure = run_file("compile-fail", "tests/compile-fail-fail", "forbid-output-regex.rs",
+                           |_| {})
+        .unwrap_err();
+    assert!(failure.message.starts_with(r"forbidden pattern regex 'error\[E\d+\]' found"));
+    assert!(failure.message.ends_with(": error[E0308]: mismatched types"));
+}
+
+#[test]
+fn revision_annotations() {
+    use compiletest::errors;
+
+    let path = PathBuf::from("tests/annotations/revisions.rs");
+    let messages = |revision| {
+        errors::load_errors(&path, revision).into_iter()
+            .map(|error| (error.line_num, error.msg))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(messages(None), vec![(10, "mismatched types".to_owned())]);
+    assert_eq!(messages(Some("a")), vec![(8, "mismatched types".to_owned()),
+                                         (10, "mismatched types".to_owned()),
+                                         (11, "mismatched types".to_owned())]);
+    assert_eq!(messages(Some("b")), vec![(8, "cannot find value `VALUE`".to_owned()),
+                                         (10, "mismatched types".to_owned()),
+                                         (11, "mismatched types".to_owned())]);
+
+    run_file("compile-fail", "tests/annotations", "revisions.rs", |_| {})
+        .expect("each revision gets its own errors");
+    let failure = run_file("compile-fail", "tests/compile-fail-fail", "revisions.rs", |_| {})
+        .unwrap_err();
+    assert_eq!(failure.revision.as_ref().map(String::as_str), Some("b"));
+    assert_eq!(failure.message, "1 unexpected errors found, 1 expected errors not found");
+}
+
+#[test]
+fn floating_annotations() {
+    run_file("compile-fail", "tests/annotations", "floating.rs", |_| {})
+        .expect("`//~?` matches errors on any line");
+    let failure = run_file("compile-fail", "tests/compile-fail-fail", "floating.rs", |_| {})
+        .unwrap_err();
+    assert_eq!(failure.message, "0 unexpected errors found, 1 expected errors not found");
+}
+
+#[test]
+fn annotation_columns() {
+    use compiletest::errors;
+
+    let expected = errors::load_errors(&PathBuf::from("tests/annotations/columns.rs"), None);
+    let positions = expected.iter().map(|e| (e.line_num, e.column)).collect::<Vec<_>>();
+    assert_eq!(positions, [(4, Some(19)), (5, Some(19))]);
+
+    run_file("compile-fail", "tests/annotations", "columns.rs", |_| {})
+        .expect("the errors are at the expected columns");
+    let failure = run_file("compile-fail", "tests/compile-fail-fail", "columns.rs", |_| {})
+        .unwrap_err();
+    assert_eq!(failure.message, "0 unexpected errors found, 0 expected errors not found, \
+                                 1 errors at the wrong column");
+}
+
+#[test]
+fn annotation_error_codes() {
+    use compiletest::errors;
+
+    let expected = errors::load_errors(&PathBuf::from("tests/annotations/error-codes.rs"), None);
+    let codes = expected.iter()
+        .map(|e| (e.code.as_ref().map(String::as_str), e.msg.as_str()))
+        .collect::<Vec<_>>();
+    assert_eq!(codes, [(Some("E0308"), ""), (Some("E0425"), "cannot find value")]);
+
+    run_file("compile-fail", "tests/annotations", "error-codes.rs", |_| {})
+        .expect("the errors have the expected codes");
+    let failure = run_file("compile-fail", "tests/compile-fail-fail", "error-codes.rs", |_| {})
+        .unwrap_err();
+    assert_eq!(failure.message, "1 unexpected errors found, 1 expected errors not found");
+}
+
+#[test]
+fn annotation_counts() {
+    use compiletest::errors;
+
+    let expected = errors::load_errors(&PathBuf::from("tests/annotations/counts.rs"), None);
+    assert_eq!(expected.len(), 1);
+    assert_eq!((expected[0].msg.as_str(), expected[0].count), ("mismatched types", 2));
+
+    run_file("compile-fail", "tests/annotations", "counts.rs", |_| {})
+        .expect("the error is reported twice");
+    let failure = run_file("compile-fail", "tests/compile-fail-fail", "counts.rs", |_| {})
+        .unwrap_err();
+    assert_eq!(failure.message, "0 unexpected errors found, 0 expected errors not found, \
+                                 1 errors found too few times");
+}
+
+#[test]
+fn downward_annotations() {
+    use compiletest::errors;
+
+    let expected = errors::load_errors(&PathBuf::from("tests/annotations/downward.rs"), None);
+    let lines = expected.iter().map(|e| e.line_num).collect::<Vec<_>>();
+    assert_eq!(lines, [5, 9, 9, 10]);
+    run_file("compile-fail", "tests/annotations", "downward.rs", |_| {})
+        .expect("the annotations point at the lines below");
+
+    let panic = std::panic::catch_unwind(|| {
+        errors::load_errors(&PathBuf::from("tests/annotations/past-end.rs"), None)
+    }).unwrap_err();
+    assert_eq!(panic.downcast_ref::<String>().map(String::as_str),
+               Some("annotation on line 3 points past end of file"));
+}
+
+#[test]
+fn config_builder() {
+    use compiletest::common::Mode;
+
+    let config = compiletest::Config::new()
+        .mode(Mode::Ui)
+        .src_base("tests/ui")
+        .build_base(std::env::temp_dir())
+        .target_rustcflags("-O");
+    assert_eq!(config.mode, Mode::Ui);
+    assert_eq!(config.src_base, PathBuf::from("tests/ui"));
+    assert_eq!(config.target_rustcflags.as_ref().map(String::as_str), Some("-O"));
+    assert!(config.missing_fields().is_empty());
+
+    let config = config.src_base("tests/missing").rustc_path("").host("");
+    assert_eq!(config.missing_fields(),
+               ["src_base (`tests/missing` is not a directory)", "rustc_path (empty)",
+                "host (empty)"]);
+    let panic = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        compiletest::run_tests(&config)
+    })).unwrap_err();
+    assert_eq!(panic.downcast_ref::<String>().map(String::as_str),
+               Some("invalid compiletest configuration, these fields have to be set: \
+                     src_base (`tests/missing` is not a directory), rustc_path (empty), \
+                     host (empty)"));
+}
+
+#[test]
+fn link_deps() {
+    let mut config = compiletest::Config::default();
+    config.link_deps();
+    for flags in &[&config.target_rustcflags, &config.host_rustcflags] {
+        let flags = compiletest::util::split_args(flags.as_ref().expect("running under Cargo"));
+        // Just the deps directory and `target/<profile>`, nothing else from
+        // the dynamic library search path.
+        assert_eq!(flags.len(), 4);
+        assert_eq!((&*flags[0], &*flags[2]), ("-L", "-L"));
+        let deps_dir = PathBuf::from(&flags[1]);
+        assert!(deps_dir.ends_with("deps") && deps_dir.is_dir());
+        assert_eq!(PathBuf::from(&flags[3]), deps_dir.parent().unwrap());
+    }
+
+    run_file("ui", "tests/ui", "extern-host-crate.rs", |_| {})
+        .expect("the host crate can be imported");
+}
+
+#[test]
+fn clean_rmeta() {
+    use std::fs;
+    use std::process::Command;
+
+    // `dup` built by `cargo check` and then by `cargo build` with other flags.
+    let deps = std::env::temp_dir().join("compiletest-clean-rmeta").join("deps");
+    let _ = fs::remove_dir_all(&deps);
+    fs::create_dir_all(&deps).unwrap();
+    for &(hash, emit) in &[("a", "metadata"), ("b", "metadata,link")] {
+        let status = Command::new("rustc")
+            .args(&["--crate-type", "lib", "--crate-name", "dup", "--emit", emit])
+            .arg("-C").arg(format!("metadata={}", hash))
+            .arg("-C").arg(format!("extra-filename=-{}", hash))
+            .arg("--out-dir").arg(&deps)
+            .arg("tests/clean-rmeta/dup.rs")
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+    let flags = format!("-L {}", deps.display());
+
+    let failure = run_file("ui", "tests/clean-rmeta", "uses-dup.rs", |config| {
+        config.target_rustcflags = Some(flags.clone());
+    }).unwrap_err();
+    assert!(format!("{:?}", failure.proc_res).contains("error[E0464]"));
+
+    run_file("ui", "tests/clean-rmeta", "uses-dup.rs", |config| {
+        config.target_rustcflags = Some(flags.clone());
+        config.clean_rmeta();
+    }).expect("the stale rmeta file was removed");
+    assert!(!deps.join("libdup-a.rmeta").exists());
+    assert!(deps.join("libdup-b.rmeta").exists() && deps.join("libdup-b.rlib").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn resolve_tool_paths() {
+    use std::fs;
+
+    let mut config = compiletest::Config::default();
+    config.resolve_tool_paths().unwrap();
+    assert!(config.rustc_path.is_absolute() && config.rustc_path.is_file());
+    assert!(config.rustdoc_path.as_ref().map_or(false, |path| path.is_file()));
+
+    // `RUSTC` wins, a link works as well as the real compiler in case another
+    // test resolves it meanwhile.
+    let dir = std::env::temp_dir().join("compiletest-resolve-tool-paths");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    std::os::unix::fs::symlink(&config.rustc_path, dir.join("rustc")).unwrap();
+    std::env::set_var("RUSTC", dir.join("rustc"));
+    let mut linked = compiletest::Config::default();
+    let result = linked.resolve_tool_paths();
+    std::env::remove_var("RUSTC");
+    result.unwrap();
+    assert_eq!(linked.rustc_path, dir.join("rustc"));
+
+    let mut explicit = compiletest::Config::default();
+    explicit.rustc_path = PathBuf::from("/usr/local/bin/my-rustc");
+    explicit.resolve_tool_paths().unwrap();
+    assert_eq!(explicit.rustc_path, PathBuf::from("/usr/local/bin/my-rustc"));
+}
+
+#[cfg(windows)]
+#[test]
+fn inherited_pipes() {
+    use std::time::{Duration, Instant};
+
+    let start = Instant::now();
+    run_file("run-pass", "tests/windows", "inherited-pipes.rs", |_| {})
+        .expect("the test program exits successfully");
+    assert!(start.elapsed() < Duration::from_secs(120));
+}
+
+fn run_ui_fail<F>(file: &str, configure: F) -> Result<(), Box<compiletest::runtest::TestFailure>>
+    where F: FnOnce(&mut compiletest::Config)
+{
+    run_file("ui", "tests/ui-fail", file, configure)
+}
+
+#[test]
+fn codegen_units() {
+    run_file("codegen-units", "tests/codegen-units", "generic.rs", |_| {})
+        .expect("the mono items match the annotations");
+    let failure = run_file("codegen-units", "tests/codegen-units-fail", "generic.rs", |_| {})
+        .unwrap_err();
+    assert_eq!(failure.message, "1 missing, 1 unexpected and 0 misplaced mono items");
+}
+
+#[test]
+fn exclusive_test_names() {
+    let mut config = compiletest::Config::default();
+    config.mode = "run-pass".parse().unwrap();
+    config.src_base = PathBuf::from("tests/exclusive");
+    let mut names = compiletest::make_tests(&config).iter()
+        .map(|test| test.desc.name.to_string())
+        .collect::<Vec<_>>();
+    names.sort();
+    assert_eq!(names, ["[run-pass exclusive] exclusive/alone.rs",
+                       "[run-pass] exclusive/concurrent.rs"]);
+}
+
+fn run_file<F>(mode: &str, src_base: &str, file: &str, configure: F)
+               -> Result<(), Box<compiletest::runtest::TestFailure>>
+    where F: FnOnce(&mut compiletest::Config)
+{
+    let mut config = compiletest::Config::default().tempdir();
+    config.mode = mode.parse().expect("Invalid mode");
+    config.src_base = PathBuf::from(src_base);
+    config.link_deps();
+    config.clean_rmeta();
+    configure(&mut config);
+
+    let testpaths = compiletest::common::TestPaths {
+        file: config.src_base.join(file),
+        base: config.src_base.clone(),
+        relative_dir: PathBuf::new(),
+    };
+    compiletest::runtest::try_run((*config).clone(), &testpaths)
+}
+
+#[cfg(all(unix, target_arch = "x86_64"))]
+#[test]
+fn assembly() {
+    use compiletest::header::EarlyProps;
+
+    let filecheck = PathBuf::from("tests/filecheck.sh");
+    run_file("assembly", "tests/assembly", "add-one.rs", |config| {
+        config.llvm_filecheck = Some(filecheck.clone());
+    }).expect("the function is in the assembly");
+    let failure = run_file("assembly", "tests/assembly-fail", "add-one.rs", |config| {
+        config.llvm_filecheck = Some(filecheck.clone());
+    }).unwrap_err();
+    assert!(failure.message.starts_with("verification with 'FileCheck' failed, assembly is in `"));
+    assert!(failure.message.ends_with("add-one.s`"));
+
+    let failure = run_file("assembly", "tests/assembly", "add-one.rs", |_| {}).unwrap_err();
+    assert_eq!(failure.message, "missing --llvm-filecheck");
+
+    // The expected assembly is specific to the target.
+    let mut config = compiletest::Config::default();
+    config.mode = "assembly".parse().unwrap();
+    let file = PathBuf::from("tests/assembly/add-one.rs");
+    config.target = "x86_64-unknown-linux-gnu".to_owned();
+    assert!(!EarlyProps::from_file(&config, &file).ignore);
+    config.target = "aarch64-unknown-linux-gnu".to_owned();
+    assert!(EarlyProps::from_file(&config, &file).ignore);
+}
+
+/// A copy of `tests/counting-rustc.sh` in a new directory `name`.
+#[cfg(unix)]
+fn counting_rustc(name: &str) -> PathBuf {
+    use std::fs;
+
+    let dir = std::env::temp_dir().join(name);
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let rustc = dir.join("rustc.sh");
+    fs::copy("tests/counting-rustc.sh", &rustc).unwrap();
+    rustc
+}
+
+/// The arguments of each run of a `counting_rustc` so far.
+#[cfg(unix)]
+fn rustc_invocations(rustc: &std::path::Path) -> Vec<String> {
+    let log = rustc.with_file_name("invocations");
+    std::fs::read_to_string(log).unwrap_or_default().lines().map(str::to_owned).collect()
+}
+
+#[cfg(unix)]
+#[test]
+fn proc_macro_built_once() {
+    let rustc = counting_rustc("compiletest-proc-macro-built-once");
+    let pm_builds = || {
+        rustc_invocations(&rustc).iter()
+            .filter(|args| args.contains("--crate-type proc-macro"))
+            .count()
+    };
+    let build_base = rustc.parent().unwrap().join("build");
+    std::fs::create_dir_all(&build_base).unwrap();
+    let run = |target_flags: &str, host_flags: &str, build_base: &PathBuf| {
+        run_file("run-pass", "tests/proc-macro", "uses-double.rs", |config| {
+            config.rustc_path = rustc.clone();
+            config.build_base = build_base.clone();
+            config.target_rustcflags = Some(target_flags.to_owned());
+            config.host_rustcflags = Some(host_flags.to_owned());
+        }).expect("the proc macro expands");
+    };
+
+    run("", "", &build_base);
+    assert_eq!(pm_builds(), 1);
+    // The proc macro is built for the host, whatever the test is built for.
+    run("-C opt-level=1", "", &build_base);
+    assert_eq!(pm_builds(), 1);
+
+    run("", "-C opt-level=1", &build_base);
+    assert_eq!(pm_builds(), 2);
+    let other_base = rustc.parent().unwrap().join("other-build");
+    std::fs::create_dir_all(&other_base).unwrap();
+    run("", "", &other_base);
+    assert_eq!(pm_builds(), 3);
+    // A cached library which is gone is built again.
+    std::fs::remove_dir_all(build_base.join("proc-macros")).unwrap();
+    run("", "", &build_base);
+    assert_eq!(pm_builds(), 4);
+    run("", "", &build_base);
+    assert_eq!(pm_builds(), 4);
+}
+
+#[cfg(unix)]
+#[test]
+fn remote_test_client() {
+    use compiletest::runtest::FailureKind;
+    use compiletest::TestStatus;
+    use std::env;
+
+    let client = PathBuf::from("tests/fake-remote-test-client.sh");
+    let with_client = |config: &mut compiletest::Config| {
+        config.remote_test_client = Some(client.clone());
+    };
+    run_file("run-pass", "tests/run-pass", "while-with-break.rs", with_client)
+        .expect("the program ran through the client");
+    // The output of the program is not taken for a failure of the client.
+    run_file("run-fail", "tests/run-fail", "connection-refused.rs", with_client)
+        .expect("the program failed as expected");
+
+    let mut config = compiletest::Config::default().tempdir();
+    config.mode = "run-fail".parse().unwrap();
+    config.src_base = PathBuf::from("tests/run-fail");
+    config.filter = Some("connection-refused".to_owned());
+    config.quiet = true;
+    config.link_deps();
+    with_client(&mut config);
+
+    env::set_var("FAKE_REMOTE_TEST_CLIENT", "refused");
+    let refused = run_file("run-fail", "tests/run-fail", "connection-refused.rs", with_client);
+    let outcomes = compiletest::run_tests_collect(&config);
+    env::set_var("FAKE_REMOTE_TEST_CLIENT", "broken");
+    let broken = run_file("run-fail", "tests/run-fail", "connection-refused.rs", with_client);
+    env::remove_var("FAKE_REMOTE_TEST_CLIENT");
+
+    // Though the client's error matches the error pattern.
+    let refused = refused.unwrap_err();
+    assert_eq!(refused.kind, FailureKind::Infrastructure);
+    assert!(refused.retryable);
+    assert_eq!(refused.message, "remote-test-client failed, the test was not run: \
+                                 remote-test-client: error: failed to connect to \
+                                 127.0.0.1:12345: Connection refused");
+    let outcomes = outcomes.unwrap();
+    assert_eq!(outcomes.len(), 1);
+    assert_eq!(outcomes[0].status, TestStatus::Error);
+
+    let broken = broken.unwrap_err();
+    assert_eq!(broken.kind, FailureKind::Infrastructure);
+    assert!(!broken.retryable);
+}
+
+#[cfg(unix)]
+#[test]
+fn aux_builds_shared_by_revisions() {
+    let rustc = counting_rustc("compiletest-aux-builds-shared-by-revisions");
+    run_file("run-pass", "tests/aux-revisions", "revisions.rs", |config| {
+        config.rustc_path = rustc.clone();
+    }).expect("each revision sees its own aux build");
+    let builds = |aux: &str| {
+        rustc_invocations(&rustc).iter().filter(|args| args.contains(aux)).count()
+    };
+    assert_eq!(builds("auxiliary/shared.rs"), 1);
+    // The other one can tell the revisions apart.
+    assert_eq!(builds("auxiliary/per-revision.rs"), 2);
+}
+
+#[test]
+fn run_args() {
+    use compiletest::runtest::run_args;
+
+    let flags = vec!["--flag".to_owned(), "value".to_owned()];
+    let mut config = compiletest::Config::default();
+    config.target = "x86_64-unknown-linux-gnu".to_owned();
+    assert_eq!(run_args(&config, "a.out", &flags).unwrap(), ["a.out", "--flag", "value"]);
+    config.runtool = Some("valgrind --quiet".to_owned());
+    assert_eq!(run_args(&config, "a.out", &flags).unwrap(),
+               ["valgrind", "--quiet", "a.out", "--flag", "value"]);
+    config.runtool = None;
+
+    config.target = "asmjs-unknown-emscripten".to_owned();
+    assert_eq!(run_args(&config, "a.js", &[]).unwrap_err(), "no NodeJS binary found (--nodejs)");
+    config.nodejs = Some("node".to_owned());
+    config.nodejs_args = vec!["--stack-size=8192".to_owned()];
+    // The shim is only for plain wasm.
+    config.wasm_shim = Some(PathBuf::from("shim.js"));
+    assert_eq!(run_args(&config, "a.js", &flags).unwrap(),
+               ["node", "--stack-size=8192", "a.js", "--flag", "value"]);
+
+    config.target = "wasm32-unknown-unknown".to_owned();
+    assert_eq!(run_args(&config, "a.wasm", &flags).unwrap(),
+               ["node", "--stack-size=8192", "shim.js", "a.wasm", "--flag", "value"]);
+    config.wasm_shim = None;
+    assert_eq!(run_args(&config, "a.wasm", &flags).unwrap(),
+               ["node", "--stack-size=8192", "a.wasm", "--flag", "value"]);
+    // A wasm runner takes precedence over nodejs.
+    config.wasm_runner = Some("wasmtime run --".to_owned());
+    config.runtool = Some("time".to_owned());
+    assert_eq!(run_args(&config, "a.wasm", &flags).unwrap(),
+               ["time", "wasmtime", "run", "--", "a.wasm", "--flag", "value"]);
+    config.wasm_runner = None;
+    config.nodejs = None;
+    assert_eq!(run_args(&config, "a.wasm", &[]).unwrap_err(),
+               "no wasm runner (--wasm-runner) or NodeJS binary (--nodejs) found");
+}
+
+#[test]
+fn no_run() {
+    use compiletest::TestStatus;
+
+    run_file("run-pass", "tests/no-run", "builds-only.rs", |_| {})
+        .expect("the program is only built");
+    let failure = run_file("run-pass", "tests/no-run", "panics.rs", |_| {}).unwrap_err();
+    assert_eq!(failure.message, "test run failed!");
+    run_file("run-pass", "tests/no-run", "panics.rs", |config| {
+        config.run = Some(false);
+    }).expect("the program is only built");
+    // The program still has to build.
+    let failure = run_file("run-pass", "tests/compile-fail", "trait-bounds-cant-coerce.rs",
+                           |config| config.run = Some(false)).unwrap_err();
+    assert_eq!(failure.message, "compilation failed!");
+
+    // Run-fail tests can't be checked without running them.
+    let mut config = compiletest::Config::default().tempdir();
+    config.mode = "run-fail".parse().unwrap();
+    config.src_base = PathBuf::from("tests/run-fail");
+    config.filter = Some("args-panic".to_owned());
+    config.quiet = true;
+    config.run = Some(false);
+    let outcomes = compiletest::run_tests_collect(&config).unwrap();
+    assert_eq!(outcomes.len(), 1);
+    assert_eq!(outcomes[0].status, TestStatus::Ignored);
+}
+
+#[test]
+fn test_file_extensions_and_filter() {
+    use compiletest::is_test_file;
+    use std::path::Path;
+    use std::sync::Arc;
+
+    let mut config = compiletest::Config::default();
+    assert!(is_test_file(&config, Path::new("tests/ui/foo.rs")));
+    assert!(!is_test_file(&config, Path::new("tests/ui/foo.md")));
+    assert!(!is_test_file(&config, Path::new("tests/ui/.foo.rs")));
+
+    config.test_extensions = vec!["md".to_owned(), ".fixed".to_owned()];
+    assert!(is_test_file(&config, Path::new("tests/ui/foo.rs")));
+    assert!(is_test_file(&config, Path::new("tests/ui/foo.md")));
+    assert!(is_test_file(&config, Path::new("tests/ui/foo.fixed")));
+    assert!(!is_test_file(&config, Path::new("tests/ui/foo.txt")));
+    assert!(!is_test_file(&config, Path::new("tests/ui/foomd")));
+    // Temp files are skipped whatever their extension.
+    for temp in &[".foo.md", "#foo.md#.md", "~foo.fixed"] {
+        assert!(!is_test_file(&config, &Path::new("tests/ui").join(temp)), "{}", temp);
+    }
+
+    config.test_file_filter = Some(Arc::new(|path: &Path| {
+        !path.to_str().unwrap().contains("skip")
+    }));
+    assert!(is_test_file(&config, Path::new("tests/ui/foo.md")));
+    assert!(!is_test_file(&config, Path::new("tests/ui/skip.md")));
+    assert!(!is_test_file(&config, Path::new("tests/ui/skip.rs")));
+    // The filter only sees files with a test extension.
+    assert!(!is_test_file(&config, Path::new("tests/ui/foo.txt")));
+}
+
+#[test]
+fn max_artifacts_size() {
+    use std::fs;
+
+    let build_base = std::env::temp_dir().join("compiletest-max-artifacts-size");
+    let index = |max_artifacts_size| {
+        let _ = fs::remove_dir_all(&build_base);
+        fs::create_dir_all(&build_base).unwrap();
+        let failure = run_ui_fail("check-run-results.rs", |config| {
+            config.build_base = build_base.clone();
+            config.max_artifacts_size = max_artifacts_size;
+        }).unwrap_err();
+        assert_eq!(failure.message, "1 errors occurred comparing run output.");
+        let dir = fs::read_dir(&build_base).unwrap()
+            .map(|entry| entry.unwrap().path())
+            .find(|path| path.extension().map_or(false, |ext| ext == "artifacts"))
+            .unwrap();
+        let index = fs::read_to_string(dir.join("index.txt")).unwrap();
+        index.lines().map(|line| {
+            let fields: Vec<_> = line.split('\t').collect();
+            let path = dir.join(fields[0]);
+            let size: u64 = fields[1].parse().unwrap();
+            assert_eq!(fs::metadata(&path).unwrap().len(), size, "{}", line);
+            (fields[0].to_owned(), size, fields[3].to_owned())
+        }).collect::<Vec<_>>()
+    };
+    let actual = build_base.join("check-run-results.run.stderr").display().to_string();
+    let entry = |path: &str, size, producer: &str| (path.to_owned(), size, producer.to_owned());
+
+    assert_eq!(index(None), [entry("out", 9, "stdout of the last command"),
+                             entry("err", 11, "stderr of the last command"),
+                             entry(&actual, 11, "actual run.stderr")]);
+    // The oldest artifact is truncated first, the actual output isn't counted.
+    assert_eq!(index(Some(12)), [entry("out", 1, "stdout of the last command (truncated)"),
+                                 entry("err", 11, "stderr of the last command"),
+                                 entry(&actual, 11, "actual run.stderr")]);
+    assert_eq!(index(Some(0)), [entry("out", 0, "stdout of the last command (truncated)"),
+                                entry("err", 0, "stderr of the last command (truncated)"),
+                                entry(&actual, 11, "actual run.stderr")]);
+    fs::remove_dir_all(&build_base).unwrap();
+}
+
+#[test]
+fn only_directives() {
+    use compiletest::header::EarlyProps;
+
+    let mut config = compiletest::Config::default();
+    let ignored_by = |config: &compiletest::Config, file: &str| {
+        let props = EarlyProps::from_file(config, &PathBuf::from("tests/only").join(file));
+        assert_eq!(props.ignore, !props.ignored_by.is_empty());
+        props.ignored_by
+    };
+
+    config.target = "x86_64-unknown-linux-gnu".to_owned();
+    assert!(ignored_by(&config, "linux.rs").is_empty());
+    assert!(ignored_by(&config, "linux-x86_64.rs").is_empty());
+    // `ignore-*` wins over a matching `only-*`.
+    assert_eq!(ignored_by(&config, "linux-ignore-x86_64.rs"), ["ignore-x86_64"]);
+
+    // Several `only-*` all have to match.
+    config.target = "aarch64-unknown-linux-gnu".to_owned();
+    assert!(ignored_by(&config, "linux.rs").is_empty());
+    assert_eq!(ignored_by(&config, "linux-x86_64.rs"), ["only-x86_64"]);
+    assert!(ignored_by(&config, "linux-ignore-x86_64.rs").is_empty());
+
+    config.target = "x86_64-pc-windows-msvc".to_owned();
+    assert_eq!(ignored_by(&config, "linux.rs"), ["only-linux"]);
+    assert_eq!(ignored_by(&config, "linux-x86_64.rs"), ["only-linux"]);
+    assert_eq!(ignored_by(&config, "linux-ignore-x86_64.rs"), ["only-linux", "ignore-x86_64"]);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn process_group_killed() {
+    use std::fs;
+
+    let pid_file = std::env::temp_dir().join("compiletest-process-group.pid");
+    // Killed processes stay zombies until whoever inherited them reaps them.
+    let alive = |pid: &str| {
+        fs::read_to_string(format!("/proc/{}/stat", pid))
+            .map_or(false, |stat| !stat.contains(") Z "))
+    };
+
+    run_file("run-pass", "tests/process-group", "leaves-sleeper.rs", |_| {})
+        .expect("the program exits right away");
+    let pid = fs::read_to_string(&pid_file).unwrap();
+    assert!(!alive(&pid), "the sleeping grandchild {} survived", pid);
+
+    run_file("run-pass", "tests/process-group", "leaves-sleeper.rs", |config| {
+        config.isolate_process_group = false;
+    }).expect("the program exits right away");
+    let pid = fs::read_to_string(&pid_file).unwrap();
+    assert!(alive(&pid));
+    std::process::Command::new("kill").args(&["-9", &pid]).status().unwrap();
+    fs::remove_file(&pid_file).unwrap();
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn process_group_killed_with_inherited_pipes() {
+    use std::fs;
+    use std::time::{Duration, Instant};
+
+    // The grandchild holds on to the pipes, compiletest must not wait for it.
+    let pid_file = std::env::temp_dir().join("compiletest-process-group-inherited.pid");
+    let start = Instant::now();
+    run_file("run-pass", "tests/process-group", "inherits-stdout.rs", |_| {})
+        .expect("the program exits right away");
+    assert!(start.elapsed() < Duration::from_secs(30));
+    let pid = fs::read_to_string(&pid_file).unwrap();
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid));
+    assert!(stat.map_or(true, |stat| stat.contains(") Z ")),
+            "the sleeping grandchild {} survived", pid);
+    fs::remove_file(&pid_file).unwrap();
+}
+
+#[test]
+fn up_to_date_fingerprint() {
+    let mut config = compiletest::Config::default().tempdir();
+    config.mode = "run-pass".parse().unwrap();
+    config.src_base = PathBuf::from("tests/up-to-date");
+    config.skip_up_to_date = true;
+    // The compiler is part of the fingerprint, so don't let `run_tests` look
+    // up another path for it.
+    let sysroot = std::process::Command::new("rustc").args(&["--print", "sysroot"]).output()
+        .unwrap().stdout;
+    config.rustc_path = PathBuf::from(String::from_utf8(sysroot).unwrap().trim())
+        .join("bin").join("rustc");
+    let skipped = |config: &compiletest::Config| {
+        compiletest::make_tests(config).iter().all(|test| test.desc.ignore)
+    };
+    assert!(!skipped(&config));
+    compiletest::run_tests(&config);
+    assert!(skipped(&config));
+
+    // Another backend compiles the tests differently. Any existing file will
+    // do, the tests aren't run with it.
+    config.codegen_backend = Some(PathBuf::from("tests/up-to-date/passes.rs"));
+    assert!(!skipped(&config));
+    config.codegen_backend = None;
+
+    // Outputs which only matched with whitespace ignored need comparing again.
+    config.strict_whitespace = true;
+    assert!(!skipped(&config));
+}
+
+#[test]
+fn env_precedence() {
+    run_file("run-pass", "tests/run-pass", "env-precedence.rs", |_| {})
+        .expect("the program and the compiler see the merged variables");
+}
+
+#[test]
+fn annotations_in_strings() {
+    run_file("compile-fail", "tests/annotations", "strings.rs", |_| {})
+        .expect("only the annotation after the strings counts");
+}
+
+#[cfg(unix)]
+#[test]
+fn prefer_dynamic() {
+    use compiletest::util::target_supports_dylibs;
+
+    assert!(target_supports_dylibs("x86_64-unknown-linux-gnu"));
+    for target in &["x86_64-unknown-linux-musl", "wasm32-unknown-unknown",
+                    "asmjs-unknown-emscripten"] {
+        assert!(!target_supports_dylibs(target), "{}", target);
+    }
+
+    // Only the compiler command lines are checked, the target needn't be
+    // installed.
+    let invocations = |target: &str, file: &str| {
+        let rustc = counting_rustc("compiletest-prefer-dynamic");
+        let _ = run_file("run-pass", "tests/prefer-dynamic", file, |config| {
+            config.rustc_path = rustc.clone();
+            config.target = target.to_owned();
+        });
+        rustc_invocations(&rustc)
+    };
+    let prefers_dynamic = |args: &String| args.contains("-C prefer-dynamic");
+    let gnu = "x86_64-unknown-linux-gnu";
+    let musl = "x86_64-unknown-linux-musl";
+
+    assert!(prefers_dynamic(&invocations(gnu, "plain.rs")[0]));
+    assert!(!prefers_dynamic(&invocations(musl, "plain.rs")[0]));
+    assert!(prefers_dynamic(&invocations(musl, "forced.rs")[0]));
+
+    let with_aux = invocations(gnu, "with-aux.rs");
+    assert!(with_aux[0].contains("--crate-type dylib"));
+    assert!(with_aux.iter().all(prefers_dynamic));
+    let with_aux = invocations(musl, "with-aux.rs");
+    assert!(with_aux[0].contains("--crate-type lib"));
+    assert!(!with_aux.iter().any(prefers_dynamic));
+}
+
+#[test]
+fn run_and_try_run_agree() {
+    use compiletest::runtest::{self, TestFailure};
+    use std::panic;
+
+    for &(mode, src_base, file) in &[("ui", "tests/ui-fail", "run-pass-exit-code.rs"),
+                                     ("ui", "tests/ui-fail", "result-main-err.rs"),
+                                     ("ui", "tests/ui-fail", "check-run-results.rs"),
+                                     ("compile-fail", "tests/compile-fail-fail", "columns.rs")] {
+        let mut config = compiletest::Config::default().tempdir();
+        config.mode = mode.parse().unwrap();
+        config.src_base = PathBuf::from(src_base);
+        config.link_deps();
+        let testpaths = compiletest::common::TestPaths {
+            file: config.src_base.join(file),
+            base: config.src_base.clone(),
+            relative_dir: PathBuf::new(),
+        };
+
+        let returned = runtest::try_run((*config).clone(), &testpaths).unwrap_err();
+        let run_config = (*config).clone();
+        let payload = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            runtest::run(run_config, &testpaths)
+        })).unwrap_err();
+        let panicked = payload.downcast_ref::<TestFailure>()
+            .expect("`run` panics with the failure");
+        assert_eq!(panicked.message, returned.message, "{}", file);
+        assert_eq!(panicked.kind, returned.kind, "{}", file);
+        assert_eq!(panicked.to_string(), returned.to_string(), "{}", file);
+        assert_eq!(panicked.proc_res.is_some(), returned.proc_res.is_some(), "{}", file);
+    }
+}
+
+#[test]
+fn whitespace_of_colored_output() {
+    use std::fs;
+
+    let src = std::env::temp_dir().join("compiletest-whitespace");
+    let build = src.join("build");
+    let _ = fs::remove_dir_all(&src);
+    fs::create_dir_all(&build).unwrap();
+    let src_base = src.to_str().unwrap();
+    for &(file, expected) in &[("plain.rs", "plain.stderr"),
+                               ("colored.rs", "colored.colored.stderr")] {
+        fs::copy(PathBuf::from("tests/whitespace").join(file), src.join(file)).unwrap();
+        // Without an expected output the actual one is saved in the build directory.
+        let failure = run_file("ui", src_base, file, |config| config.build_base = build.clone())
+            .expect_err("no expected output yet");
+        assert_eq!(failure.message, "1 errors occurred comparing output.");
+        let actual = fs::read_to_string(build.join(expected)).unwrap();
+        // Trailing whitespace and an extra final newline.
+        fs::write(src.join(expected), actual.replace("\n", " \n") + "\n").unwrap();
+    }
+
+    run_file("ui", src_base, "plain.rs", |_| {}).expect("whitespace doesn't matter");
+    let failure = run_file("ui", src_base, "colored.rs", |_| {}).unwrap_err();
+    assert_eq!(failure.message, "1 errors occurred comparing output.");
+    let failure = run_file("ui", src_base, "plain.rs", |config| {
+        config.strict_whitespace = true;
+    }).unwrap_err();
+    assert_eq!(failure.message, "1 errors occurred comparing output.");
+    fs::remove_dir_all(&src).unwrap();
+}
+
+#[test]
+fn aux_paths() {
+    run_file("run-pass", "tests/aux-paths/sub", "uses-shared.rs", |_| {})
+        .expect("`..` leaves the auxiliary directory");
+
+    let failure = run_file("run-pass", "tests/aux-paths/sub", "uses-aux-base.rs", |_| {})
+        .unwrap_err();
+    assert!(failure.message.contains("aux-build"), "{}", failure.message);
+    run_file("run-pass", "tests/aux-paths/sub", "uses-aux-base.rs", |config| {
+        config.aux_base = Some(PathBuf::from("tests/aux-paths/aux-base"));
+    }).expect("aux-base is searched as well");
+
+    let config = compiletest::Config::default();
+    let file = PathBuf::from("tests/aux-paths/sub/uses-shared.rs");
+    assert_eq!(config.aux_file(&file, "../../shared/helper.rs"),
+               Some(PathBuf::from("tests/aux-paths/shared/helper.rs")));
+    assert_eq!(config.aux_file(&file, "base_helper.rs"), None);
+}
+
+#[test]
+fn header_after_attributes() {
+    use compiletest::header::TestProps;
+
+    let file = PathBuf::from("tests/run-pass/header-after-attributes.rs");
+    let props = TestProps::from_file(&file, None, &compiletest::Config::default());
+    // The scan goes past comments and declarations, and stops at `macro_rules!`.
+    assert_eq!(props.run_flags, ["--after-attributes"]);
+    assert_eq!(props.exec_env, [("AFTER_ATTRIBUTES".to_owned(), "1".to_owned()),
+                                ("AFTER_USE".to_owned(), "1".to_owned())]);
+    run_file("run-pass", "tests/run-pass", "header-after-attributes.rs", |_| {})
+        .expect("the directives should apply");
+}
+
+#[test]
+fn json_banner_before_diagnostics() {
+    let errors = compiletest::parse_output("banner.rs", include_str!("json/banner.stderr"))
+        .expect("banner lines should be skipped");
+    assert!(errors.iter().any(|e| e.line_num == 1 && e.msg.contains("mismatched types")));
+}
+
+#[test]
+fn json_truncated_diagnostic() {
+    let error = compiletest::parse_output("banner.rs", include_str!("json/truncated.stderr"))
+        .unwrap_err();
+    assert!(error.line.contains("internal compiler error"));
+}
+
+#[test]
+fn annotation_span_count() {
+    use compiletest::errors::{self, ErrorKind};
+
+    let mut expected = errors::load_errors(&PathBuf::from("tests/annotations/spans.rs"), None);
+    assert_eq!(expected.len(), 1);
+    assert_eq!(expected[0].kind, Some(ErrorKind::Error));
+    assert_eq!(expected[0].span_count, Some(3));
+    assert_eq!(expected[0].msg, "cannot borrow `x` as mutable more than once");
+
+    let actual = compiletest::parse_output("spans.rs", include_str!("annotations/spans.stderr"))
+        .unwrap();
+    assert!(errors::match_errors(&expected, &actual).wrong_span_count.is_empty());
+
+    expected[0].span_count = Some(2);
+    let result = errors::match_errors(&expected, &actual);
+    assert_eq!(result.wrong_span_count.len(), 1);
+    assert_eq!(result.wrong_span_count[0].1.span_lines, vec![3, 4, 5]);
+}
+
+#[test]
+fn local_rustc_build() {
+    use std::fs;
+
+    let build = std::env::temp_dir().join("compiletest-local-rustc-build");
+    let _ = fs::remove_dir_all(&build);
+    let host = compiletest::Config::default().host;
+    let target = "x86_64-unknown-linux-gnu";
+    let stage = build.join(&host).join("stage1");
+    let exe = |name: &str| format!("{}{}", name, std::env::consts::EXE_SUFFIX);
+
+    let error = compiletest::Config::for_local_rustc_build(&build, 1, target).err().unwrap();
+    assert!(error.to_string().starts_with("the compiler wasn't built for stage 1"));
+
+    fs::create_dir_all(stage.join("bin")).unwrap();
+    fs::write(stage.join("bin").join(exe("rustc")), "").unwrap();
+    let error = compiletest::Config::for_local_rustc_build(&build, 1, target).err().unwrap();
+    assert!(error.to_string().contains(&format!("--target {}", target)));
+
+    let target_lib = stage.join("lib/rustlib").join(target).join("lib");
+    fs::create_dir_all(&target_lib).unwrap();
+    let config = compiletest::Config::for_local_rustc_build(&build, 1, target).unwrap();
+    assert_eq!(config.rustc_path, stage.join("bin").join(exe("rustc")));
+    assert_eq!(config.rustdoc_path, None);
+    assert_eq!(config.compile_lib_path, stage.join("lib"));
+    assert_eq!(config.run_lib_path, target_lib);
+    assert_eq!(config.stage_id, format!("stage1-{}", target));
+    assert_eq!(config.llvm_version, None);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let llvm_config = build.join(&host).join("llvm/bin/llvm-config");
+        fs::create_dir_all(llvm_config.parent().unwrap()).unwrap();
+        fs::write(&llvm_config, "#!/bin/sh\n\
+                                 case $1 in\n\
+                                 --version) echo 8.0.0 ;;\n\
+                                 --components) echo x86 arm ;;\n\
+                                 --cxxflags) echo -O2 ;;\n\
+                                 esac\n").unwrap();
+        fs::set_permissions(&llvm_config, fs::Permissions::from_mode(0o755)).unwrap();
+        let config = compiletest::Config::for_local_rustc_build(&build, 1, target).unwrap();
+        assert_eq!(config.llvm_version, Some("8.0.0".to_owned()));
+        assert_eq!(config.llvm_components, "x86 arm");
+        assert_eq!(config.llvm_cxxflags, "-O2");
+    }
+    fs::remove_dir_all(&build).unwrap();
+}
+
+#[test]
+fn run_flags_accumulate() {
+    use compiletest::header::TestProps;
+
+    let file = PathBuf::from("tests/run-pass/run-flags.rs");
+    let config = compiletest::Config::default();
+    assert_eq!(TestProps::from_file(&file, None, &config).run_flags,
+               ["--first", "--second", "third"]);
+    assert_eq!(TestProps::from_file(&file, Some("a"), &config).run_flags,
+               ["--first", "--second", "third", "--only-a"]);
+    assert_eq!(TestProps::from_file(&file, Some("b"), &config).run_flags,
+               ["--first", "--second", "third"]);
+
+    run_file("run-pass", "tests/run-pass", "run-flags.rs", |_| {})
+        .expect("each revision gets its flags");
+}
+
+#[test]
+fn injected_flags() {
+    use compiletest::header::TestProps;
+
+    let props = |flags: &str| {
+        let mut props = TestProps::new();
+        props.compile_flags = flags.split_whitespace().map(str::to_owned).collect();
+        props
+    };
+    for &flags in &["-D unused", "-Dunused", "-W unused-variables", "--deny unused_imports",
+                    "--forbid=unused", "-O -A unused-mut"] {
+        assert!(props(flags).sets_unused_lint_level(), "{}", flags);
+    }
+    for &flags in &["", "-D warnings", "-W dead-code", "--deny=unsafe-code", "--cfg unused",
+                    "-Zunused"] {
+        assert!(!props(flags).sets_unused_lint_level(), "{}", flags);
+    }
+    assert_eq!(props("").error_format(), None);
+    assert_eq!(props("-O --error-format json").error_format(), Some("json"));
+    assert_eq!(props("--error-format=short").error_format(), Some("short"));
+    assert_eq!(props("--error-format human --error-format short").error_format(), Some("short"));
+
+    run_file("compile-fail", "tests/annotations", "deny-unused.rs", |_| {})
+        .expect("`-D unused-variables` isn't overridden and JSON isn't passed twice");
+}
+
+#[test]
+fn split_args() {
+    use compiletest::util::{quote_arg, split_args};
+
+    assert_eq!(split_args(""), Vec::<String>::new());
+    assert_eq!(split_args("  -O\t-g  "), ["-O", "-g"]);
+    assert_eq!(split_args("a \"\" '' b"), ["a", "", "", "b"]);
+    assert_eq!(split_args("--cfg 'feature=\"some value\"'"), ["--cfg", "feature=\"some value\""]);
+    assert_eq!(split_args("\"say \\\"hi\\\"\" it\\'s"), ["say \"hi\"", "it's"]);
+    assert_eq!(split_args("a\\ b"), ["a b"]);
+    assert_eq!(split_args("\"C:\\Program Files\\runner.exe\" -L C:\\deps"),
+               ["C:\\Program Files\\runner.exe", "-L", "C:\\deps"]);
+    assert_eq!(split_args("--x=\"unterminated  "), ["--x=unterminated  "]);
+
+    for &arg in &["", "plain", "two words", "it's", "say \"hi\"", "C:\\dir with space\\"] {
+        assert_eq!(split_args(&quote_arg(arg)), [arg]);
+    }
+
+    run_file("run-pass", "tests/run-pass", "quoted-flags.rs", |_| {})
+        .expect("quoted flags reach rustc and the program unchanged");
+}
+
+#[test]
+fn long_type_file() {
+    let build_base = PathBuf::from("target/long-type-file");
+    let _ = std::fs::remove_dir_all(&build_base);
+    std::fs::create_dir_all(&build_base).unwrap();
+    run_file("ui", "tests/ui", "long-type.rs", |config| config.build_base = build_base.clone())
+        .expect("the full type name matches");
+    assert_no_long_type_files(&build_base);
+    std::fs::remove_dir_all(&build_base).unwrap();
+}
+
+fn assert_no_long_type_files(dir: &std::path::Path) {
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        assert!(!path.to_string_lossy().contains(".long-type-"),
+                "`{}` wasn't removed", path.display());
+        if path.is_dir() {
+            assert_no_long_type_files(&path);
+        }
+    }
+}
+
+#[test]
+fn expand_target_variables() {
+    use compiletest::header::TestProps;
+
+    let file = PathBuf::from("tests/run-pass/expand-target-variables.rs");
+    let mut config = compiletest::Config::default();
+    config.target = "x86_64-unknown-linux-gnu".to_owned();
+    let sysroot = config.sysroot().expect("rustc prints its sysroot");
+    let lib = format!("{}/lib/rustlib/x86_64-unknown-linux-gnu/lib", sysroot.display());
+    assert_eq!(TestProps::from_file(&file, None, &config).compile_flags, ["-L", &*lib]);
+
+    // A driver which can't print its sysroot leaves the variable alone.
+    config.rustc_path = PathBuf::from("tests/no-such-driver");
+    assert_eq!(config.sysroot(), None);
+    assert_eq!(TestProps::from_file(&file, None, &config).compile_flags,
+               ["-L", "{{sysroot}}/lib/rustlib/x86_64-unknown-linux-gnu/lib"]);
+
+    run_file("run-pass", "tests/run-pass", "expand-target-variables.rs", |_| {})
+        .expect("the variables are expanded");
+}
+
+#[test]
+fn strict_headers() {
+    use compiletest::header::TestProps;
+    use std::panic;
+
+    let mut config = compiletest::Config::default();
+    config.strict_headers = true;
+    // All directives of the other tests are known.
+    for dir in &["compile-fail", "run-fail", "run-pass", "ui", "ui-fail", "run-fail-fail",
+                 "compile-fail-fail", "annotations", "pretty", "dry-run", "endian"] {
+        for entry in std::fs::read_dir(PathBuf::from("tests").join(dir)).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().map_or(false, |e| e == "rs") {
+                TestProps::from_file(&path, None, &config);
+            }
+        }
+    }
+
+    let file = PathBuf::from("tests/strict-headers/misspelled.rs");
+    let load = || TestProps::from_file(&file, None, &config);
+    let message = panic::catch_unwind(panic::AssertUnwindSafe(load))
+        .err().unwrap().downcast::<String>().unwrap();
+    assert_eq!(*message, "tests/strict-headers/misspelled.rs:1: unknown directive \
+                          `compile-flag`, did you mean `compile-flags`?");
+    config.strict_headers = false;
+    assert!(TestProps::from_file(&file, None, &config).compile_flags.is_empty());
+}
+
+#[test]
+fn at_directives() {
+    use compiletest::header::TestProps;
+
+    let file = PathBuf::from("tests/run-pass/at-directives.rs");
+    let mut config = compiletest::Config::default();
+    let props = TestProps::from_file(&file, Some("a"), &config);
+    assert_eq!(props.revisions, ["a", "b"]);
+    assert_eq!(props.run_flags, ["--only-a"]);
+    assert!(props.exec_env.contains(&("LEGACY".to_owned(), "1".to_owned())));
+
+    config.legacy_directives = false;
+    let props = TestProps::from_file(&file, Some("a"), &config);
+    assert_eq!(props.run_flags, ["--only-a"]);
+    assert!(!props.exec_env.iter().any(|&(ref key, _)| key == "LEGACY"));
+
+    run_file("run-pass", "tests/run-pass", "at-directives.rs", |_| {})
+        .expect("both kinds of directives apply");
+}
+
+#[test]
+fn debug_assertions() {
+    use compiletest::header::EarlyProps;
+
+    let file = PathBuf::from("tests/run-pass/debug-assertions.rs");
+    let mut config = compiletest::Config::default();
+    config.debug_assertions = Some(false);
+    assert!(EarlyProps::from_file(&config, &file).ignore);
+    config.debug_assertions = Some(true);
+    assert!(!EarlyProps::from_file(&config, &file).ignore);
+
+    // Probed from the cfgs of the compiler otherwise.
+    config.debug_assertions = None;
+    assert!(config.has_debug_assertions());
+    assert!(!EarlyProps::from_file(&config, &file).ignore);
+    config.target_rustcflags = Some("-O".to_owned());
+    assert!(!config.has_debug_assertions());
+    assert!(EarlyProps::from_file(&config, &file).ignore);
+    config.target_rustcflags = Some("-O -C debug-assertions".to_owned());
+    assert!(config.has_debug_assertions());
+}
+
+#[test]
+fn ignore_compare_mode() {
+    use compiletest::header::EarlyProps;
+
+    let file = PathBuf::from("tests/run-pass/ignore-compare-mode.rs");
+    let mut config = compiletest::Config::default();
+    assert!(!EarlyProps::from_file(&config, &file).ignore);
+    config.compare_mode = Some("polonius".to_owned());
+    assert!(!EarlyProps::from_file(&config, &file).ignore);
+    config.compare_mode = Some("nll".to_owned());
+    let props = EarlyProps::from_file(&config, &file);
+    assert!(props.ignore);
+    assert_eq!(props.ignored_by,
+               ["ignore-compare-mode-nll (running in compare mode `nll`)"]);
+
+    // The mode may be followed by a reason.
+    let file = PathBuf::from("tests/run-pass/ignore-compare-mode-reason.rs");
+    assert!(EarlyProps::from_file(&config, &file).ignore);
+    config.compare_mode = Some("polonius".to_owned());
+    assert!(!EarlyProps::from_file(&config, &file).ignore);
+}
+
+#[test]
+fn dedup_diagnostics() {
+    use compiletest::errors;
+
+    let file = PathBuf::from("tests/annotations/dedup.rs");
+    let config = compiletest::Config::default();
+    assert!(compiletest::header::TestProps::from_file(&file, None, &config).dedup_diagnostics);
+
+    let expected = errors::load_errors(&file, None);
+    let actual = compiletest::parse_output("dedup.rs", include_str!("annotations/dedup.stderr"))
+        .unwrap();
+    assert_eq!(errors::match_errors(&expected, &actual).unexpected.len(), 1);
+
+    let (actual, removed) = errors::dedup_errors(actual);
+    assert!(removed > 0);
+    let result = errors::match_errors(&expected, &actual);
+    assert!(result.unexpected.is_empty() && result.not_found.is_empty());
+}
+
+#[test]
+fn bless_env_var() {
+    use std::{env, fs};
+
+    let src = std::env::temp_dir().join("compiletest-bless-env-var");
+    let _ = fs::remove_dir_all(&src);
+    fs::create_dir_all(&src).unwrap();
+    fs::copy("tests/bless/revisions.rs", src.join("revisions.rs")).unwrap();
+    let mut config = compiletest::Config::default().tempdir();
+    config.mode = "ui".parse().unwrap();
+    config.src_base = src.clone();
+    config.quiet = true;
+
+    // Only the variable no other test looks at is set, and never so that
+    // it blesses.
+    for value in &["", "0"] {
+        env::set_var("COMPILETEST_BLESS", value);
+        let summary = compiletest::run_tests_result(&config);
+        env::remove_var("COMPILETEST_BLESS");
+        assert_eq!(summary.unwrap().failed, 1, "COMPILETEST_BLESS={:?}", value);
+        assert!(!src.join("revisions.a.stderr").exists());
+    }
+    fs::remove_dir_all(&src).unwrap();
+}
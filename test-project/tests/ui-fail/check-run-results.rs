@@ -0,0 +1,9 @@
+// run-pass
+// check-run-results
+
+// Nothing is expected on stderr.
+
+fn main() {
+    println!("expected");
+    eprintln!("unexpected");
+}
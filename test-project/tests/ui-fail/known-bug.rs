@@ -0,0 +1,4 @@
+// known-bug: #1234
+// compile-flags: --print crate-name
+
+// The recorded output is wrong, which stands in for a bug that was fixed.
@@ -0,0 +1,7 @@
+// run-pass
+
+// `main` returns an error, which is named in the failure.
+
+fn main() -> Result<(), String> {
+    Err("the answer wasn't 42".to_owned())
+}
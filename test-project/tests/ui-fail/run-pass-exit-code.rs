@@ -0,0 +1,7 @@
+// run-pass
+
+// The program exits unsuccessfully, so the test has to fail.
+
+fn main() {
+    std::process::exit(1);
+}
@@ -0,0 +1,5 @@
+// should-ice
+
+// The compiler doesn't crash on this (any more).
+
+fn main() {}
@@ -0,0 +1,7 @@
+// run-pass
+// check-run-results
+
+fn main() {
+    println!("hello from {}", file!());
+    eprintln!("warning: not much to do");
+}
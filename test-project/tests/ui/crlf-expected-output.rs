@@ -0,0 +1,6 @@
+// compile-flags: --print crate-name
+
+// The expected stdout has CRLF line endings, trailing whitespace and an extra
+// final newline, none of which make the comparison fail.
+
+fn main() {}
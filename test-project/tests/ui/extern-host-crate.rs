@@ -0,0 +1,5 @@
+// check-pass
+// Only found with the flags `Config::link_deps` adds.
+extern crate testp;
+
+fn main() {}
@@ -0,0 +1,14 @@
+// check-long-type-file
+
+// The name of the type is too long for the diagnostic, so the compiler
+// writes it to a file.
+
+type A = Option<Option<Option<Option<Option<Option<Option<Option<u8>>>>>>>>;
+type B = (A, A, A, A);
+type C = (B, B, B, B);
+type D = (C, C, C, C);
+
+fn main() {
+    let x: D = Default::default();
+    let _: () = x; //~ ERROR mismatched types
+}
@@ -0,0 +1,4 @@
+// compile-flags: --print crate-name
+// normalize-stdout-regex-test: "^([a-z]+)_([a-z]+)" -> "$2 $1"
+// Literal rules don't treat `.` specially.
+// normalize-stdout-test: "." -> "!"
@@ -0,0 +1,5 @@
+// check-color-output
+
+fn main() {
+    let _x: u32 = "";
+}
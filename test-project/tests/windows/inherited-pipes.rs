@@ -0,0 +1,16 @@
+// The program exits while a process it spawned still holds its stdout and
+// stderr, which mustn't make the harness wait for that process.
+
+use std::env;
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+
+fn main() {
+    if env::args().nth(1).as_ref().map(String::as_str) == Some("sleep") {
+        thread::sleep(Duration::from_secs(600));
+        return;
+    }
+    println!("spawning");
+    Command::new(env::current_exe().unwrap()).arg("sleep").spawn().unwrap();
+}